@@ -1,8 +1,10 @@
+#[cfg(feature = "esp")]
 use embuild::{
     self,
     build::{CfgArgs, LinkArgs},
 };
 
+#[cfg(feature = "esp")]
 fn main() -> anyhow::Result<()> {
     // Necessary because of this issue: https://github.com/rust-lang/cargo/issues/9641
     LinkArgs::output_propagated("ESP_IDF")?;
@@ -28,3 +30,7 @@ fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// host builds, see the `esp` feature, need none of the esp-idf setup
+#[cfg(not(feature = "esp"))]
+fn main() {}
@@ -0,0 +1,60 @@
+//! Plays effects on a `SimStrip` in the terminal, no board needed:
+//!
+//! `cargo run --example sim --no-default-features --target x86_64-unknown-linux-gnu -- [Effect..]`
+//!
+//! Without arguments every effect plays once, with its default settings, on top of a red strip so
+//! the effects that change colors have something to work with.
+
+use std::{thread, time::Duration};
+
+use anyhow::{bail, Result};
+use espoxi3::neopixel::{
+    effects::{solid::SolidColorConfig, EffectConfig},
+    layer::Layer,
+    segment::Segments,
+    strip::{
+        color::default::Color,
+        sim::{self, SimStrip},
+    },
+};
+
+const LED_COUNT: u16 = 60;
+const FRAMES: u32 = 50;
+const MSPF: u32 = 40;
+
+fn main() -> Result<()> {
+    let mut names: Vec<String> = std::env::args().skip(1).collect();
+    if names.is_empty() {
+        names = EffectConfig::NAMES.iter().map(|n| n.to_string()).collect();
+    }
+    for name in names {
+        let effect = match EffectConfig::default_named(&name) {
+            Some(effect) => effect,
+            None => bail!(
+                "no effect called '{}', there are {:?}",
+                name,
+                EffectConfig::NAMES
+            ),
+        };
+        println!("{}", name);
+        let base = EffectConfig::SolidColor(SolidColorConfig {
+            color: Color::red(),
+        });
+        let strip = SimStrip::new(LED_COUNT);
+        sim::play(
+            &[Layer::from(base), Layer::from(effect)],
+            &Segments::default(),
+            None,
+            None,
+            &strip,
+            (0..FRAMES).map(|frame| Duration::from_millis((frame * MSPF) as u64)),
+            None,
+        )?;
+        for frame in strip.frames() {
+            print!("\r{}", sim::to_ansi(&frame));
+            thread::sleep(Duration::from_millis(MSPF as u64));
+        }
+        println!();
+    }
+    Ok(())
+}
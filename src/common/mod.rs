@@ -1 +1,3 @@
-pub mod time;
\ No newline at end of file
+pub mod network;
+pub mod query;
+pub mod time;
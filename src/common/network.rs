@@ -0,0 +1,27 @@
+//! Where we are on the network, kept by `connection` and read by everything that announces us.
+
+use std::net::Ipv4Addr;
+
+use serde::Serialize;
+
+/// What the rest of the firmware needs to know about the network we are on.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkInfo {
+    /// the station address, or our address on the access point we are hosting
+    pub ip: Option<Ipv4Addr>,
+    pub mac: [u8; 6],
+    /// the network we are connected to or hosting
+    pub ssid: String,
+    pub hostname: String,
+    pub mode: WifiMode,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WifiMode {
+    /// not started yet, or the last connect/host attempt failed
+    #[default]
+    Offline,
+    Station,
+    Host,
+}
@@ -0,0 +1,63 @@
+//! Query strings of request uris.
+
+/// value of `key` in the query string of `uri`, e.g. `query_param("/effects/item?id=3", "id") == Some("3")`
+pub fn query_param<'a>(uri: &'a str, key: &str) -> Option<&'a str> {
+    let (_, query) = uri.split_once('?')?;
+    query.split('&').find_map(|pair| match pair.split_once('=') {
+        Some((k, v)) if k == key => Some(v),
+        None if pair == key => Some(""),
+        _ => None,
+    })
+}
+
+/// like `query_param`, but with `%XX` escapes and `+` decoded
+pub fn query_param_decoded(uri: &str, key: &str) -> Option<String> {
+    let raw = query_param(uri, key)?.as_bytes();
+    let mut decoded = Vec::with_capacity(raw.len());
+    let mut i = 0;
+    while i < raw.len() {
+        match raw[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < raw.len() => {
+                match u8::from_str_radix(std::str::from_utf8(&raw[i + 1..i + 3]).ok()?, 16) {
+                    Ok(b) => decoded.push(b),
+                    Err(_) => return None,
+                }
+                i += 2;
+            }
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_value_of_a_key() {
+        let uri = "/effects/item?id=3&enabled&to=";
+        assert_eq!(query_param(uri, "id"), Some("3"));
+        assert_eq!(query_param(uri, "enabled"), Some(""));
+        assert_eq!(query_param(uri, "to"), Some(""));
+        assert_eq!(query_param(uri, "i"), None);
+        assert_eq!(query_param("/effects/item", "id"), None);
+    }
+
+    #[test]
+    fn decodes_escapes_and_plus() {
+        let decoded = |uri| query_param_decoded(uri, "name");
+        assert_eq!(
+            decoded("/presets?name=Living%20Room").unwrap(),
+            "Living Room"
+        );
+        assert_eq!(decoded("/presets?name=Living+Room").unwrap(), "Living Room");
+        assert_eq!(decoded("/presets?name=a%2Bb%26c").unwrap(), "a+b&c");
+        assert_eq!(decoded("/presets?name=K%C3%BCche").unwrap(), "Küche");
+        // a lone % is taken as it is, a broken escape is no name at all
+        assert_eq!(decoded("/presets?name=100%").unwrap(), "100%");
+        assert_eq!(decoded("/presets?name=%zz"), None);
+    }
+}
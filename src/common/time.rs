@@ -1,3 +1,4 @@
+#[cfg(feature = "esp")]
 use esp_idf_svc::{sntp, systime};
 
 pub trait TimeProvider {
@@ -5,12 +6,14 @@ pub trait TimeProvider {
     fn clone(&self) -> Box<dyn TimeProvider + Send>;
 }
 
+#[cfg(feature = "esp")]
 pub struct EspNTPC {
     pub sntp: sntp::EspSntp,
     // conf : sntp::SntpConf,
     timer: systime::EspSystemTime,
 }
 
+#[cfg(feature = "esp")]
 impl EspNTPC {
     pub fn new() -> Self {
         let conf = sntp::SntpConf {
@@ -23,6 +26,7 @@ impl EspNTPC {
     }
 }
 
+#[cfg(feature = "esp")]
 impl TimeProvider for EspNTPC {
     fn now(&self) -> Option<std::time::Duration> {
         // let status = self.sntp.get_sync_status();
@@ -50,8 +54,10 @@ impl TimeProvider for EspNTPC {
     } //FIXME: wrong provider
 }
 
+#[cfg(feature = "esp")]
 pub struct EspSystemTime {}
 
+#[cfg(feature = "esp")]
 impl TimeProvider for EspSystemTime {
     fn now(&self) -> Option<std::time::Duration> {
         Some(systime::EspSystemTime.now())
@@ -61,3 +67,13 @@ impl TimeProvider for EspSystemTime {
         Box::new(EspSystemTime {})
     }
 }
+
+/// 2023-01-01, the clock starts at 1970 after boot so anything past this came from sntp
+#[cfg(feature = "esp")]
+const SYNCED_AFTER: std::time::Duration = std::time::Duration::from_secs(1_672_531_200);
+
+/// Whether the system clock has been set by sntp yet.
+#[cfg(feature = "esp")]
+pub fn synced() -> bool {
+    systime::EspSystemTime.now() > SYNCED_AFTER
+}
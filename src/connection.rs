@@ -8,7 +8,7 @@ use anyhow::{bail, Result};
 use embedded_svc::ipv4;
 use esp_idf_hal::peripheral;
 use esp_idf_svc::{eventloop::EspSystemEventLoop, ping};
-use log::{info, warn};
+use log::{error, info, warn};
 
 pub mod client;
 pub mod server;
@@ -16,9 +16,10 @@ pub mod wifi;
 
 use wifi::{Creds, Wlan};
 
+pub use crate::common::network::{NetworkInfo, WifiMode};
 use crate::{connection::server as s, store::DStore};
 
-use server::RouteData;
+use server::{RouteData, WsRouteData};
 
 #[toml_cfg::toml_config]
 pub struct Config {
@@ -43,15 +44,33 @@ macro_rules! add_new_route {
     };
 }
 
+#[macro_export]
+macro_rules! add_new_ws_route {
+    ($sender:expr; $uri:expr, $handler:expr) => {
+        $sender
+            .send(crate::connection::ConnectionRelevantEvent::WsRoute(
+                crate::connection::server::WsRouteData::new($uri, $handler),
+            ))
+            .unwrap();
+    };
+}
+
+/// Called with the new `NetworkInfo` whenever the wifi connection changes,
+/// and once right away when it is registered.
+pub type NetworkListener = Box<dyn Fn(&NetworkInfo) + Send>;
+
 pub enum ConnectionRelevantEvent {
     Wifi(ConnectionEvent),
     Route(RouteData),
+    WsRoute(WsRouteData),
+    NetworkListener(NetworkListener),
 }
 
 pub fn init(
     modem: impl peripheral::Peripheral<P = esp_idf_hal::modem::Modem> + 'static + std::marker::Send,
     sysloop: EspSystemEventLoop,
     sstore: Arc<Mutex<DStore>>,
+    network: Arc<Mutex<NetworkInfo>>,
 ) -> Result<Sender<ConnectionRelevantEvent>> {
     let (successful_wifi_connection_tx, succesful_wifi_connection_rx) = std::sync::mpsc::channel();
     let (ttx, rx) = std::sync::mpsc::channel::<ConnectionRelevantEvent>();
@@ -67,17 +86,25 @@ pub fn init(
             }
         };
 
+        {
+            let mut nnetwork = network.lock().unwrap();
+            nnetwork.hostname = wifi::NAME.into();
+            nnetwork.mac = wifi.mac().unwrap_or_default();
+        }
+
         let ssstore = sstore.lock().unwrap();
 
         info!("Connecting to stored wifi...");
-        let should_start_host = if let Ok(Some(creds)) = ssstore.get("client_creds") {
+        let should_start_host = if let Ok(Some(creds)) = ssstore.get::<Creds>("client_creds") {
+            let ssid = creds.ssid.clone();
             match wifi.connect_to(creds) {
                 Err(e) => {
                     warn!("Failed to connect to stored wifi: {}", e);
                     true
                 }
-                Ok(_) => {
+                Ok(address) => {
                     info!("Connected to stored wifi");
+                    network.lock().unwrap().connected(address, ssid);
                     false
                 }
             }
@@ -86,14 +113,19 @@ pub fn init(
         };
         if should_start_host {
             info!("No stored wifi credentials, we will start our own access point");
-            match wifi.host_as(match ssstore.get("ap_creds") {
+            let creds = match ssstore.get("ap_creds") {
                 Ok(Some(creds)) => creds,
                 _ => Creds {
                     ssid: CONFIG.wifi_ssid.into(),
                     psk: CONFIG.wifi_psk.into(),
                 },
-            }) {
-                Ok(_) => info!("Wifi started as host"),
+            };
+            let ssid = creds.ssid.clone();
+            match wifi.host_as(creds) {
+                Ok(_) => {
+                    info!("Wifi started as host");
+                    network.lock().unwrap().hosting(wifi.ap_ip().ok(), ssid);
+                }
                 Err(e) => warn!("Wifi hosting failed: {}", e),
             };
         }
@@ -114,6 +146,7 @@ pub fn init(
         s::add_connect_route(&mut server, tx.clone(), sta_ip.clone()).unwrap();
         s::add_rename_route(&mut server, tx.clone()).unwrap();
         let _ = successful_wifi_connection_tx.send(Ok(()));
+        let mut listeners: Vec<NetworkListener> = Vec::new();
         loop {
             // FreeRtos::delay_ms(100); //not needed since we are blocking on the rx (no busy waiting, and therefore no polling delay needed)
             match rx.recv() {
@@ -122,33 +155,57 @@ pub fn init(
                         info!("Connecting to wifi...");
                         let mut ssstore = sstore.lock().unwrap();
                         ssstore.set("client_creds", &creds).unwrap();
+                        let ssid = creds.ssid.clone();
                         match wifi.connect_to(creds) {
                             Ok(address) => {
                                 info!("Connected to wifi as {}", address);
                                 *sta_ip.lock().unwrap() = Some(address);
+                                network.lock().unwrap().connected(address, ssid);
                             }
                             Err(e) => {
                                 warn!("Failed to connect to wifi: {}", e);
                                 *sta_ip.lock().unwrap() = None;
+                                network.lock().unwrap().offline();
                             }
                         };
+                        drop(ssstore);
+                        notify(&listeners, &network);
                     }
                     ConnectionEvent::HostAs(creds) => {
                         info!("Starting wifi as host...");
                         let mut ssstore = sstore.lock().unwrap();
                         ssstore.set("ap_creds", &creds).unwrap();
+                        let ssid = creds.ssid.clone();
                         match wifi.host_as(creds) {
-                            Ok(_) => info!("Wifi started as host"),
-                            Err(e) => warn!("Wifi hosting failed: {}", e),
+                            Ok(_) => {
+                                info!("Wifi started as host");
+                                network.lock().unwrap().hosting(wifi.ap_ip().ok(), ssid);
+                            }
+                            Err(e) => {
+                                warn!("Wifi hosting failed: {}", e);
+                                network.lock().unwrap().offline();
+                            }
                         };
+                        drop(ssstore);
+                        notify(&listeners, &network);
                     }
                 },
                 Ok(ConnectionRelevantEvent::Route(route_data)) => {
                     match s::add_new_route(&mut server, route_data) {
                         Ok(_) => info!("Added new route"),
-                        Err(e) => warn!("Failed to add new route: {}", e),
+                        Err(e) => error!("{}", e),
+                    };
+                }
+                Ok(ConnectionRelevantEvent::WsRoute(route_data)) => {
+                    match s::add_new_ws_route(&mut server, route_data) {
+                        Ok(_) => info!("Added new websocket route"),
+                        Err(e) => error!("{}", e),
                     };
                 }
+                Ok(ConnectionRelevantEvent::NetworkListener(listener)) => {
+                    listener(&network.lock().unwrap().clone());
+                    listeners.push(listener);
+                }
                 Err(_) => {
                     warn!("Route data channel closed");
                     break;
@@ -160,6 +217,32 @@ pub fn init(
     Ok(ttx)
 }
 
+fn notify(listeners: &[NetworkListener], network: &Mutex<NetworkInfo>) {
+    let network = network.lock().unwrap().clone();
+    for listener in listeners {
+        listener(&network);
+    }
+}
+
+impl NetworkInfo {
+    fn connected(&mut self, ip: Ipv4Addr, ssid: String) {
+        self.ip = Some(ip);
+        self.ssid = ssid;
+        self.mode = WifiMode::Station;
+    }
+
+    fn hosting(&mut self, ip: Option<Ipv4Addr>, ssid: String) {
+        self.ip = ip;
+        self.ssid = ssid;
+        self.mode = WifiMode::Host;
+    }
+
+    fn offline(&mut self) {
+        self.ip = None;
+        self.mode = WifiMode::Offline;
+    }
+}
+
 pub enum ConnectionEvent {
     ConnectToWifi(Creds),
     HostAs(Creds),
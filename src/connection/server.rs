@@ -1,3 +1,4 @@
+use std::io::BufReader;
 use std::net::Ipv4Addr;
 use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
@@ -5,11 +6,15 @@ use std::sync::{Arc, Mutex};
 use anyhow::{bail, Result};
 
 use embedded_svc::http::server::{HandlerError, Method, Request};
+use embedded_svc::http::Headers;
 use embedded_svc::io::{Read, Write};
-use esp_idf_svc::http::server::EspHttpConnection;
+use esp_idf_svc::http::server::{ws::EspHttpWsConnection, Configuration, EspHttpConnection};
+use esp_idf_sys::EspError;
 use log::info;
 use serde::de;
 
+pub use crate::common::query::{query_param, query_param_decoded};
+
 #[allow(unused_imports)]
 use super::wifi::Creds;
 
@@ -49,22 +54,29 @@ macro_rules! send_as_json {
 
 #[macro_export]
 macro_rules! match_parsed_json {
-    ($req:expr, $($t:tt)*) => {
-        {
-            let buf = &mut [0u8; crate::connection::server::BODY_BUFFER_SIZE as usize];
-            match crate::connection::server::parse_req_json_to(&mut $req, buf)$($t)*
-        }
+    ($req:expr, $limit:expr, $($t:tt)*) => {
+        match crate::connection::server::parse_req_json_to(&mut $req, $limit)$($t)*
     };
 }
 
+/// `parse_req_or_fail_with_message!(req; "msg {}")` accepts bodies up to `MAX_BODY_SIZE`,
+/// `parse_req_or_fail_with_message!(req, limit; "msg {}")` up to `limit` bytes.
 #[macro_export]
 macro_rules! parse_req_or_fail_with_message {
     ($req:ident; $($t:tt)*) => {
-        match_parsed_json!($req,{
+        parse_req_or_fail_with_message!($req, crate::connection::server::MAX_BODY_SIZE; $($t)*)
+    };
+    ($req:ident, $limit:expr; $($t:tt)*) => {
+        match_parsed_json!($req, $limit, {
             Ok(parsed) => {
                 info!("parsed body: {:?}", parsed);
                 parsed
             }
+            Err(crate::connection::server::BodyError::TooLarge(limit)) => {
+                $req.into_status_response(413)?
+                    .write_all(format!("body is larger than {} bytes", limit).as_bytes())?;
+                return Ok(());
+            }
             Err(err) => {
                 // let info = format!($($t)*, err);
                 // $req.into_status_response(400)?.write_all(info.as_bytes())?;
@@ -76,33 +88,124 @@ macro_rules! parse_req_or_fail_with_message {
     };
 }
 
-pub const BODY_BUFFER_SIZE: u16 = 1024;
-// const BODY_BUFFER_SIZE: u16 = 1024;
-pub fn parse_req_json_to<'a, T>(
+/// default upper limit for request bodies, big enough for a large effect stack or preset
+pub const MAX_BODY_SIZE: usize = 16 * 1024;
+/// every `add_new_route!` and `add_new_ws_route!` takes one, the default of 32 is not enough
+pub const MAX_URI_HANDLERS: usize = 64;
+/// serde_json reads byte by byte, this is how much of the body is fetched from the connection at once
+const BODY_BUFFER_SIZE: usize = 512;
+
+#[derive(Debug)]
+pub enum BodyError {
+    /// the body is (or claims to be, by `Content-Length`) bigger than the limit
+    TooLarge(usize),
+    Read(String),
+    Json(serde_json::Error),
+}
+
+impl std::fmt::Display for BodyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BodyError::TooLarge(limit) => write!(f, "body is larger than {} bytes", limit),
+            BodyError::Read(e) => write!(f, "failed reading body: {}", e),
+            BodyError::Json(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Feeds the request body to serde as it arrives, until `Content-Length` bytes (or the end of the
+/// body) have been read, failing once more than `limit` bytes came in.
+struct BodyReader<'r, 'c> {
+    req: &'r mut Request<&'c mut EspHttpConnection>,
+    remaining: Option<usize>,
+    read: usize,
+    limit: usize,
+    overflowed: bool,
+}
+
+impl<'r, 'c> std::io::Read for BodyReader<'r, 'c> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let max = match self.remaining {
+            Some(0) => return Ok(0),
+            Some(remaining) => buf.len().min(remaining),
+            None => buf.len(),
+        };
+        let len = self
+            .req
+            .read(&mut buf[..max])
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("{:?}", e)))?;
+        self.read += len;
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining -= len;
+        }
+        if self.read > self.limit {
+            self.overflowed = true;
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "body too large",
+            ));
+        }
+        Ok(len)
+    }
+}
+
+impl<'r, 'c> BodyReader<'r, 'c> {
+    fn new(
+        req: &'r mut Request<&'c mut EspHttpConnection>,
+        limit: usize,
+    ) -> Result<Self, BodyError> {
+        let content_len = req
+            .header("Content-Length")
+            .and_then(|l| l.trim().parse::<usize>().ok());
+        if content_len.is_some_and(|len| len > limit) {
+            return Err(BodyError::TooLarge(limit));
+        }
+        info!("reading body of {:?} bytes...", content_len);
+        Ok(Self {
+            req,
+            remaining: content_len,
+            read: 0,
+            limit,
+            overflowed: false,
+        })
+    }
+}
+
+pub fn parse_req_json_to<T>(
     r: &mut Request<&mut EspHttpConnection>,
-    buf: &'a mut [u8],
-) -> Result<T, serde_json::Error>
+    limit: usize,
+) -> Result<T, BodyError>
 where
-    T: de::Deserialize<'a> + Clone,
+    T: de::DeserializeOwned,
 {
-    let len = r.read(buf).unwrap();
-    info!("parsing body...\n{}", show(&buf[0..len]));
-    serde_json::from_slice::<T>(&buf[0..len])
-}
-use std::ascii::escape_default;
-use std::str;
-
-fn show(bs: &[u8]) -> String {
-    let mut visible = String::new();
-    for &b in bs {
-        let part: Vec<u8> = escape_default(b).collect();
-        visible.push_str(str::from_utf8(&part).unwrap());
+    let mut reader = BufReader::with_capacity(BODY_BUFFER_SIZE, BodyReader::new(r, limit)?);
+    match serde_json::from_reader(&mut reader) {
+        Ok(parsed) => Ok(parsed),
+        Err(_) if reader.get_ref().overflowed => Err(BodyError::TooLarge(limit)),
+        Err(e) if e.is_io() => Err(BodyError::Read(e.to_string())),
+        Err(e) => Err(BodyError::Json(e)),
+    }
+}
+
+/// The whole body, for what is not JSON. Unlike `parse_req_json_to` this holds all of it in memory.
+pub fn read_req_body(
+    r: &mut Request<&mut EspHttpConnection>,
+    limit: usize,
+) -> Result<Vec<u8>, BodyError> {
+    let mut reader = BodyReader::new(r, limit)?;
+    let mut body = Vec::new();
+    match std::io::Read::read_to_end(&mut reader, &mut body) {
+        Ok(_) => Ok(body),
+        Err(_) if reader.overflowed => Err(BodyError::TooLarge(limit)),
+        Err(e) => Err(BodyError::Read(e.to_string())),
     }
-    visible
 }
 
 pub fn init_server() -> Result<esp_idf_svc::http::server::EspHttpServer> {
-    let mut server = esp_idf_svc::http::server::EspHttpServer::new(&Default::default())?;
+    let mut server = esp_idf_svc::http::server::EspHttpServer::new(&Configuration {
+        max_uri_handlers: MAX_URI_HANDLERS,
+        ..Default::default()
+    })?;
 
     server.fn_handler("/", Method::Get, |req| {
         req.into_ok_response()?.write_all(index_html().as_bytes())?;
@@ -226,6 +329,44 @@ pub(crate) fn add_new_route(
     } = route_data;
     match server.fn_handler(uri.as_str(), method, handler) {
         Ok(_) => Ok(()),
-        Err(e) => bail!("Failed to add route: {}", e),
+        Err(e) => bail!(
+            "Failed to add route {} (at most {} routes): {}",
+            uri,
+            MAX_URI_HANDLERS,
+            e
+        ),
+    }
+}
+
+/// A WebSocket endpoint, the handler is called for every new connection, every frame and every close.
+pub struct WsRouteData {
+    uri: String,
+    handler: Box<dyn Fn(&mut EspHttpWsConnection) -> Result<(), EspError> + Send + Sync>,
+}
+impl WsRouteData {
+    pub fn new(
+        uri: impl Into<String>,
+        handler: impl Fn(&mut EspHttpWsConnection) -> Result<(), EspError> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            uri: uri.into(),
+            handler: Box::new(handler),
+        }
+    }
+}
+
+pub(crate) fn add_new_ws_route(
+    server: &mut esp_idf_svc::http::server::EspHttpServer,
+    route_data: WsRouteData,
+) -> anyhow::Result<()> {
+    let WsRouteData { uri, handler } = route_data;
+    match server.ws_handler(uri.as_str(), handler) {
+        Ok(_) => Ok(()),
+        Err(e) => bail!(
+            "Failed to add websocket route {} (at most {} routes): {}",
+            uri,
+            MAX_URI_HANDLERS,
+            e
+        ),
     }
 }
@@ -43,7 +43,8 @@ struct WConfig {
     ap: Option<AccessPointConfiguration>,
 }
 
-const NAME: &str = env!("CARGO_PKG_NAME");
+/// also used as dhcp hostname
+pub const NAME: &str = env!("CARGO_PKG_NAME");
 impl Wlan {
     pub fn start(
         modem: impl peripheral::Peripheral<P = esp_idf_hal::modem::Modem> + 'static,
@@ -189,6 +190,15 @@ impl Wlan {
         Ok(())
     }
 
+    /// our own address on the access point we are hosting
+    pub fn ap_ip(&self) -> Result<Ipv4Addr> {
+        Ok(self.wifi.ap_netif().get_ip_info()?.ip)
+    }
+
+    pub fn mac(&self) -> Result<[u8; 6]> {
+        Ok(self.wifi.sta_netif().get_mac()?)
+    }
+
     #[allow(dead_code)]
     pub fn disable_ap(&mut self) -> anyhow::Result<()> {
         self.config.ap = None;
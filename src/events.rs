@@ -0,0 +1,218 @@
+//! Server-Sent Events at `http://<device>:8080/events`, so dashboards get changes pushed instead of
+//! polling `/effects` and `/ip`.
+//!
+//! esp-idf's http server handles all requests from one task, a response that never ends would block
+//! every other route, so the stream has its own small listener.
+//!
+//! Events, all with JSON data:
+//! - `effects`: `{"revision":N,"layers":[..]}`, the layers as `GET /effects` returns them
+//! - `output`: as `GET /output`
+//! - `network`: `{"mode":"station"|"host"|"offline","ip":"..","ssid":".."}` after every
+//!   connect/host attempt
+//! - `time`: `{"synced":bool}`
+//!
+//! A new client first gets the current value of every event.
+
+use std::{
+    io::{Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    sync::{
+        mpsc::{self, Sender},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use log::{info, warn};
+use serde_json::json;
+
+use crate::{
+    common::time,
+    connection::{ConnectionRelevantEvent, NetworkInfo},
+    neopixel::NeopixelManager,
+};
+
+pub const PORT: u16 = 8080;
+/// every socket is precious on the esp, see `CONFIG_LWIP_MAX_SOCKETS`
+const MAX_CLIENTS: usize = 4;
+/// how often the effect stack, output and clock are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// comment lines keep proxies from closing the stream and let us notice dead clients
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+const MAX_REQUEST_LEN: usize = 1024;
+
+const HEADERS: &[u8] = b"HTTP/1.1 200 OK\r\n\
+Content-Type: text/event-stream\r\n\
+Cache-Control: no-cache\r\n\
+Connection: keep-alive\r\n\
+Access-Control-Allow-Origin: *\r\n\
+\r\n\
+retry: 2000\n\n";
+const NOT_FOUND: &[u8] =
+    b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+const BUSY: &[u8] =
+    b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+enum Msg {
+    Client(TcpStream),
+    Network(NetworkInfo),
+}
+
+/// Starts listening and pushing, `tx` is used to follow the network state.
+pub fn start(
+    nm: Arc<NeopixelManager<'static>>,
+    tx: &Sender<ConnectionRelevantEvent>,
+) -> Result<()> {
+    let (events_tx, events_rx) = mpsc::channel();
+
+    let network_tx = events_tx.clone();
+    tx.send(ConnectionRelevantEvent::NetworkListener(Box::new(
+        move |network| {
+            let _ = network_tx.send(Msg::Network(network.clone()));
+        },
+    )))?;
+
+    let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, PORT))?;
+    thread::Builder::new()
+        .stack_size(6 * 1024)
+        .spawn(move || accept(listener, events_tx))?;
+    thread::Builder::new()
+        .stack_size(8 * 1024)
+        .spawn(move || Hub::default().run(nm, events_rx))?;
+    info!("Serving events on port {}", PORT);
+    Ok(())
+}
+
+/// Answers the request and hands event streams over to the hub.
+fn accept(listener: TcpListener, tx: Sender<Msg>) {
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Failed to accept event client: {}", e);
+                continue;
+            }
+        };
+        let _ = stream.set_read_timeout(Some(Duration::from_secs(2)));
+        let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+        match read_request_line(&mut stream) {
+            Some(line) if line.starts_with("GET /events ") || line.starts_with("GET /events?") => {
+                if stream.write_all(HEADERS).is_ok() && tx.send(Msg::Client(stream)).is_err() {
+                    break;
+                }
+            }
+            _ => {
+                let _ = stream.write_all(NOT_FOUND);
+            }
+        }
+    }
+}
+
+/// the first line of the request, after reading all of its headers
+fn read_request_line(stream: &mut TcpStream) -> Option<String> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 256];
+    while !request.ends_with(b"\r\n\r\n") {
+        let len = stream.read(&mut buf).ok()?;
+        if len == 0 || request.len() + len > MAX_REQUEST_LEN {
+            return None;
+        }
+        request.extend_from_slice(&buf[..len]);
+    }
+    let line = request.split(|b| *b == b'\r').next()?;
+    String::from_utf8(line.to_vec()).ok()
+}
+
+#[derive(Default)]
+struct Hub {
+    clients: Vec<TcpStream>,
+    effects_revision: Option<u32>,
+    /// the last sent data of every event, in the order a new client gets them
+    last: Vec<(&'static str, String)>,
+}
+
+impl Hub {
+    fn run(mut self, nm: Arc<NeopixelManager<'static>>, rx: mpsc::Receiver<Msg>) {
+        let mut last_keepalive = Instant::now();
+        loop {
+            match rx.recv_timeout(POLL_INTERVAL) {
+                Ok(Msg::Client(stream)) => self.add_client(stream),
+                Ok(Msg::Network(network)) => {
+                    let data = json!({
+                        "mode": network.mode,
+                        "ip": network.ip,
+                        "ssid": network.ssid,
+                    });
+                    self.emit("network", data.to_string());
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+            self.poll(&nm);
+            if last_keepalive.elapsed() >= KEEPALIVE_INTERVAL {
+                last_keepalive = Instant::now();
+                self.broadcast(b":\n\n");
+            }
+        }
+    }
+
+    fn add_client(&mut self, mut stream: TcpStream) {
+        if self.clients.len() >= MAX_CLIENTS {
+            let _ = stream.write_all(BUSY);
+            return;
+        }
+        let mut ok = true;
+        for (event, data) in &self.last {
+            ok = ok && stream.write_all(&message(event, data)).is_ok();
+        }
+        if ok {
+            self.clients.push(stream);
+        }
+    }
+
+    fn poll(&mut self, nm: &NeopixelManager) {
+        let stack = nm.effects.lock().unwrap();
+        if self.effects_revision != Some(stack.revision()) {
+            self.effects_revision = Some(stack.revision());
+            let data = json!({
+                "revision": stack.revision(),
+                "layers": stack.layers(),
+            });
+            drop(stack);
+            self.emit("effects", data.to_string());
+        } else {
+            drop(stack);
+        }
+
+        let output = nm.output.lock().unwrap().clone();
+        match serde_json::to_string(&output) {
+            Ok(data) => self.emit("output", data),
+            Err(e) => warn!("Failed to serialize output settings: {}", e),
+        }
+
+        self.emit("time", json!({ "synced": time::synced() }).to_string());
+    }
+
+    /// Sends `data` to everyone, unless it is what they got last time.
+    fn emit(&mut self, event: &'static str, data: String) {
+        match self.last.iter_mut().find(|(e, _)| *e == event) {
+            Some((_, last)) if *last == data => return,
+            Some((_, last)) => *last = data.clone(),
+            None => self.last.push((event, data.clone())),
+        }
+        self.broadcast(&message(event, &data));
+    }
+
+    /// Writes to every client, dropping those that are gone or too slow.
+    fn broadcast(&mut self, bytes: &[u8]) {
+        self.clients
+            .retain(|mut client| client.write_all(bytes).is_ok());
+    }
+}
+
+/// `data` has no newlines, serde_json escapes them
+fn message(event: &str, data: &str) -> Vec<u8> {
+    format!("event: {}\ndata: {}\n\n", event, data).into_bytes()
+}
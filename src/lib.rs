@@ -0,0 +1,21 @@
+//! Everything the firmware is made of, `main.rs` only wires it up on the board.
+//!
+//! Without the `esp` feature only the parts that do not need esp-idf are built: effects, layers,
+//! strips as far as `SimStrip`, the MQTT light without its client, the WLED state without its
+//! routes, the realtime protocols without their sockets, and the store on top of an in-memory
+//! nvs. Those run on any machine,
+//! e.g. `cargo test --no-default-features --target x86_64-unknown-linux-gnu`.
+
+#![allow(clippy::single_component_path_imports)]
+
+pub mod common;
+#[cfg(feature = "esp")]
+pub mod connection;
+#[cfg(feature = "esp")]
+pub mod demos;
+#[cfg(feature = "esp")]
+pub mod events;
+pub mod mqtt;
+pub mod neopixel;
+pub mod protocols;
+pub mod store;
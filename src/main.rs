@@ -2,12 +2,7 @@
 #![allow(clippy::single_component_path_imports)]
 //#![feature(backtrace)]
 
-mod common;
-mod connection;
-mod demos;
-mod neopixel;
-mod store;
-use common::time;
+use espoxi3::{common::time, connection, events, mqtt, neopixel, protocols, store};
 
 #[allow(unused_imports)]
 use std::sync::{Condvar, Mutex};
@@ -17,7 +12,6 @@ use std::{env, sync::Arc, thread, time::*};
 #[allow(unused_imports)]
 use anyhow::{bail, Result};
 
-use embedded_svc::io::Write;
 use esp_idf_hal::delay::FreeRtos;
 use esp_idf_hal::gpio::PinDriver;
 #[allow(unused_imports)]
@@ -29,11 +23,14 @@ use esp_idf_svc::eventloop::*;
 // use esp_idf_svc::timer::*;
 
 use esp_idf_hal::prelude::*;
-use neopixel::effects::EffectConfig;
+use neopixel::layer::Layer;
+use neopixel::matrix::{Matrix, MatrixConfig};
+use neopixel::pixelmap::{PixelMap, Point};
+use neopixel::segment::{Segment, Segments};
 
-use crate::common::time::TimeProvider;
-use crate::neopixel::strip::Strip;
-use crate::neopixel::NeopixelManager;
+use espoxi3::common::time::TimeProvider;
+use espoxi3::neopixel::strip::{self, StripConfig};
+use espoxi3::neopixel::NeopixelManager;
 
 // use esp_idf_sys::{self, c_types};
 
@@ -60,43 +57,109 @@ fn main() -> Result<()> {
     let sysloop = EspSystemEventLoop::take()?;
     let store = Arc::new(Mutex::new(store::default()));
 
-    let max_pixel_count = 500;
-    let nm = Arc::new(NeopixelManager::new(Strip::ws2812b(
-        //XXX: 2812(b?)
-        pins.gpio14,
-        peripherals.rmt.channel1,
-        max_pixel_count,
-    )));
+    let strip_config = match store.lock().unwrap().get::<Vec<StripConfig>>("strip") {
+        Ok(Some(config)) => config,
+        Ok(None) => vec![StripConfig::default()],
+        Err(e) => {
+            warn!("Stored strip config could not be loaded: {}", e);
+            vec![StripConfig::default()]
+        }
+    };
+    let (strip, strip_config) = match strip::from_configs(&strip_config) {
+        Ok(strip) => (strip, strip_config),
+        Err(e) => {
+            warn!("Failed to set up the stored strips, falling back to the default: {}", e);
+            let default = vec![StripConfig::default()];
+            (strip::from_configs(&default)?, default)
+        }
+    };
+    let strip_config = Arc::new(Mutex::new(strip_config));
+    let nm = Arc::new(NeopixelManager::new(strip));
     let timer = time::EspNTPC::new();
     let btimer = Box::new(timer);
     nm.run(20, btimer.clone());
 
-    if let Ok(Some(ref mut stored_effects)) =
-        store.lock().unwrap().get::<Vec<EffectConfig>>("effects")
+    let mut sstore = store.lock().unwrap();
+    if let Err(e) = sstore.migrate(nm.led_count()) {
+        warn!("Stored settings could not be migrated: {}", e);
+    }
+    match sstore.get::<Vec<Segment>>("segments") {
+        Ok(Some(segments)) => *nm.segments.lock().unwrap() = Segments::new(segments),
+        Ok(None) => {}
+        Err(e) => warn!("Stored segments could not be loaded: {}", e),
+    }
+    match sstore.get::<MatrixConfig>("matrix") {
+        // the strips may have been shortened since
+        Ok(Some(matrix)) => match matrix.check(nm.led_count()) {
+            Ok(()) => *nm.matrix.lock().unwrap() = Some(Matrix::new(matrix)),
+            Err(e) => warn!("Stored matrix layout no longer fits, ignoring it: {}", e),
+        },
+        Ok(None) => {}
+        Err(e) => warn!("Stored matrix layout could not be loaded: {}", e),
+    }
+    match sstore.get::<Vec<Point>>("pixelmap") {
+        Ok(Some(points)) => *nm.pixel_map.lock().unwrap() = Some(PixelMap::new(points)),
+        Ok(None) => {}
+        Err(e) => warn!("Stored pixel map could not be loaded: {}", e),
+    }
+    match sstore.get::<Vec<Layer>>("effects") {
+        Ok(Some(stored_effects)) => {
+            // info!("Found stored effects: {:?}", stored_effects);
+            nm.effects.lock().unwrap().replace(stored_effects);
+        }
+        Ok(None) => {}
+        Err(e) => warn!("Stored effects could not be loaded: {}", e),
+    }
+    if let Ok(Some(output)) = sstore.get("output") {
+        *nm.output.lock().unwrap() = output;
+    }
+    match sstore.get("power") {
+        Ok(Some(power)) => *nm.power.lock().unwrap() = power,
+        Ok(None) => {}
+        Err(e) => warn!("Stored power budget could not be loaded: {}", e),
+    }
+    match sstore.get("calibration") {
+        Ok(Some(calibration)) => nm.set_calibration(calibration),
+        Ok(None) => {}
+        Err(e) => warn!("Stored calibration could not be loaded: {}", e),
+    }
+    drop(sstore);
+
+    let network = Arc::new(Mutex::new(connection::NetworkInfo::default()));
+    let add_route_tx = connection::init(
+        peripherals.modem,
+        sysloop.clone(),
+        store.clone(),
+        network.clone(),
+    )?;
+
+    neopixel::api::add_routes(&add_route_tx, nm.clone(), store.clone(), strip_config.clone());
+    neopixel::wled::add_routes(
+        &add_route_tx,
+        nm.clone(),
+        store.clone(),
+        network.clone(),
+        strip_config,
+    );
+    neopixel::live::add_routes(&add_route_tx, nm.clone(), store.clone());
+
+    if let Err(e) = protocols::e131::start(nm.clone(), store.clone(), &add_route_tx) {
+        warn!("Failed to start the e1.31 receiver: {}", e);
+    }
+    if let Err(e) =
+        protocols::artnet::start(nm.clone(), store.clone(), network, &add_route_tx)
     {
-        // info!("Found stored effects: {:?}", stored_effects);
-        nm.effects.lock().unwrap().append(stored_effects);
+        warn!("Failed to start the art-net node: {}", e);
+    }
+    if let Err(e) = protocols::ddp::start(nm.clone(), store.clone(), &add_route_tx) {
+        warn!("Failed to start the ddp receiver: {}", e);
+    }
+    if let Err(e) = mqtt::start(nm.clone(), store, &add_route_tx) {
+        warn!("Failed to start mqtt: {}", e);
+    }
+    if let Err(e) = events::start(nm.clone(), &add_route_tx) {
+        warn!("Failed to start the event stream: {}", e);
     }
-
-    let add_route_tx = connection::init(peripherals.modem, sysloop.clone(), store.clone())?;
-
-    let nm2 = nm.clone();
-    add_new_route!(add_route_tx; "/effects", Get, move |req|{
-        let effects = nm2.effects.lock().unwrap();
-        let e = Vec::clone(&effects); //XXX: i wanna be able to just return the lock fast, idk tho if vec clone is faster than parse json, but as u can see here i suppose it is
-        drop(effects);
-        send_as_json!(req, e)
-    });
-
-    let nm3 = nm.clone();
-    add_new_route!(add_route_tx; "/effects", Post, move |mut req|{
-        let new_effects = parse_req_or_fail_with_message!(req; "couldn't parse effects.. {}");
-
-        store.lock().unwrap().set("effects", &new_effects).unwrap();
-        *nm3.effects.lock().unwrap() = new_effects;
-        send_as_json!(req, "ok")
-
-    });
 
     // let _sntp = sntp::EspSntp::new_default()?;
     // info!("SNTP initialized");
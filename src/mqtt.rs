@@ -0,0 +1,73 @@
+//! MQTT client that exposes the strip to Home Assistant, see `light.rs`, `client.rs` drives it.
+//!
+//! The client is (re)created whenever the wifi connection or the config changes, esp-mqtt itself
+//! reconnects after broker outages and `Light::on_connected` announces us again.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "esp")]
+mod client;
+pub mod light;
+
+#[cfg(feature = "esp")]
+pub use client::start;
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct MqttConfig {
+    pub enabled: bool,
+    /// e.g. `mqtt://192.168.1.2:1883`
+    pub url: String,
+    pub username: Option<String>,
+    /// never sent back by `GET /mqtt`, a `POST` without it keeps the stored one
+    pub password: Option<String>,
+    /// topics are `<base_topic>/set`, `/state` and `/status`; empty means `<hostname>/<mac>`
+    pub base_topic: String,
+    pub discovery_prefix: String,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: String::new(),
+            username: None,
+            password: None,
+            base_topic: String::new(),
+            discovery_prefix: "homeassistant".into(),
+        }
+    }
+}
+
+/// Leaves out the password, parsed request bodies end up in the log.
+impl fmt::Debug for MqttConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MqttConfig")
+            .field("enabled", &self.enabled)
+            .field("url", &self.url)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "***"))
+            .field("base_topic", &self.base_topic)
+            .field("discovery_prefix", &self.discovery_prefix)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_hides_the_password() {
+        let config = MqttConfig {
+            username: Some("espoxi".into()),
+            password: Some("hunter2".into()),
+            ..Default::default()
+        };
+        let debug = format!("{:?}", config);
+        assert!(!debug.contains("hunter2"), "{}", debug);
+        assert!(debug.contains(r#"password: Some("***")"#), "{}", debug);
+        assert!(debug.contains("espoxi"), "{}", debug);
+    }
+}
@@ -0,0 +1,245 @@
+//! The esp-mqtt side: one client per network and config, feeding `light::Light`.
+
+use std::{
+    sync::{
+        mpsc::{self, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use anyhow::Result;
+use embedded_svc::{
+    io::Write,
+    mqtt::client::{Client, Event, Message, Publish, QoS},
+};
+use esp_idf_svc::mqtt::client::{EspMqttClient, LwtConfiguration, MqttClientConfiguration};
+use log::{info, warn};
+
+use crate::{
+    add_new_route,
+    connection::{ConnectionRelevantEvent, NetworkInfo},
+    handler_bail, handler_soft_bail, match_parsed_json,
+    neopixel::NeopixelManager,
+    parse_req_or_fail_with_message, send_as_json,
+    store::DStore,
+};
+
+use super::{
+    light::{self, Broker, Light, Topics},
+    MqttConfig,
+};
+
+const STORE_KEY: &str = "mqtt";
+/// how often changes made over HTTP are looked for and published
+const STATE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+enum Msg {
+    Network(NetworkInfo),
+    Config(MqttConfig),
+    Connected,
+    Disconnected,
+    Received(String, Vec<u8>),
+}
+
+struct EspBroker(EspMqttClient);
+
+impl Broker for EspBroker {
+    fn publish(&mut self, topic: &str, retain: bool, payload: &[u8]) -> Result<()> {
+        self.0.publish(topic, QoS::AtLeastOnce, retain, payload)?;
+        Ok(())
+    }
+
+    fn subscribe(&mut self, topic: &str) -> Result<()> {
+        self.0.subscribe(topic, QoS::AtLeastOnce)?;
+        Ok(())
+    }
+}
+
+/// Loads the stored config, adds the `/mqtt` routes and connects whenever the network is up.
+pub fn start(
+    nm: Arc<NeopixelManager<'static>>,
+    store: Arc<Mutex<DStore>>,
+    tx: &Sender<ConnectionRelevantEvent>,
+) -> Result<()> {
+    let config = match store.lock().unwrap().get::<MqttConfig>(STORE_KEY) {
+        Ok(Some(config)) => config,
+        Ok(None) => MqttConfig::default(),
+        Err(e) => {
+            warn!("Stored mqtt config could not be loaded: {}", e);
+            MqttConfig::default()
+        }
+    };
+    let (mqtt_tx, mqtt_rx) = mpsc::channel();
+    add_routes(tx, config.clone(), store.clone(), mqtt_tx.clone());
+
+    let network_tx = mqtt_tx.clone();
+    tx.send(ConnectionRelevantEvent::NetworkListener(Box::new(
+        move |network| {
+            let _ = network_tx.send(Msg::Network(network.clone()));
+        },
+    )))?;
+
+    thread::Builder::new()
+        .stack_size(8 * 1024)
+        .spawn(move || run(nm, store, config, mqtt_tx, mqtt_rx))?;
+    Ok(())
+}
+
+fn run(
+    nm: Arc<NeopixelManager<'static>>,
+    store: Arc<Mutex<DStore>>,
+    mut config: MqttConfig,
+    tx: Sender<Msg>,
+    rx: mpsc::Receiver<Msg>,
+) {
+    let mut network: Option<NetworkInfo> = None;
+    let mut session: Option<(EspBroker, Light)> = None;
+    let mut connected = false;
+    loop {
+        let msg = match rx.recv_timeout(STATE_POLL_INTERVAL) {
+            Ok(msg) => msg,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                if let (true, Some((broker, light))) = (connected, session.as_mut()) {
+                    if let Err(e) = light.publish_state_if_changed(broker, &nm) {
+                        warn!("Failed to publish mqtt state: {}", e);
+                    }
+                }
+                continue;
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+
+        match msg {
+            Msg::Network(new_network) => {
+                network = Some(new_network);
+                session = reconnect(&config, network.as_ref(), &tx);
+                connected = false;
+            }
+            Msg::Config(new_config) => {
+                config = new_config;
+                session = reconnect(&config, network.as_ref(), &tx);
+                connected = false;
+            }
+            Msg::Connected => {
+                info!("Connected to mqtt broker");
+                connected = true;
+                if let Some((broker, light)) = session.as_mut() {
+                    if let Err(e) = light.on_connected(broker, &nm, &store) {
+                        warn!("Failed to announce ourselves over mqtt: {}", e);
+                    }
+                }
+            }
+            Msg::Disconnected => {
+                if connected {
+                    warn!("Lost the mqtt broker, esp-mqtt keeps trying");
+                }
+                connected = false;
+            }
+            Msg::Received(topic, payload) => {
+                if let Some((broker, light)) = session.as_mut() {
+                    if let Err(e) = light.on_message(broker, &nm, &store, &topic, &payload) {
+                        warn!("Failed to handle mqtt message on '{}': {}", topic, e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Drops the old client and creates a new one if mqtt is enabled and we have an address.
+fn reconnect(
+    config: &MqttConfig,
+    network: Option<&NetworkInfo>,
+    tx: &Sender<Msg>,
+) -> Option<(EspBroker, Light)> {
+    let network = match network {
+        Some(network) if network.ip.is_some() => network,
+        _ => return None,
+    };
+    if !config.enabled || config.url.is_empty() {
+        return None;
+    }
+    let topics = Topics::new(config, network);
+    match connect(config, &topics, network, tx.clone()) {
+        Ok(client) => {
+            info!("Connecting to mqtt broker at {}", config.url);
+            Some((EspBroker(client), Light::new(topics, network.clone())))
+        }
+        Err(e) => {
+            warn!("Failed to create mqtt client: {}", e);
+            None
+        }
+    }
+}
+
+fn connect(
+    config: &MqttConfig,
+    topics: &Topics,
+    network: &NetworkInfo,
+    tx: Sender<Msg>,
+) -> Result<EspMqttClient> {
+    let client_id = format!("{}-{}", network.hostname, light::node_id(network));
+    let conf = MqttClientConfiguration {
+        client_id: Some(&client_id),
+        username: config.username.as_deref(),
+        password: config.password.as_deref(),
+        lwt: Some(LwtConfiguration {
+            topic: &topics.availability,
+            payload: b"offline",
+            qos: QoS::AtLeastOnce,
+            retain: true,
+        }),
+        ..Default::default()
+    };
+    let client = EspMqttClient::new(&config.url, &conf, move |event| {
+        let msg = match event {
+            Ok(Event::Connected(_)) => Msg::Connected,
+            Ok(Event::Disconnected) => Msg::Disconnected,
+            Ok(Event::Received(message)) => Msg::Received(
+                message.topic().unwrap_or_default().to_owned(),
+                message.data().to_vec(),
+            ),
+            Ok(_) => return,
+            Err(e) => {
+                warn!("mqtt error: {}", e);
+                return;
+            }
+        };
+        let _ = tx.send(msg);
+    })?;
+    Ok(client)
+}
+
+fn add_routes(
+    tx: &Sender<ConnectionRelevantEvent>,
+    config: MqttConfig,
+    store: Arc<Mutex<DStore>>,
+    mqtt_tx: Sender<Msg>,
+) {
+    let config = Arc::new(Mutex::new(config));
+
+    let config2 = config.clone();
+    add_new_route!(tx; "/mqtt", Get, move |req| {
+        let config = MqttConfig {
+            password: None,
+            ..config2.lock().unwrap().clone()
+        };
+        send_as_json!(req, config)
+    });
+
+    add_new_route!(tx; "/mqtt", Post, move |mut req| {
+        let mut new_config: MqttConfig = parse_req_or_fail_with_message!(req; "couldn't parse mqtt config.. {}");
+        let mut config = config.lock().unwrap();
+        if new_config.password.is_none() {
+            new_config.password = config.password.clone();
+        }
+        if let Err(e) = store.lock().unwrap().set(STORE_KEY, &new_config) {
+            handler_soft_bail!(req; "couldn't store mqtt config: {:?}", e)
+        }
+        *config = new_config.clone();
+        let _ = mqtt_tx.send(Msg::Config(new_config));
+        send_as_json!(req, "ok")
+    });
+}
@@ -0,0 +1,579 @@
+//! The strip as a Home Assistant MQTT light (JSON schema).
+//!
+//! Nothing in here knows about the actual client, everything goes through `Broker`, so the light
+//! can just as well be driven by a broker stand-in.
+
+use std::sync::Mutex;
+
+use anyhow::{bail, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    common::network::NetworkInfo,
+    neopixel::{
+        effects::{solid::SolidColorConfig, EffectConfig},
+        layer::Layer,
+        presets,
+        stack::LayerPatch,
+        strip::{color::default::Color, sim::channel_u8},
+        NeopixelManager,
+    },
+    store::DStore,
+};
+
+use super::MqttConfig;
+
+/// What the light needs from an MQTT connection.
+pub trait Broker {
+    fn publish(&mut self, topic: &str, retain: bool, payload: &[u8]) -> Result<()>;
+    fn subscribe(&mut self, topic: &str) -> Result<()>;
+}
+
+#[derive(Debug, Clone)]
+pub struct Topics {
+    pub command: String,
+    pub state: String,
+    /// `online`/`offline`, the latter is our last will
+    pub availability: String,
+    pub discovery: String,
+}
+
+impl Topics {
+    pub fn new(config: &MqttConfig, network: &NetworkInfo) -> Self {
+        let base = match config.base_topic.is_empty() {
+            true => format!("{}/{}", network.hostname, node_id(network)),
+            false => config.base_topic.trim_end_matches('/').to_owned(),
+        };
+        Self {
+            command: format!("{}/set", base),
+            state: format!("{}/state", base),
+            availability: format!("{}/status", base),
+            discovery: format!(
+                "{}/light/{}/config",
+                config.discovery_prefix,
+                node_id(network)
+            ),
+        }
+    }
+}
+
+/// the mac in hex, stable across renames and networks
+pub fn node_id(network: &NetworkInfo) -> String {
+    network.mac.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Rgb {
+    r: u8,
+    g: u8,
+    b: u8,
+}
+
+#[derive(Debug, Deserialize)]
+struct Command {
+    /// `ON` or `OFF`
+    state: Option<String>,
+    brightness: Option<u8>,
+    color: Option<Rgb>,
+    effect: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct State {
+    state: &'static str,
+    brightness: u8,
+    color_mode: &'static str,
+    color: Rgb,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    effect: Option<String>,
+}
+
+pub struct Light {
+    topics: Topics,
+    network: NetworkInfo,
+    /// the effect last picked over MQTT, as long as the stack is still at that revision
+    picked_effect: Option<(u32, String)>,
+    last_state: Option<Vec<u8>>,
+}
+
+impl Light {
+    pub fn new(topics: Topics, network: NetworkInfo) -> Self {
+        Self {
+            topics,
+            network,
+            picked_effect: None,
+            last_state: None,
+        }
+    }
+
+    /// (Re)announces the light, has to be called after every (re)connect.
+    pub fn on_connected(
+        &mut self,
+        broker: &mut dyn Broker,
+        nm: &NeopixelManager,
+        store: &Mutex<DStore>,
+    ) -> Result<()> {
+        broker.subscribe(&self.topics.command)?;
+        let discovery = serde_json::to_vec(&self.discovery(store))?;
+        broker.publish(&self.topics.discovery, true, &discovery)?;
+        broker.publish(&self.topics.availability, true, b"online")?;
+        self.last_state = None;
+        self.publish_state_if_changed(broker, nm)
+    }
+
+    pub fn on_message(
+        &mut self,
+        broker: &mut dyn Broker,
+        nm: &NeopixelManager,
+        store: &Mutex<DStore>,
+        topic: &str,
+        payload: &[u8],
+    ) -> Result<()> {
+        if topic != self.topics.command {
+            return Ok(());
+        }
+        let command: Command = match serde_json::from_slice(payload) {
+            Ok(command) => command,
+            Err(e) => {
+                warn!("Ignoring unparsable mqtt command: {}", e);
+                return Ok(());
+            }
+        };
+        info!("mqtt command: {:?}", command);
+        self.apply(command, nm, store)?;
+        self.publish_state_if_changed(broker, nm)
+    }
+
+    /// Publishes the state if it differs from what was published last,
+    /// which also catches changes made over HTTP.
+    pub fn publish_state_if_changed(
+        &mut self,
+        broker: &mut dyn Broker,
+        nm: &NeopixelManager,
+    ) -> Result<()> {
+        let state = serde_json::to_vec(&self.state(nm))?;
+        if self.last_state.as_ref() == Some(&state) {
+            return Ok(());
+        }
+        broker.publish(&self.topics.state, true, &state)?;
+        self.last_state = Some(state);
+        Ok(())
+    }
+
+    fn discovery(&self, store: &Mutex<DStore>) -> serde_json::Value {
+        let mut effects: Vec<String> = EffectConfig::NAMES.iter().map(|n| n.to_string()).collect();
+        match presets::list(&store.lock().unwrap()) {
+            Ok(names) => {
+                for name in names {
+                    if !effects.contains(&name) {
+                        effects.push(name);
+                    }
+                }
+            }
+            Err(e) => warn!("Presets are missing from the mqtt effect list: {}", e),
+        }
+        let id = node_id(&self.network);
+        json!({
+            "name": null,
+            "unique_id": format!("{}_light", id),
+            "schema": "json",
+            "command_topic": self.topics.command,
+            "state_topic": self.topics.state,
+            "availability_topic": self.topics.availability,
+            "brightness": true,
+            "brightness_scale": 255,
+            "supported_color_modes": ["rgb"],
+            "effect": true,
+            "effect_list": effects,
+            "device": {
+                "identifiers": [id],
+                "name": self.network.hostname,
+                "model": "esp32",
+                "sw_version": env!("CARGO_PKG_VERSION"),
+            },
+        })
+    }
+
+    fn state(&self, nm: &NeopixelManager) -> State {
+        let output = nm.output.lock().unwrap().clone();
+        let stack = nm.effects.lock().unwrap();
+        let effect = match &self.picked_effect {
+            Some((revision, name)) if *revision == stack.revision() => Some(name.clone()),
+            _ => stack
+                .layers()
+                .iter()
+                .rev()
+                .find(|l| l.enabled)
+                .map(|l| l.effect.name().to_owned()),
+        };
+        let color = base_color(stack.layers()).unwrap_or_else(Color::white);
+        State {
+            state: if output.on { "ON" } else { "OFF" },
+            brightness: (output.brightness.clamp(0.0, 1.0) * 255.0).round() as u8,
+            color_mode: "rgb",
+            color: Rgb {
+                r: channel_u8(color.red),
+                g: channel_u8(color.green),
+                b: channel_u8(color.blue),
+            },
+            effect,
+        }
+    }
+
+    fn apply(
+        &mut self,
+        command: Command,
+        nm: &NeopixelManager,
+        store: &Mutex<DStore>,
+    ) -> Result<()> {
+        if command.state.is_some() || command.brightness.is_some() {
+            let mut output = nm.output.lock().unwrap();
+            match command.state.as_deref() {
+                Some("ON") => output.on = true,
+                Some("OFF") => output.on = false,
+                Some(other) => warn!("Unknown mqtt light state '{}'", other),
+                None => {}
+            }
+            if let Some(brightness) = command.brightness {
+                output.brightness = brightness as f32 / 255.0;
+            }
+            if let Err(e) = store.lock().unwrap().set("output", &*output) {
+                bail!("couldn't store output settings: {:?}", e);
+            }
+        }
+
+        if let Some(effect) = &command.effect {
+            self.pick_effect(effect, nm, store)?;
+        }
+
+        if let Some(rgb) = command.color {
+            let color = Color::from_u8(rgb.r, rgb.g, rgb.b);
+            let mut stack = nm.effects.lock().unwrap();
+            let base = stack
+                .layers()
+                .iter()
+                .find(|l| matches!(l.effect, EffectConfig::SolidColor(_)))
+                .map(|l| l.id);
+            let picked = self.picked_effect.as_ref().map(|(r, _)| *r) == Some(stack.revision());
+            let effect = EffectConfig::SolidColor(SolidColorConfig { color });
+            match base {
+                Some(id) => {
+                    stack.patch(
+                        id,
+                        LayerPatch {
+                            effect: Some(effect),
+                            ..Default::default()
+                        },
+                    );
+                }
+                None => {
+                    stack.insert(Some(0), Layer::new(effect));
+                }
+            }
+            // a new color does not change which effect is playing
+            if let (true, Some((revision, _))) = (picked, self.picked_effect.as_mut()) {
+                *revision = stack.revision();
+            }
+            if let Err(e) = store
+                .lock()
+                .unwrap()
+                .set("effects", &stack.layers().to_vec())
+            {
+                bail!("effects changed but could not be stored: {:?}", e);
+            }
+        }
+        Ok(())
+    }
+
+    /// An `EffectConfig` variant over the whole strip on top of the current color, or a preset.
+    fn pick_effect(
+        &mut self,
+        name: &str,
+        nm: &NeopixelManager,
+        store: &Mutex<DStore>,
+    ) -> Result<()> {
+        if let Some(effect) = EffectConfig::default_named(name) {
+            let mut stack = nm.effects.lock().unwrap();
+            let color = base_color(stack.layers()).unwrap_or_else(Color::white);
+            let base = Layer::new(EffectConfig::SolidColor(SolidColorConfig { color }));
+            let layers = match effect {
+                EffectConfig::SolidColor(_) => vec![base],
+                effect => vec![base, Layer::new(effect)],
+            };
+            stack.replace(layers);
+            self.picked_effect = Some((stack.revision(), name.to_owned()));
+            if let Err(e) = store
+                .lock()
+                .unwrap()
+                .set("effects", &stack.layers().to_vec())
+            {
+                bail!("effects changed but could not be stored: {:?}", e);
+            }
+            return Ok(());
+        }
+
+        let preset = presets::load(&store.lock().unwrap(), name)?;
+        match preset {
+            Some(preset) => {
+                presets::recall(nm, store, name, preset)?;
+                let revision = nm.effects.lock().unwrap().revision();
+                self.picked_effect = Some((revision, name.to_owned()));
+                Ok(())
+            }
+            None => {
+                warn!("mqtt asked for unknown effect '{}'", name);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// the color of the lowest solid color layer
+fn base_color(layers: &[Layer]) -> Option<Color> {
+    layers.iter().find_map(|l| match &l.effect {
+        EffectConfig::SolidColor(c) => Some(c.color),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{
+        neopixel::{output::OutputConfig, presets::Preset, strip::sim::SimStrip},
+        store,
+    };
+
+    const COMMAND: &str = "espoxi3/aabbccddeeff/set";
+    const STATE: &str = "espoxi3/aabbccddeeff/state";
+    const AVAILABILITY: &str = "espoxi3/aabbccddeeff/status";
+    const DISCOVERY: &str = "homeassistant/light/aabbccddeeff/config";
+
+    /// Remembers what the light subscribed to and published, in order.
+    #[derive(Default)]
+    struct FakeBroker {
+        subscribed: Vec<String>,
+        published: Vec<(String, bool, Vec<u8>)>,
+    }
+
+    impl Broker for FakeBroker {
+        fn publish(&mut self, topic: &str, retain: bool, payload: &[u8]) -> Result<()> {
+            self.published
+                .push((topic.to_owned(), retain, payload.to_vec()));
+            Ok(())
+        }
+
+        fn subscribe(&mut self, topic: &str) -> Result<()> {
+            self.subscribed.push(topic.to_owned());
+            Ok(())
+        }
+    }
+
+    impl FakeBroker {
+        /// everything published on `topic` since the last call, as JSON
+        fn take(&mut self, topic: &str) -> Vec<Value> {
+            let (taken, kept) = self.published.drain(..).partition(|(t, _, _)| t == topic);
+            self.published = kept;
+            taken
+                .into_iter()
+                .map(|(_, retain, payload)| {
+                    assert!(retain, "{} is not retained", topic);
+                    serde_json::from_slice(&payload).unwrap()
+                })
+                .collect()
+        }
+    }
+
+    struct Setup {
+        light: Light,
+        broker: FakeBroker,
+        nm: NeopixelManager<'static>,
+        store: Mutex<DStore>,
+    }
+
+    impl Setup {
+        fn connected() -> Self {
+            let network = NetworkInfo {
+                mac: [0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff],
+                hostname: "espoxi3".into(),
+                ..Default::default()
+            };
+            let topics = Topics::new(&MqttConfig::default(), &network);
+            let mut setup = Self {
+                light: Light::new(topics, network),
+                broker: FakeBroker::default(),
+                nm: NeopixelManager::new(Box::new(SimStrip::new(10))),
+                store: Mutex::new(store::default()),
+            };
+            let preset = Preset {
+                effects: vec![Layer::new(EffectConfig::default_named("Plasma").unwrap())],
+                output: OutputConfig {
+                    brightness: 0.2,
+                    on: true,
+                },
+                calibration: None,
+            };
+            presets::save(&mut setup.store.lock().unwrap(), "party", &preset).unwrap();
+            setup
+                .light
+                .on_connected(&mut setup.broker, &setup.nm, &setup.store)
+                .unwrap();
+            setup
+        }
+
+        /// sends `command` and returns the state published in response, if any
+        fn command(&mut self, command: Value) -> Option<Value> {
+            let payload = serde_json::to_vec(&command).unwrap();
+            self.light
+                .on_message(&mut self.broker, &self.nm, &self.store, COMMAND, &payload)
+                .unwrap();
+            let mut states = self.broker.take(STATE);
+            assert!(states.len() <= 1);
+            states.pop()
+        }
+    }
+
+    #[test]
+    fn announces_itself_on_connect() {
+        let mut setup = Setup::connected();
+        assert_eq!(setup.broker.subscribed, vec![COMMAND]);
+
+        let discovery = setup.broker.take(DISCOVERY).remove(0);
+        assert_eq!(discovery["schema"], "json");
+        assert_eq!(discovery["unique_id"], "aabbccddeeff_light");
+        assert_eq!(discovery["command_topic"], COMMAND);
+        assert_eq!(discovery["state_topic"], STATE);
+        assert_eq!(discovery["availability_topic"], AVAILABILITY);
+        assert_eq!(
+            discovery["supported_color_modes"],
+            serde_json::json!(["rgb"])
+        );
+        let effects: Vec<&str> = discovery["effect_list"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e.as_str().unwrap())
+            .collect();
+        let mut expected = EffectConfig::NAMES.to_vec();
+        expected.push("party");
+        assert_eq!(effects, expected);
+
+        let (topic, retain, payload) = setup.broker.published.remove(0);
+        assert_eq!((topic.as_str(), retain), (AVAILABILITY, true));
+        assert_eq!(payload, b"online");
+        let state = setup.broker.take(STATE).remove(0);
+        assert_eq!(state["state"], "ON");
+        assert!(setup.broker.published.is_empty());
+    }
+
+    #[test]
+    fn turns_off_and_on() {
+        let mut setup = Setup::connected();
+        setup.broker.published.clear();
+
+        let state = setup
+            .command(serde_json::json!({ "state": "OFF" }))
+            .unwrap();
+        assert_eq!(state["state"], "OFF");
+        assert!(!setup.nm.output.lock().unwrap().on);
+        let stored = setup.store.lock().unwrap().get::<OutputConfig>("output");
+        assert!(!stored.unwrap().unwrap().on);
+
+        let state = setup.command(serde_json::json!({ "state": "ON" })).unwrap();
+        assert_eq!(state["state"], "ON");
+        assert!(setup.nm.output.lock().unwrap().on);
+    }
+
+    #[test]
+    fn sets_the_brightness() {
+        let mut setup = Setup::connected();
+        setup.broker.published.clear();
+
+        let state = setup
+            .command(serde_json::json!({ "brightness": 51 }))
+            .unwrap();
+        assert_eq!(state["brightness"], 51);
+        assert_eq!(setup.nm.output.lock().unwrap().brightness, 0.2);
+    }
+
+    #[test]
+    fn colors_the_base_layer() {
+        let mut setup = Setup::connected();
+        setup.broker.published.clear();
+
+        let state = setup
+            .command(serde_json::json!({ "color": { "r": 255, "g": 0, "b": 0 } }))
+            .unwrap();
+        assert_eq!(state["color_mode"], "rgb");
+        assert_eq!(
+            state["color"],
+            serde_json::json!({ "r": 255, "g": 0, "b": 0 })
+        );
+        let stack = setup.nm.effects.lock().unwrap();
+        assert_eq!(stack.layers().len(), 1);
+        assert_eq!(base_color(stack.layers()), Some(Color::red()));
+    }
+
+    #[test]
+    fn plays_an_effect_over_the_color() {
+        let mut setup = Setup::connected();
+        setup.broker.published.clear();
+        setup.command(serde_json::json!({ "color": { "r": 255, "g": 0, "b": 0 } }));
+
+        let state = setup
+            .command(serde_json::json!({ "effect": "Strobo" }))
+            .unwrap();
+        assert_eq!(state["effect"], "Strobo");
+        assert_eq!(
+            state["color"],
+            serde_json::json!({ "r": 255, "g": 0, "b": 0 })
+        );
+        let stack = setup.nm.effects.lock().unwrap();
+        let names: Vec<&str> = stack.layers().iter().map(|l| l.effect.name()).collect();
+        assert_eq!(names, vec!["SolidColor", "Strobo"]);
+    }
+
+    #[test]
+    fn recalls_a_preset_as_effect() {
+        let mut setup = Setup::connected();
+        setup.broker.published.clear();
+
+        let state = setup
+            .command(serde_json::json!({ "effect": "party" }))
+            .unwrap();
+        assert_eq!(state["effect"], "party");
+        assert_eq!(state["brightness"], 51);
+        let stack = setup.nm.effects.lock().unwrap();
+        assert!(matches!(stack.layers()[0].effect, EffectConfig::Plasma(_)));
+    }
+
+    #[test]
+    fn publishes_only_changes() {
+        let mut setup = Setup::connected();
+        setup.broker.published.clear();
+
+        assert!(setup
+            .command(serde_json::json!({ "state": "ON" }))
+            .is_none());
+        assert!(setup
+            .command(serde_json::json!({ "effect": "Nope" }))
+            .is_none());
+        setup
+            .light
+            .on_message(&mut setup.broker, &setup.nm, &setup.store, STATE, b"{}")
+            .unwrap();
+        assert!(setup.broker.published.is_empty());
+
+        // changes made over HTTP are picked up as well
+        setup.nm.output.lock().unwrap().on = false;
+        setup
+            .light
+            .publish_state_if_changed(&mut setup.broker, &setup.nm)
+            .unwrap();
+        assert_eq!(setup.broker.take(STATE).remove(0)["state"], "OFF");
+    }
+}
@@ -1,58 +1,178 @@
 use std::{
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc, Mutex,
+    },
     thread,
-    time::Instant,
+    time::{Duration, Instant},
 };
 
-use esp_idf_hal::delay::FreeRtos;
+use anyhow::Result;
+
 // use esp_idf_svc::timer::{self, EspTimer};
 
 use crate::common::time::{TimeProvider};
 
 use self::{
-    effects::EffectConfig,
-    strip::{color::default::Color, Strip},
+    matrix::Matrix,
+    output::{OutputConfig, PowerConfig},
+    pixelmap::PixelMap,
+    realtime::Realtime,
+    segment::Segments,
+    stack::EffectStack,
+    strip::{
+        calibration::{Calibration, CalibrationConfig},
+        color::default::Color,
+        LedSink,
+    },
 };
 
+#[cfg(feature = "esp")]
+pub mod api;
 pub mod effects;
+pub mod golden;
+pub mod layer;
+pub mod live;
+pub mod matrix;
+pub mod output;
+pub mod pixelmap;
+pub mod presets;
+pub mod realtime;
+pub mod segment;
+pub mod stack;
 pub mod strip;
+pub mod wled;
 
 // const PIXELCOUNT: u16 = 60;
 
+pub type Sink<'a> = Box<dyn LedSink + Send + Sync + 'a>;
+
 pub struct NeopixelManager<'a> {
-    strip: Arc<strip::Strip<'a>>,
+    /// `None` only while it is being replaced, or if that failed
+    strip: Arc<Mutex<Option<Sink<'a>>>>,
+    led_count: Arc<AtomicU16>,
     colors: Arc<Mutex<Vec<Color>>>,
-    pub effects: Arc<Mutex<Vec<EffectConfig>>>,
+    pub effects: Arc<Mutex<EffectStack>>,
+    /// lock after `effects` when holding both
+    pub segments: Arc<Mutex<Segments>>,
+    /// lock after `segments` when holding both
+    pub matrix: Arc<Mutex<Option<Matrix>>>,
+    /// lock after `matrix` when holding both
+    pub pixel_map: Arc<Mutex<Option<PixelMap>>>,
+    pub output: Arc<Mutex<OutputConfig>>,
+    pub power: Arc<Mutex<PowerConfig>>,
+    pub realtime: Arc<Realtime>,
+    /// the last frame sent to the strip, after the output stage
+    frame: Arc<Mutex<Vec<Color>>>,
+    /// what the strip was last calibrated with, it only keeps the lookup table
+    calibration: Mutex<CalibrationConfig>,
+    /// the preset recalled last, with the revision of `effects` it left behind
+    pub recalled: Mutex<Option<(u32, String)>>,
+}
+
+impl NeopixelManager<'_> {
+    pub fn led_count(&self) -> u16 {
+        self.led_count.load(Ordering::SeqCst)
+    }
+
+    pub fn calibration(&self) -> CalibrationConfig {
+        self.calibration.lock().unwrap().clone()
+    }
+
+    /// Gamma and white balance of the strip, from the next frame on.
+    pub fn set_calibration(&self, config: CalibrationConfig) {
+        if let Some(strip) = self.strip.lock().unwrap().as_ref() {
+            strip.set_calibration(Calibration::new(&config));
+        }
+        *self.calibration.lock().unwrap() = config;
+    }
 }
 
 impl NeopixelManager<'static> {
-    pub fn new(strip: Strip<'static>) -> Self {
-        let strip = Arc::new(strip);
-        let colors = Arc::new(Mutex::new(vec![Color::black(); strip.led_count as usize]));
-        let effects = Arc::new(Mutex::new(Vec::new()));
+    pub fn new(strip: Sink<'static>) -> Self {
+        let led_count = Arc::new(AtomicU16::new(strip.led_count()));
+        let colors = Arc::new(Mutex::new(vec![Color::black(); strip.led_count() as usize]));
+        let strip: Arc<Mutex<Option<Sink>>> = Arc::new(Mutex::new(Some(strip)));
+        let effects = Arc::new(Mutex::new(EffectStack::default()));
+        let segments = Arc::new(Mutex::new(Segments::default()));
+        let matrix = Arc::new(Mutex::new(None));
+        let pixel_map = Arc::new(Mutex::new(None));
+        let output = Arc::new(Mutex::new(OutputConfig::default()));
+        let power = Arc::new(Mutex::new(PowerConfig::default()));
+        let realtime = Arc::new(Realtime::new());
+        let frame = Arc::new(Mutex::new(Vec::new()));
+        let calibration = Mutex::new(CalibrationConfig::default());
+        let recalled = Mutex::new(None);
         Self {
             strip,
+            led_count,
             colors,
-            effects, 
+            effects,
+            segments,
+            matrix,
+            pixel_map,
+            output,
+            power,
+            realtime,
+            frame,
+            calibration,
+            recalled,
         }
     }
 
+    /// Drops the current strip, then puts what `make` returns in its place.
+    /// The old strip goes first so the new one can take over its pin and peripherals.
+    pub fn replace_strip(&self, make: impl FnOnce() -> Result<Sink<'static>>) -> Result<()> {
+        let mut strip = self.strip.lock().unwrap();
+        *strip = None;
+        let new = make()?;
+        new.set_calibration(Calibration::new(&self.calibration()));
+        self.led_count.store(new.led_count(), Ordering::SeqCst);
+        *strip = Some(new);
+        Ok(())
+    }
+
+    /// Copies what the strip currently shows into `out`.
+    pub fn frame_into(&self, out: &mut Vec<Color>) {
+        out.clone_from(&self.frame.lock().unwrap());
+    }
+
     ///mspf = milliseconds per frame = 1000 / fps
     pub fn run(&self, mspf: u32, /*timer : &'static(dyn TimeProvider +Sync)*/ timer : Box<dyn TimeProvider + Send>) -> &Self {
         let ccolors = self.colors.clone();
         let sstrip = self.strip.clone();
+        let lled_count = self.led_count.clone();
         let eeffects = self.effects.clone();
+        let ssegments = self.segments.clone();
+        let mmatrix = self.matrix.clone();
+        let ppixel_map = self.pixel_map.clone();
+        let ooutput = self.output.clone();
+        let ppower = self.power.clone();
+        let rrealtime = self.realtime.clone();
+        let fframe = self.frame.clone();
         thread::spawn(move || {
             let s = Instant::now();
+            let mut frame = Vec::new();
             loop {
                 let effects = eeffects.lock().unwrap();
                 let mut colors = ccolors.lock().unwrap();
-                effects::apply_effects(&effects, &mut colors, Instant::now() - s, timer.now()).unwrap();
+                colors.resize(lled_count.load(Ordering::SeqCst) as usize, Color::black());
+                if !rrealtime.render_into(&mut colors) {
+                    let segments = ssegments.lock().unwrap();
+                    let matrix = mmatrix.lock().unwrap();
+                    let pixel_map = ppixel_map.lock().unwrap();
+                    layer::compose(effects.layers(), &segments, matrix.as_ref(), pixel_map.as_ref(), &mut colors, Instant::now() - s, timer.now()).unwrap();
+                }
                 // println!("applied effects effects: {:?}", effects);
                 drop(effects);
-                sstrip.send_colors(&colors).unwrap();
+                ooutput.lock().unwrap().apply(&colors, &mut frame);
                 drop(colors);
-                FreeRtos::delay_ms(mspf);
+                ppower.lock().unwrap().limit(&mut frame);
+                if let Some(strip) = sstrip.lock().unwrap().as_ref() {
+                    strip.send_colors(&frame).unwrap();
+                }
+                fframe.lock().unwrap().clone_from(&frame);
+                thread::sleep(Duration::from_millis(mspf as u64));
             }
         });
         self
@@ -0,0 +1,644 @@
+//! HTTP routes for editing the effect stack, the segments layers are drawn on, the matrix layout
+//! and pixel map, the output settings, power budget, calibration, the strips themselves and the
+//! presets.
+//!
+//! Single layers are addressed by their stable id via query parameters, e.g. `DELETE /effects/item?id=3`.
+//! Every change can be made conditional with `&rev=N`: if the stack has changed since the client
+//! saw revision `N` the request is rejected with 409 and the current revision.
+//! `POST /effects` and `/effects/item` also take bare effects, as sent before there were layers.
+
+use std::sync::{mpsc::Sender, Arc, Mutex};
+
+use embedded_svc::http::server::{HandlerError, Request};
+use embedded_svc::http::Query;
+use embedded_svc::io::Write;
+use esp_idf_svc::http::server::EspHttpConnection;
+use log::info;
+use serde::Serialize;
+
+use crate::{
+    add_new_route,
+    connection::{
+        server::{query_param, query_param_decoded, read_req_body, BodyError},
+        ConnectionRelevantEvent,
+    },
+    handler_bail, handler_soft_bail, match_parsed_json, parse_req_or_fail_with_message,
+    send_as_json,
+    store::DStore,
+};
+
+use super::{
+    layer::{Layer, LayerOrEffect},
+    matrix::{Matrix, MatrixConfig},
+    output::{OutputConfig, PowerConfig},
+    pixelmap::{self, PixelMap},
+    presets::{self, Preset},
+    segment::Segment,
+    stack::{EffectStack, LayerPatch},
+    strip::{self, calibration::CalibrationConfig, StripConfig},
+    NeopixelManager,
+};
+
+macro_rules! id_or_fail {
+    ($req:ident) => {{
+        let uri = $req.uri().to_owned();
+        id_or_fail!($req, uri)
+    }};
+    ($req:ident, $uri:expr) => {
+        match query_param(&$uri, "id").and_then(|id| id.parse::<u32>().ok()) {
+            Some(id) => id,
+            None => handler_soft_bail!($req; "missing or invalid 'id'"),
+        }
+    };
+}
+
+macro_rules! name_or_fail {
+    ($req:ident, $uri:expr) => {
+        match query_param_decoded(&$uri, "name") {
+            Some(name) => name,
+            None => handler_soft_bail!($req; "missing or invalid 'name'"),
+        }
+    };
+}
+
+macro_rules! persist_or_fail {
+    ($req:ident, $store:expr, $stack:expr) => {
+        if let Err(e) = $store.lock().unwrap().set("effects", &$stack.layers().to_vec()) {
+            handler_soft_bail!($req; "effects changed but could not be stored: {:?}", e)
+        }
+    };
+}
+
+macro_rules! persist_segments_or_fail {
+    ($req:ident, $store:expr, $segments:expr) => {
+        if let Err(e) = $store.lock().unwrap().set("segments", &$segments.list().to_vec()) {
+            handler_soft_bail!($req; "couldn't store segments: {:?}", e)
+        }
+    };
+}
+
+#[derive(Serialize)]
+struct Ack {
+    revision: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    enabled: Option<bool>,
+}
+
+impl Ack {
+    fn new(stack: &EffectStack, id: Option<u32>) -> Self {
+        Self {
+            revision: stack.revision(),
+            id,
+            enabled: None,
+        }
+    }
+}
+
+pub fn add_routes(
+    tx: &Sender<ConnectionRelevantEvent>,
+    nm: Arc<NeopixelManager<'static>>,
+    store: Arc<Mutex<DStore>>,
+    strip_config: Arc<Mutex<Vec<StripConfig>>>,
+) {
+    let nm2 = nm.clone();
+    add_new_route!(tx; "/effects", Get, move |req| {
+        let effects = nm2.effects.lock().unwrap();
+        let e = effects.layers().to_vec(); //XXX: i wanna be able to just return the lock fast, idk tho if vec clone is faster than parse json, but as u can see here i suppose it is
+        drop(effects);
+        send_as_json!(req, e)
+    });
+
+    let (nm2, store2) = (nm.clone(), store.clone());
+    add_new_route!(tx; "/effects", Post, move |mut req| {
+        let uri = req.uri().to_owned();
+        let new_effects: Vec<LayerOrEffect> = parse_req_or_fail_with_message!(req; "couldn't parse effects.. {}");
+        let new_effects: Vec<Layer> = new_effects.into_iter().map(Layer::from).collect();
+
+        let mut stack = nm2.effects.lock().unwrap();
+        if revision_conflict(&uri, &stack) {
+            return conflict(req, &stack);
+        }
+        if let Some(segment) = unknown_segment(&nm2, new_effects.iter().map(|l| l.segment)) {
+            return segment_not_found(req, segment);
+        }
+        stack.replace(new_effects);
+        persist_or_fail!(req, store2, stack);
+        send_as_json!(req, Ack::new(&stack, None))
+    });
+
+    let nm2 = nm.clone();
+    add_new_route!(tx; "/effects/revision", Get, move |req| {
+        let revision = nm2.effects.lock().unwrap().revision();
+        send_as_json!(req, revision)
+    });
+
+    let nm2 = nm.clone();
+    add_new_route!(tx; "/effects/item", Get, move |req| {
+        let id = id_or_fail!(req);
+        let layer = nm2.effects.lock().unwrap().get(id).cloned();
+        match layer {
+            Some(layer) => send_as_json!(req, layer),
+            None => not_found(req, id),
+        }
+    });
+
+    // insert, `?at=I` puts it at index I (0 is the bottom), without it the layer is appended on top
+    let (nm2, store2) = (nm.clone(), store.clone());
+    add_new_route!(tx; "/effects/item", Post, move |mut req| {
+        let uri = req.uri().to_owned();
+        let at = query_param(&uri, "at").and_then(|i| i.parse::<usize>().ok());
+        let layer: LayerOrEffect = parse_req_or_fail_with_message!(req; "couldn't parse effect.. {}");
+        let layer = Layer::from(layer);
+
+        let mut stack = nm2.effects.lock().unwrap();
+        if revision_conflict(&uri, &stack) {
+            return conflict(req, &stack);
+        }
+        if let Some(segment) = unknown_segment(&nm2, Some(layer.segment)) {
+            return segment_not_found(req, segment);
+        }
+        let id = stack.insert(at, layer);
+        persist_or_fail!(req, store2, stack);
+        send_as_json!(req, Ack::new(&stack, Some(id)))
+    });
+
+    let (nm2, store2) = (nm.clone(), store.clone());
+    add_new_route!(tx; "/effects/item", Patch, move |mut req| {
+        let uri = req.uri().to_owned();
+        let id = id_or_fail!(req, uri);
+        let patch: LayerPatch = parse_req_or_fail_with_message!(req; "couldn't parse patch.. {}");
+
+        let mut stack = nm2.effects.lock().unwrap();
+        if revision_conflict(&uri, &stack) {
+            return conflict(req, &stack);
+        }
+        if let Some(segment) = unknown_segment(&nm2, patch.segment) {
+            return segment_not_found(req, segment);
+        }
+        if stack.patch(id, patch).is_none() {
+            return not_found(req, id);
+        }
+        persist_or_fail!(req, store2, stack);
+        send_as_json!(req, Ack::new(&stack, Some(id)))
+    });
+
+    let (nm2, store2) = (nm.clone(), store.clone());
+    add_new_route!(tx; "/effects/item", Delete, move |req| {
+        let uri = req.uri().to_owned();
+        let id = id_or_fail!(req, uri);
+
+        let mut stack = nm2.effects.lock().unwrap();
+        if revision_conflict(&uri, &stack) {
+            return conflict(req, &stack);
+        }
+        if stack.remove(id).is_none() {
+            return not_found(req, id);
+        }
+        persist_or_fail!(req, store2, stack);
+        send_as_json!(req, Ack::new(&stack, Some(id)))
+    });
+
+    // `?id=N&to=I` moves the layer to index I (0 is the bottom)
+    let (nm2, store2) = (nm.clone(), store.clone());
+    add_new_route!(tx; "/effects/move", Post, move |req| {
+        let uri = req.uri().to_owned();
+        let id = id_or_fail!(req, uri);
+        let to = match query_param(&uri, "to").and_then(|i| i.parse::<usize>().ok()) {
+            Some(to) => to,
+            None => handler_soft_bail!(req; "missing or invalid 'to'"),
+        };
+
+        let mut stack = nm2.effects.lock().unwrap();
+        if revision_conflict(&uri, &stack) {
+            return conflict(req, &stack);
+        }
+        if stack.move_to(id, to).is_none() {
+            return not_found(req, id);
+        }
+        persist_or_fail!(req, store2, stack);
+        send_as_json!(req, Ack::new(&stack, Some(id)))
+    });
+
+    // `?id=N` flips the layer on or off, `&enabled=true|false` sets it explicitly
+    let (nm2, store2) = (nm.clone(), store.clone());
+    add_new_route!(tx; "/effects/toggle", Post, move |req| {
+        let uri = req.uri().to_owned();
+        let id = id_or_fail!(req, uri);
+        let enabled = query_param(&uri, "enabled").and_then(|e| e.parse::<bool>().ok());
+
+        let mut stack = nm2.effects.lock().unwrap();
+        if revision_conflict(&uri, &stack) {
+            return conflict(req, &stack);
+        }
+        let enabled = match stack.set_enabled(id, enabled) {
+            Some(enabled) => enabled,
+            None => return not_found(req, id),
+        };
+        persist_or_fail!(req, store2, stack);
+        send_as_json!(req, Ack { enabled: Some(enabled), ..Ack::new(&stack, Some(id)) })
+    });
+
+    add_segment_routes(tx, nm.clone(), store.clone());
+    add_matrix_routes(tx, nm.clone(), store.clone());
+    add_pixel_map_routes(tx, nm.clone(), store.clone());
+    add_output_routes(tx, nm.clone(), store.clone());
+    add_strip_routes(tx, nm.clone(), store.clone(), strip_config);
+    add_preset_routes(tx, nm, store);
+}
+
+/// Segment 0 is the whole strip, it is not listed and can not be changed.
+/// A segment can only be deleted once no layer is drawn on it.
+fn add_segment_routes(
+    tx: &Sender<ConnectionRelevantEvent>,
+    nm: Arc<NeopixelManager<'static>>,
+    store: Arc<Mutex<DStore>>,
+) {
+    let nm2 = nm.clone();
+    add_new_route!(tx; "/segments", Get, move |req| {
+        let segments = nm2.segments.lock().unwrap().list().to_vec();
+        send_as_json!(req, segments)
+    });
+
+    // creates a segment and answers with its id
+    let (nm2, store2) = (nm.clone(), store.clone());
+    add_new_route!(tx; "/segments", Post, move |mut req| {
+        let segment: Segment = parse_req_or_fail_with_message!(req; "couldn't parse segment.. {}");
+        let segment = Segment { id: 0, ..segment };
+        let led_count = nm2.led_count();
+        let mut segments = nm2.segments.lock().unwrap();
+        if let Err(e) = segments.check(&segment, led_count) {
+            handler_soft_bail!(req; "invalid segment: {}", e)
+        }
+        let mut changed = segments.clone();
+        let id = changed.insert(segment);
+        persist_segments_or_fail!(req, store2, changed);
+        *segments = changed;
+        send_as_json!(req, id)
+    });
+
+    let (nm2, store2) = (nm.clone(), store.clone());
+    add_new_route!(tx; "/segments/item", Post, move |mut req| {
+        let uri = req.uri().to_owned();
+        let id = id_or_fail!(req, uri);
+        let segment: Segment = parse_req_or_fail_with_message!(req; "couldn't parse segment.. {}");
+        let segment = Segment { id, ..segment };
+        let led_count = nm2.led_count();
+        let mut segments = nm2.segments.lock().unwrap();
+        if let Err(e) = segments.check(&segment, led_count) {
+            handler_soft_bail!(req; "invalid segment: {}", e)
+        }
+        let mut changed = segments.clone();
+        if !changed.update(segment) {
+            return segment_not_found(req, id);
+        }
+        persist_segments_or_fail!(req, store2, changed);
+        *segments = changed;
+        send_as_json!(req, "ok")
+    });
+
+    add_new_route!(tx; "/segments/item", Delete, move |req| {
+        let id = id_or_fail!(req);
+        let stack = nm.effects.lock().unwrap();
+        let mut segments = nm.segments.lock().unwrap();
+        if let Some(layer) = stack.layers().iter().find(|l| l.segment == id) {
+            handler_soft_bail!(req; "segment {} is used by effect {}", id, layer.id)
+        }
+        let mut changed = segments.clone();
+        if changed.remove(id).is_none() {
+            return segment_not_found(req, id);
+        }
+        persist_segments_or_fail!(req, store, changed);
+        *segments = changed;
+        send_as_json!(req, "ok")
+    });
+}
+
+/// `/matrix` is the panel layout 2D effects draw on, `null` when the leds are just a strip
+fn add_matrix_routes(
+    tx: &Sender<ConnectionRelevantEvent>,
+    nm: Arc<NeopixelManager<'static>>,
+    store: Arc<Mutex<DStore>>,
+) {
+    let nm2 = nm.clone();
+    add_new_route!(tx; "/matrix", Get, move |req| {
+        let config = nm2.matrix.lock().unwrap().as_ref().map(|m| m.config().clone());
+        send_as_json!(req, config)
+    });
+
+    let (nm2, store2) = (nm.clone(), store.clone());
+    add_new_route!(tx; "/matrix", Post, move |mut req| {
+        let config: MatrixConfig = parse_req_or_fail_with_message!(req; "couldn't parse matrix layout.. {}");
+        if let Err(e) = config.check(nm2.led_count()) {
+            handler_soft_bail!(req; "invalid matrix layout: {}", e)
+        }
+        if let Err(e) = store2.lock().unwrap().set("matrix", &config) {
+            handler_soft_bail!(req; "couldn't store matrix layout: {:?}", e)
+        }
+        *nm2.matrix.lock().unwrap() = Some(Matrix::new(config));
+        send_as_json!(req, "ok")
+    });
+
+    add_new_route!(tx; "/matrix", Delete, move |req| {
+        if let Err(e) = store.lock().unwrap().remove("matrix") {
+            handler_soft_bail!(req; "couldn't remove matrix layout: {:?}", e)
+        }
+        *nm.matrix.lock().unwrap() = None;
+        send_as_json!(req, "ok")
+    });
+}
+
+/// enough for `pixelmap::MAX_POINTS` lines of CSV with a few decimals
+const MAX_PIXEL_MAP_SIZE: usize = 64 * 1024;
+
+/// `/pixelmap` is where every led is in space, see `pixelmap.rs` for the formats.
+/// `GET` answers with the points as uploaded, `null` without a map.
+fn add_pixel_map_routes(
+    tx: &Sender<ConnectionRelevantEvent>,
+    nm: Arc<NeopixelManager<'static>>,
+    store: Arc<Mutex<DStore>>,
+) {
+    let nm2 = nm.clone();
+    add_new_route!(tx; "/pixelmap", Get, move |req| {
+        let points = nm2.pixel_map.lock().unwrap().as_ref().map(|m| m.points().to_vec());
+        send_as_json!(req, points)
+    });
+
+    let (nm2, store2) = (nm.clone(), store.clone());
+    add_new_route!(tx; "/pixelmap", Post, move |mut req| {
+        let body = match read_req_body(&mut req, MAX_PIXEL_MAP_SIZE) {
+            Ok(body) => body,
+            Err(BodyError::TooLarge(limit)) => {
+                req.into_status_response(413)?
+                    .write_all(format!("body is larger than {} bytes", limit).as_bytes())?;
+                return Ok(());
+            }
+            Err(e) => handler_soft_bail!(req; "{}", e),
+        };
+        let map = match std::str::from_utf8(&body) {
+            Ok(map) => map,
+            Err(e) => handler_soft_bail!(req; "the pixel map is not text: {}", e),
+        };
+        let points = match pixelmap::parse(map) {
+            Ok(points) => points,
+            Err(e) => handler_soft_bail!(req; "couldn't parse pixel map.. {}", e),
+        };
+        if points.len() > nm2.led_count() as usize {
+            handler_soft_bail!(req; "the map has {} points, the strips only {} leds", points.len(), nm2.led_count())
+        }
+        if let Err(e) = store2.lock().unwrap().set("pixelmap", &points) {
+            handler_soft_bail!(req; "couldn't store pixel map: {:?}", e)
+        }
+        info!("Pixel map now has {} points", points.len());
+        *nm2.pixel_map.lock().unwrap() = Some(PixelMap::new(points));
+        send_as_json!(req, "ok")
+    });
+
+    add_new_route!(tx; "/pixelmap", Delete, move |req| {
+        if let Err(e) = store.lock().unwrap().remove("pixelmap") {
+            handler_soft_bail!(req; "couldn't remove pixel map: {:?}", e)
+        }
+        *nm.pixel_map.lock().unwrap() = None;
+        send_as_json!(req, "ok")
+    });
+}
+
+fn add_output_routes(
+    tx: &Sender<ConnectionRelevantEvent>,
+    nm: Arc<NeopixelManager<'static>>,
+    store: Arc<Mutex<DStore>>,
+) {
+    let nm2 = nm.clone();
+    add_new_route!(tx; "/output", Get, move |req| {
+        let output = nm2.output.lock().unwrap().clone();
+        send_as_json!(req, output)
+    });
+
+    add_new_route!(tx; "/output", Post, move |mut req| {
+        let output: OutputConfig = parse_req_or_fail_with_message!(req; "couldn't parse output settings.. {}");
+        if let Err(e) = store.lock().unwrap().set("output", &output) {
+            handler_soft_bail!(req; "couldn't store output settings: {:?}", e)
+        }
+        *nm.output.lock().unwrap() = output;
+        send_as_json!(req, "ok")
+    });
+
+    let nm2 = nm.clone();
+    add_new_route!(tx; "/power", Get, move |req| {
+        let power = nm2.power.lock().unwrap().clone();
+        let mut frame = Vec::new();
+        nm2.frame_into(&mut frame);
+        let estimated_ma = power.estimate_ma(&frame);
+        send_as_json!(req, PowerStatus { power, estimated_ma })
+    });
+
+    add_new_route!(tx; "/power", Post, move |mut req| {
+        let power: PowerConfig = parse_req_or_fail_with_message!(req; "couldn't parse power budget.. {}");
+        if let Err(e) = store.lock().unwrap().set("power", &power) {
+            handler_soft_bail!(req; "couldn't store power budget: {:?}", e)
+        }
+        *nm.power.lock().unwrap() = power;
+        send_as_json!(req, "ok")
+    });
+
+    let nm2 = nm.clone();
+    add_new_route!(tx; "/calibration", Get, move |req| {
+        send_as_json!(req, nm2.calibration())
+    });
+
+    add_new_route!(tx; "/calibration", Post, move |mut req| {
+        let calibration: CalibrationConfig = parse_req_or_fail_with_message!(req; "couldn't parse calibration.. {}");
+        if let Err(e) = store.lock().unwrap().set("calibration", &calibration) {
+            handler_soft_bail!(req; "couldn't store calibration: {:?}", e)
+        }
+        nm.set_calibration(calibration);
+        send_as_json!(req, "ok")
+    });
+}
+
+#[derive(Serialize)]
+struct PowerStatus {
+    #[serde(flatten)]
+    power: PowerConfig,
+    /// what the strip draws right now, after limiting
+    estimated_ma: f32,
+}
+
+/// `/strip` is the list of strips, which share one pixel space in that order.
+/// `POST /strip` sets them up again right away, keeping the old ones if that fails
+fn add_strip_routes(
+    tx: &Sender<ConnectionRelevantEvent>,
+    nm: Arc<NeopixelManager<'static>>,
+    store: Arc<Mutex<DStore>>,
+    strip_config: Arc<Mutex<Vec<StripConfig>>>,
+) {
+    let strip_config2 = strip_config.clone();
+    add_new_route!(tx; "/strip", Get, move |req| {
+        let config = strip_config2.lock().unwrap().clone();
+        send_as_json!(req, config)
+    });
+
+    add_new_route!(tx; "/strip", Post, move |mut req| {
+        let config: Vec<StripConfig> = parse_req_or_fail_with_message!(req; "couldn't parse strip config.. {}");
+        if let Err(e) = strip::check_configs(&config) {
+            handler_soft_bail!(req; "invalid strip config: {}", e)
+        }
+        let mut current = strip_config.lock().unwrap();
+        if let Err(e) = nm.replace_strip(|| strip::from_configs(&config)) {
+            match nm.replace_strip(|| strip::from_configs(&current)) {
+                Ok(_) => handler_soft_bail!(req; "couldn't set up the strips, kept the old ones: {}", e),
+                Err(e2) => handler_soft_bail!(req; "couldn't set up the strips: {}, nor the old ones again: {}", e, e2),
+            }
+        }
+        info!("Strips are now {:?}", config);
+        if let Err(e) = store.lock().unwrap().set("strip", &config) {
+            *current = config;
+            handler_soft_bail!(req; "strips changed but could not be stored: {:?}", e)
+        }
+        *current = config;
+        send_as_json!(req, "ok")
+    });
+}
+
+/// presets are addressed by name, e.g. `POST /presets/recall?name=party`
+fn add_preset_routes(
+    tx: &Sender<ConnectionRelevantEvent>,
+    nm: Arc<NeopixelManager<'static>>,
+    store: Arc<Mutex<DStore>>,
+) {
+    let store2 = store.clone();
+    add_new_route!(tx; "/presets", Get, move |req| {
+        let names = presets::list(&store2.lock().unwrap());
+        match names {
+            Ok(names) => send_as_json!(req, names),
+            Err(e) => handler_soft_bail!(req; "{}", e),
+        }
+    });
+
+    let store2 = store.clone();
+    add_new_route!(tx; "/presets/item", Get, move |req| {
+        let uri = req.uri().to_owned();
+        let name = name_or_fail!(req, uri);
+        let preset = presets::load(&store2.lock().unwrap(), &name);
+        match preset {
+            Ok(Some(preset)) => send_as_json!(req, preset),
+            Ok(None) => preset_not_found(req, &name),
+            Err(e) => handler_soft_bail!(req; "{}", e),
+        }
+    });
+
+    // uploads a preset without applying it
+    let store2 = store.clone();
+    add_new_route!(tx; "/presets/item", Put, move |mut req| {
+        let uri = req.uri().to_owned();
+        let name = name_or_fail!(req, uri);
+        let preset: Preset = parse_req_or_fail_with_message!(req; "couldn't parse preset.. {}");
+        let saved = presets::save(&mut store2.lock().unwrap(), &name, &preset);
+        match saved {
+            Ok(()) => send_as_json!(req, "ok"),
+            Err(e) => handler_soft_bail!(req; "{}", e),
+        }
+    });
+
+    let store2 = store.clone();
+    add_new_route!(tx; "/presets/item", Delete, move |req| {
+        let uri = req.uri().to_owned();
+        let name = name_or_fail!(req, uri);
+        let deleted = presets::delete(&mut store2.lock().unwrap(), &name);
+        match deleted {
+            Ok(true) => send_as_json!(req, "ok"),
+            Ok(false) => preset_not_found(req, &name),
+            Err(e) => handler_soft_bail!(req; "{}", e),
+        }
+    });
+
+    // `?name=X&to=Y`
+    let store2 = store.clone();
+    add_new_route!(tx; "/presets/rename", Post, move |req| {
+        let uri = req.uri().to_owned();
+        let name = name_or_fail!(req, uri);
+        let to = match query_param_decoded(&uri, "to") {
+            Some(to) => to,
+            None => handler_soft_bail!(req; "missing or invalid 'to'"),
+        };
+        let renamed = presets::rename(&mut store2.lock().unwrap(), &name, &to);
+        match renamed {
+            Ok(true) => send_as_json!(req, "ok"),
+            Ok(false) => preset_not_found(req, &name),
+            Err(e) => handler_soft_bail!(req; "{}", e),
+        }
+    });
+
+    // saves what is currently playing
+    let (nm2, store2) = (nm.clone(), store.clone());
+    add_new_route!(tx; "/presets/save", Post, move |req| {
+        let uri = req.uri().to_owned();
+        let name = name_or_fail!(req, uri);
+        let preset = Preset {
+            effects: nm2.effects.lock().unwrap().layers().to_vec(),
+            output: nm2.output.lock().unwrap().clone(),
+            calibration: Some(nm2.calibration()),
+        };
+        let saved = presets::save(&mut store2.lock().unwrap(), &name, &preset);
+        match saved {
+            Ok(()) => send_as_json!(req, "ok"),
+            Err(e) => handler_soft_bail!(req; "{}", e),
+        }
+    });
+
+    add_new_route!(tx; "/presets/recall", Post, move |req| {
+        let uri = req.uri().to_owned();
+        let name = name_or_fail!(req, uri);
+        let preset = presets::load(&store.lock().unwrap(), &name);
+        let preset = match preset {
+            Ok(Some(preset)) => preset,
+            Ok(None) => return preset_not_found(req, &name),
+            Err(e) => handler_soft_bail!(req; "{}", e),
+        };
+
+        if let Err(e) = presets::recall(&nm, &store, &name, preset) {
+            handler_soft_bail!(req; "{}", e)
+        }
+        let stack = nm.effects.lock().unwrap();
+        send_as_json!(req, Ack::new(&stack, None))
+    });
+}
+
+/// the first of `segments` that is not in the registry
+fn unknown_segment(nm: &NeopixelManager, segments: impl IntoIterator<Item = u32>) -> Option<u32> {
+    let registry = nm.segments.lock().unwrap();
+    segments.into_iter().find(|s| !registry.contains(*s))
+}
+
+/// `?rev=N` makes a change conditional on the client having seen revision `N`
+fn revision_conflict(uri: &str, stack: &EffectStack) -> bool {
+    match query_param(uri, "rev").and_then(|rev| rev.parse::<u32>().ok()) {
+        Some(rev) => rev != stack.revision(),
+        None => false,
+    }
+}
+
+fn conflict(req: Request<&mut EspHttpConnection>, stack: &EffectStack) -> Result<(), HandlerError> {
+    let body = serde_json::to_vec(&Ack::new(stack, None))?;
+    req.into_status_response(409)?.write_all(&body)?;
+    Ok(())
+}
+
+fn not_found(req: Request<&mut EspHttpConnection>, id: u32) -> Result<(), HandlerError> {
+    req.into_status_response(404)?
+        .write_all(format!("no effect with id {}", id).as_bytes())?;
+    Ok(())
+}
+
+fn segment_not_found(req: Request<&mut EspHttpConnection>, id: u32) -> Result<(), HandlerError> {
+    req.into_status_response(404)?
+        .write_all(format!("no segment with id {}", id).as_bytes())?;
+    Ok(())
+}
+
+fn preset_not_found(req: Request<&mut EspHttpConnection>, name: &str) -> Result<(), HandlerError> {
+    req.into_status_response(404)?
+        .write_all(format!("no preset called '{}'", name).as_bytes())?;
+    Ok(())
+}
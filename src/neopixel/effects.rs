@@ -2,14 +2,21 @@ use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
-use super::strip::color::default::Color;
+use super::{
+    matrix::Grid,
+    pixelmap::{self, Point},
+    strip::color::default::Color,
+};
 
 pub mod hue;
 pub mod invert;
 pub mod solid;
 pub mod strobo;
 pub mod alarm;
+pub mod plasma;
+pub mod spatial;
 
+/// postcard stores the variant index, new effects go at the end
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum EffectConfig {
     Invert(invert::InversionConfig),
@@ -17,13 +24,71 @@ pub enum EffectConfig {
     SolidColor(solid::SolidColorConfig),
     Strobo(strobo::StroboConfig),
     Alarm(alarm::AlarmConfig),
+    Plasma(plasma::PlasmaConfig),
+    SpatialHue(spatial::SpatialHueConfig),
 }
 
+impl EffectConfig {
+    /// the variant names, as they are used in JSON
+    pub const NAMES: &'static [&'static str] =
+        &["Invert", "HueShift", "SolidColor", "Strobo", "Alarm", "Plasma", "SpatialHue"];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EffectConfig::Invert(_) => "Invert",
+            EffectConfig::HueShift(_) => "HueShift",
+            EffectConfig::SolidColor(_) => "SolidColor",
+            EffectConfig::Strobo(_) => "Strobo",
+            EffectConfig::Alarm(_) => "Alarm",
+            EffectConfig::Plasma(_) => "Plasma",
+            EffectConfig::SpatialHue(_) => "SpatialHue",
+        }
+    }
+
+    /// whether this is an `Effect2D`
+    pub fn is_2d(&self) -> bool {
+        matches!(self, EffectConfig::Plasma(_))
+    }
+
+    /// whether this is an `Effect3D`
+    pub fn is_spatial(&self) -> bool {
+        matches!(self, EffectConfig::SpatialHue(_))
+    }
+
+    /// The variant called `name` with its default settings.
+    pub fn default_named(name: &str) -> Option<Self> {
+        Some(match name {
+            "Invert" => EffectConfig::Invert(Default::default()),
+            "HueShift" => EffectConfig::HueShift(Default::default()),
+            "SolidColor" => EffectConfig::SolidColor(Default::default()),
+            "Strobo" => EffectConfig::Strobo(Default::default()),
+            "Alarm" => EffectConfig::Alarm(Default::default()),
+            "Plasma" => EffectConfig::Plasma(Default::default()),
+            "SpatialHue" => EffectConfig::SpatialHue(Default::default()),
+            _ => return None,
+        })
+    }
+}
+
+/// `colors` are the pixels of the layer's segment, see `segment.rs`.
 pub trait Effect {
     type Config: Default;
     fn apply(config: &Self::Config, colors: &mut Vec<Color>, dt: Duration, rt: Option<Duration>) -> anyhow::Result<()>;
 }
 
+/// Effects that work with `(x, y)`. On a matrix `grid` is the matrix with the pixels of the
+/// layer's segment where they are on it, see `matrix.rs`, elsewhere the segment as a single row.
+pub trait Effect2D {
+    type Config: Default;
+    fn apply(config: &Self::Config, grid: &mut Grid, dt: Duration, rt: Option<Duration>) -> anyhow::Result<()>;
+}
+
+/// Effects that work with where the pixels of the layer's segment are, see `pixelmap.rs`.
+pub trait Effect3D {
+    type Config: Default;
+    fn apply(config: &Self::Config, colors: &mut Vec<Color>, positions: &[Point], dt: Duration, rt: Option<Duration>) -> anyhow::Result<()>;
+}
+
 pub fn apply_effects(
     effects: &Vec<EffectConfig>,
     colors: &mut Vec<Color>,
@@ -31,13 +96,69 @@ pub fn apply_effects(
     rt: Option<Duration>,
 ) -> anyhow::Result<()> {
     for effect in effects {
-        match effect {
-            EffectConfig::HueShift(config) => hue::HueShiftEffect::apply(config, colors, dt, rt)?,
-            EffectConfig::SolidColor(config) => solid::SolidColorEffect::apply(config, colors, dt, rt)?,
-            EffectConfig::Strobo(config) => strobo::StroboEffect::apply(config, colors, dt, rt)?,
-            EffectConfig::Invert(config) => invert::InversionEffect::apply(config, colors, dt, rt)?,
-            EffectConfig::Alarm(config) => alarm::AlarmEffect::apply(config, colors, dt, rt)?,
-        }
+        apply_effect(effect, colors, dt, rt)?;
     }
     Ok(())
 }
+
+pub fn apply_effect(
+    effect: &EffectConfig,
+    colors: &mut Vec<Color>,
+    dt: Duration,
+    rt: Option<Duration>,
+) -> anyhow::Result<()> {
+    match effect {
+        EffectConfig::HueShift(config) => hue::HueShiftEffect::apply(config, colors, dt, rt),
+        EffectConfig::SolidColor(config) => solid::SolidColorEffect::apply(config, colors, dt, rt),
+        EffectConfig::Strobo(config) => strobo::StroboEffect::apply(config, colors, dt, rt),
+        EffectConfig::Invert(config) => invert::InversionEffect::apply(config, colors, dt, rt),
+        EffectConfig::Alarm(config) => alarm::AlarmEffect::apply(config, colors, dt, rt),
+        EffectConfig::Plasma(_) => {
+            let mut grid = Grid::default();
+            grid.reset(colors.len() as u16, 1);
+            grid.as_mut_slice().copy_from_slice(colors);
+            apply_effect_2d(effect, &mut grid, dt, rt)?;
+            colors.copy_from_slice(grid.as_mut_slice());
+            Ok(())
+        }
+        EffectConfig::SpatialHue(_) => {
+            let mut positions = Vec::with_capacity(colors.len());
+            pixelmap::line(colors.len(), &mut positions);
+            apply_effect_3d(effect, colors, &positions, dt, rt)
+        }
+    }
+}
+
+/// Like `apply_effect`, other effects ignore `positions`.
+pub fn apply_effect_3d(
+    effect: &EffectConfig,
+    colors: &mut Vec<Color>,
+    positions: &[Point],
+    dt: Duration,
+    rt: Option<Duration>,
+) -> anyhow::Result<()> {
+    match effect {
+        EffectConfig::SpatialHue(config) => {
+            spatial::SpatialHueEffect::apply(config, colors, positions, dt, rt)
+        }
+        effect => apply_effect(effect, colors, dt, rt),
+    }
+}
+
+/// Like `apply_effect`, 1D effects see the cells row by row.
+pub fn apply_effect_2d(
+    effect: &EffectConfig,
+    grid: &mut Grid,
+    dt: Duration,
+    rt: Option<Duration>,
+) -> anyhow::Result<()> {
+    match effect {
+        EffectConfig::Plasma(config) => plasma::PlasmaEffect::apply(config, grid, dt, rt),
+        effect => {
+            let mut colors = grid.as_mut_slice().to_vec();
+            apply_effect(effect, &mut colors, dt, rt)?;
+            grid.as_mut_slice().copy_from_slice(&colors);
+            Ok(())
+        }
+    }
+}
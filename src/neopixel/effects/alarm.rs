@@ -1,4 +1,4 @@
-use std::{ops::Range, time::Duration};
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DurationMilliSeconds};
@@ -15,7 +15,6 @@ pub struct AlarmConfig {
     #[serde_as(as = "DurationMilliSeconds<u64>")]
     pub at_ms_since_1970: Duration,
     pub alarm_type: AlarmType,
-    pub range: Range<u16>,
 }
 
 impl Default for AlarmConfig {
@@ -23,7 +22,6 @@ impl Default for AlarmConfig {
         Self {
             at_ms_since_1970: Duration::from_secs(2680471881),
             alarm_type: AlarmType::Sunrise,
-            range: 0..30,
         }
     }
 }
@@ -76,7 +74,6 @@ impl Effect for AlarmEffect {
                             &super::solid::SolidColorConfig {
                                 color: Color::new(red, green, blue), //when f is default
                                 // color: Color::from_f32(red, green, blue), //when i is default
-                                range: config.range.clone(),
                             },
                             colors,
                             dt,
@@ -91,7 +88,6 @@ impl Effect for AlarmEffect {
                             SolidColorEffect::apply(
                                 &super::solid::SolidColorConfig {
                                     color: Color::red(),
-                                    range: config.range.clone(),
                                 },
                                 colors,
                                 dt,
@@ -99,7 +95,6 @@ impl Effect for AlarmEffect {
                             )?;
                             HueShiftEffect::apply(
                                 &super::hue::HueShiftConfig {
-                                    range: config.range.clone(),
                                     degrees_per_led: 12.0, //full rotataion in 30LEDs (1m)
                                     degrees_per_second: 60.0, //full rotation in 6s (rather fast)
                                 },
@@ -118,7 +113,6 @@ impl Effect for AlarmEffect {
                                     } else {
                                         Color::black()
                                     },
-                                    range: config.range.clone(),
                                 },
                                 colors,
                                 dt,
@@ -132,7 +126,6 @@ impl Effect for AlarmEffect {
                             StroboEffect::apply(
                                 &super::strobo::StroboConfig {
                                     frequency_hz: 2.0,
-                                    range: config.range.clone(),
                                 },
                                 colors,
                                 dt,
@@ -1,4 +1,4 @@
-use std::{ops::Range, time::Duration};
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
@@ -12,7 +12,6 @@ pub struct HueShiftEffect;
 pub struct HueShiftConfig {
     pub degrees_per_second: f32,
     pub degrees_per_led: f32,
-    pub range: Range<u16>,
 }
 
 impl Default for HueShiftConfig {
@@ -20,7 +19,6 @@ impl Default for HueShiftConfig {
         Self {
             degrees_per_second: 5.0,
             degrees_per_led: 0.0,
-            range: 0..30,
         }
     }
 }
@@ -28,8 +26,8 @@ impl Default for HueShiftConfig {
 impl Effect for HueShiftEffect {
     type Config = HueShiftConfig;
     fn apply(config: &Self::Config, colors: &mut Vec<Color>, t: Duration, _ : Option<Duration>) -> anyhow::Result<()> {
-        for i in config.range.clone() {
-            (colors[i as usize]).shift_hue_deg(
+        for (i, color) in colors.iter_mut().enumerate() {
+            color.shift_hue_deg(
                 config.degrees_per_second * t.as_secs_f32() + config.degrees_per_led * i as f32,
             );
         }
@@ -1,4 +1,4 @@
-use std::{ops::Range, time::Duration};
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
@@ -8,23 +8,15 @@ use super::Effect;
 
 pub struct InversionEffect;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct InversionConfig {
-    pub range: Range<u16>,
-}
-
-impl Default for InversionConfig {
-    fn default() -> Self {
-        Self { range: 0..30 }
-    }
-}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InversionConfig {}
 
 #[allow(unused_variables)]
 impl Effect for InversionEffect {
     type Config = InversionConfig;
     fn apply(config: &Self::Config, colors: &mut Vec<Color>, _: Duration, _:Option<Duration>) -> anyhow::Result<()> {
-        for i in config.range.clone() {
-            (colors[i as usize]) = Color::white() - colors[i as usize];
+        for color in colors.iter_mut() {
+            *color = Color::white() - *color;
         }
         Ok(())
     }
@@ -0,0 +1,42 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::neopixel::{matrix::Grid, strip::color::default::Color};
+
+use super::Effect2D;
+
+pub struct PlasmaEffect;
+
+/// Overlapping sine waves across the grid, turned into hues.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlasmaConfig {
+    /// how fast the waves move, radians per second
+    pub speed: f32,
+    /// how tight the waves are, radians per pixel
+    pub scale: f32,
+}
+
+impl Default for PlasmaConfig {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            scale: 0.5,
+        }
+    }
+}
+
+impl Effect2D for PlasmaEffect {
+    type Config = PlasmaConfig;
+    fn apply(config: &Self::Config, grid: &mut Grid, dt: Duration, _: Option<Duration>) -> anyhow::Result<()> {
+        let t = dt.as_secs_f32() * config.speed;
+        for ((x, y), color) in grid.cells_mut() {
+            let (x, y) = (x as f32 * config.scale, y as f32 * config.scale);
+            // each wave is -1 to 1, together they sweep the hue circle once
+            let v = (x + t).sin() + (y - t).sin() + ((x + y) * 0.5 + t).sin();
+            *color = Color::red();
+            color.shift_hue_deg(v * 60.0 + 180.0);
+        }
+        Ok(())
+    }
+}
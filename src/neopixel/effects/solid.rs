@@ -1,4 +1,4 @@
-use std::{ops::Range, time::Duration};
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
@@ -11,14 +11,12 @@ pub struct SolidColorEffect;
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SolidColorConfig {
     pub color: Color,
-    pub range: Range<u16>,
 }
 
 impl Default for SolidColorConfig {
     fn default() -> Self {
         Self {
             color: Color::black(),
-            range: 0..30,
         }
     }
 }
@@ -26,8 +24,8 @@ impl Default for SolidColorConfig {
 impl Effect for SolidColorEffect {
     type Config = SolidColorConfig;
     fn apply(config: &Self::Config, colors: &mut Vec<Color>, _: Duration, _ : Option<Duration>) -> anyhow::Result<()> {
-        for i in config.range.clone() {
-            (colors[i as usize]) = config.color;
+        for color in colors.iter_mut() {
+            *color = config.color;
         }
         Ok(())
     }
@@ -0,0 +1,111 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::neopixel::{pixelmap::Point, strip::color::default::Color};
+
+use super::Effect3D;
+
+/// A value for every point in space, positions are in the -1 to 1 space of `pixelmap.rs`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Field {
+    /// distance from the plane through the center, along `normal`
+    Plane { normal: Point },
+    /// distance from `center`
+    Sphere { center: Point },
+    /// smooth noise from 0 to 1, `scale` blobs per unit, drifting `speed` units per second
+    Noise { scale: f32, speed: f32 },
+}
+
+impl Field {
+    pub fn sample(&self, p: Point, t: f32) -> f32 {
+        match self {
+            Field::Plane { normal } => {
+                let len = dot(*normal, *normal).sqrt();
+                match len > 0.0 {
+                    true => dot(p, *normal) / len,
+                    false => 0.0,
+                }
+            }
+            Field::Sphere { center } => {
+                let d = [p[0] - center[0], p[1] - center[1], p[2] - center[2]];
+                dot(d, d).sqrt()
+            }
+            Field::Noise { scale, speed } => value_noise([
+                p[0] * scale,
+                p[1] * scale,
+                p[2] * scale + t * speed,
+            ]),
+        }
+    }
+}
+
+fn dot(a: Point, b: Point) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+/// random values on the integer lattice, smoothly interpolated in between
+fn value_noise(p: Point) -> f32 {
+    let cell = p.map(|v| v.floor());
+    let [fx, fy, fz] = [0, 1, 2].map(|a| {
+        let f = p[a] - cell[a];
+        f * f * (3.0 - 2.0 * f)
+    });
+    let [x, y, z] = cell.map(|v| v as i32);
+    let lerp = |a: f32, b: f32, f: f32| a + (b - a) * f;
+    let corner = |dx, dy, dz| lattice(x + dx, y + dy, z + dz);
+    let plane = |dz| {
+        lerp(
+            lerp(corner(0, 0, dz), corner(1, 0, dz), fx),
+            lerp(corner(0, 1, dz), corner(1, 1, dz), fx),
+            fy,
+        )
+    };
+    lerp(plane(0), plane(1), fz)
+}
+
+fn lattice(x: i32, y: i32, z: i32) -> f32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1_e995);
+    h ^= h >> 15;
+    (h & 0xffff) as f32 / 65535.0
+}
+
+pub struct SpatialHueEffect;
+
+/// `HueShiftConfig` for shapes: the hue follows `field` instead of the led index.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpatialHueConfig {
+    pub field: Field,
+    pub degrees_per_second: f32,
+    /// hue change per unit of `field`, the whole installation is 2 units across
+    pub degrees_per_unit: f32,
+}
+
+impl Default for SpatialHueConfig {
+    fn default() -> Self {
+        Self {
+            field: Field::Plane {
+                normal: [0.0, 1.0, 0.0],
+            },
+            degrees_per_second: 30.0,
+            degrees_per_unit: 180.0,
+        }
+    }
+}
+
+impl Effect3D for SpatialHueEffect {
+    type Config = SpatialHueConfig;
+    fn apply(config: &Self::Config, colors: &mut Vec<Color>, positions: &[Point], dt: Duration, _: Option<Duration>) -> anyhow::Result<()> {
+        let t = dt.as_secs_f32();
+        for (color, p) in colors.iter_mut().zip(positions) {
+            color.shift_hue_deg(
+                config.degrees_per_second * t + config.degrees_per_unit * config.field.sample(*p, t),
+            );
+        }
+        Ok(())
+    }
+}
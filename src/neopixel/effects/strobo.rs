@@ -1,4 +1,4 @@
-use std::{ops::Range, time::Duration};
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
@@ -14,14 +14,12 @@ pub struct StroboEffect;
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StroboConfig {
     pub frequency_hz: f32,
-    pub range: Range<u16>,
 }
 
 impl Default for StroboConfig {
     fn default() -> Self {
         Self {
             frequency_hz: 2.0,
-            range: 0..30,
         }
     }
 }
@@ -35,7 +33,6 @@ impl Effect for StroboEffect {
                 SolidColorEffect::apply(
                     &SolidColorConfig {
                         color: Color::white(),
-                        range: config.range.clone(),
                     },
                     colors,
                     Duration::from_secs_f32(t),
@@ -46,7 +43,6 @@ impl Effect for StroboEffect {
                 SolidColorEffect::apply(
                     &SolidColorConfig {
                         color: Color::black(),
-                        range: config.range.clone(),
                     },
                     colors,
                     Duration::from_secs_f32(t),
@@ -0,0 +1,489 @@
+//! Golden-frame regression harness.
+//! Every case renders a fixed `dt`/`rt` timeline through `layer::compose`
+//! and compares the frames against a stored `.frames` file in `golden/`.
+//!
+//! File layout (little endian): `b"EGF1"`, led count `u16`, frame count `u32`,
+//! then `frame count * led count` pixels as 8-bit `r, g, b`.
+//!
+//! The `golden_frames` test checks every case. After a change that is meant to alter the output,
+//! or for a new case, rewrite the files and review the diff:
+//! `UPDATE_GOLDEN=1 cargo test --no-default-features --target x86_64-unknown-linux-gnu golden`
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+
+use super::{
+    effects::{
+        alarm, hue, invert, plasma, solid,
+        spatial::{self, Field},
+        strobo, EffectConfig,
+    },
+    layer::{self, BlendMode, Layer},
+    matrix::{Matrix, MatrixConfig, Panels, Rotation},
+    pixelmap::{PixelMap, Point},
+    segment::{Segment, Segments, WHOLE_STRIP},
+    strip::{color::default::Color, sim::channel_u8},
+};
+
+const MAGIC: &[u8; 4] = b"EGF1";
+/// float rounding differs between xtensa and the host, so allow one LSB of slack
+const TOLERANCE: u8 = 1;
+
+/// the moment every alarm case is scheduled for
+const ALARM_AT: Duration = Duration::from_secs(2_000_000_000);
+
+pub struct Timeline {
+    pub frame_count: u32,
+    pub mspf: u32,
+    /// wall clock of the first frame, `None` behaves like a device without time sync
+    pub rt_start: Option<Duration>,
+}
+
+pub struct GoldenCase {
+    pub name: &'static str,
+    pub led_count: u16,
+    pub segments: Vec<Segment>,
+    pub matrix: Option<MatrixConfig>,
+    pub pixel_map: Option<Vec<Point>>,
+    pub layers: Vec<Layer>,
+    pub timeline: Timeline,
+}
+
+#[derive(Debug)]
+pub struct Mismatch {
+    pub case: &'static str,
+    pub frame: u32,
+    pub led: u16,
+    pub expected: [u8; 3],
+    pub actual: [u8; 3],
+}
+
+/// Renders the timeline of `case` starting from a black strip.
+pub fn render(case: &GoldenCase) -> Result<Vec<Vec<Color>>> {
+    let segments = Segments::new(case.segments.clone());
+    let matrix = case.matrix.clone().map(Matrix::new);
+    let pixel_map = case.pixel_map.clone().map(PixelMap::new);
+    let mut colors = vec![Color::black(); case.led_count as usize];
+    let mut frames = Vec::with_capacity(case.timeline.frame_count as usize);
+    for frame in 0..case.timeline.frame_count {
+        let dt = Duration::from_millis(frame as u64 * case.timeline.mspf as u64);
+        let rt = case.timeline.rt_start.map(|rt| rt + dt);
+        layer::compose(
+            &case.layers,
+            &segments,
+            matrix.as_ref(),
+            pixel_map.as_ref(),
+            &mut colors,
+            dt,
+            rt,
+        )?;
+        frames.push(colors.clone());
+    }
+    Ok(frames)
+}
+
+pub fn encode(frames: &[Vec<Color>]) -> Vec<u8> {
+    let led_count = frames.first().map_or(0, |f| f.len());
+    let mut out = Vec::with_capacity(10 + frames.len() * led_count * 3);
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&(led_count as u16).to_le_bytes());
+    out.extend_from_slice(&(frames.len() as u32).to_le_bytes());
+    for frame in frames {
+        for c in frame {
+            out.extend_from_slice(&[channel_u8(c.red), channel_u8(c.green), channel_u8(c.blue)]);
+        }
+    }
+    out
+}
+
+/// returns `(led_count, frames)` where every frame is `led_count` 8-bit rgb triples
+pub fn decode(bytes: &[u8]) -> Result<(u16, Vec<Vec<[u8; 3]>>)> {
+    if bytes.len() < 10 || &bytes[..4] != MAGIC {
+        bail!("not a golden frame file");
+    }
+    let led_count = u16::from_le_bytes([bytes[4], bytes[5]]);
+    let frame_count = u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]);
+    let body = &bytes[10..];
+    if body.len() != frame_count as usize * led_count as usize * 3 {
+        bail!(
+            "golden file holds {} bytes of pixels, expected {} frames of {} leds",
+            body.len(),
+            frame_count,
+            led_count
+        );
+    }
+    let frames = body
+        .chunks(led_count as usize * 3)
+        .map(|frame| frame.chunks(3).map(|px| [px[0], px[1], px[2]]).collect())
+        .collect();
+    Ok((led_count, frames))
+}
+
+/// Compares `case` against its golden file in `dir`.
+/// If `update` is set the current output is written instead.
+pub fn check(case: &GoldenCase, dir: &Path, update: bool) -> Result<Vec<Mismatch>> {
+    let path = golden_path(dir, case);
+    let actual = encode(&render(case)?);
+    if update {
+        fs::create_dir_all(dir)?;
+        fs::write(&path, &actual)?;
+        return Ok(Vec::new());
+    }
+    if !path.exists() {
+        bail!(
+            "{}: there is no {}, see the module docs",
+            case.name,
+            path.display()
+        );
+    }
+
+    let (expected_leds, expected) = decode(&fs::read(&path)?)?;
+    let (_, actual) = decode(&actual)?;
+    if expected_leds != case.led_count || expected.len() != actual.len() {
+        bail!(
+            "{}: golden file has {} frames of {} leds, rendered {} frames of {} leds",
+            case.name,
+            expected.len(),
+            expected_leds,
+            actual.len(),
+            case.led_count
+        );
+    }
+
+    let mut mismatches = Vec::new();
+    for (frame, (e, a)) in expected.iter().zip(actual.iter()).enumerate() {
+        for (led, (e, a)) in e.iter().zip(a.iter()).enumerate() {
+            if e.iter()
+                .zip(a.iter())
+                .any(|(e, a)| e.abs_diff(*a) > TOLERANCE)
+            {
+                mismatches.push(Mismatch {
+                    case: case.name,
+                    frame: frame as u32,
+                    led: led as u16,
+                    expected: *e,
+                    actual: *a,
+                });
+            }
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Runs every case in `cases()` and fails with a summary if any frame differs.
+pub fn check_all(dir: &Path, update: bool) -> Result<()> {
+    let mut failed = Vec::new();
+    for case in cases() {
+        let mismatches = check(&case, dir, update)?;
+        if let Some(first) = mismatches.first() {
+            failed.push(format!(
+                "{}: {} pixels differ, first at frame {} led {} (expected {:?}, got {:?})",
+                case.name,
+                mismatches.len(),
+                first.frame,
+                first.led,
+                first.expected,
+                first.actual
+            ));
+        }
+    }
+    if !failed.is_empty() {
+        bail!("golden frames differ:\n{}", failed.join("\n"));
+    }
+    Ok(())
+}
+
+fn plain(effects: Vec<EffectConfig>) -> Vec<Layer> {
+    effects.into_iter().map(Layer::from).collect()
+}
+
+/// segments `1..` over `ranges`
+fn segments(ranges: &[std::ops::Range<u16>]) -> Vec<Segment> {
+    ranges
+        .iter()
+        .enumerate()
+        .map(|(i, range)| Segment {
+            id: i as u32 + 1,
+            ..Segment::plain(format!("{}", i + 1), range.clone())
+        })
+        .collect()
+}
+
+fn on(segment: u32, effect: EffectConfig) -> Layer {
+    Layer {
+        segment,
+        ..Layer::new(effect)
+    }
+}
+
+/// `count` leds in a spiral around a cone, the bottom ones first
+fn tree(count: usize) -> Vec<Point> {
+    (0..count)
+        .map(|led| {
+            let up = led as f32 / count as f32;
+            let angle = up * 5.0 * std::f32::consts::TAU;
+            let radius = 1.0 - up;
+            [radius * angle.cos(), up * 2.5, radius * angle.sin()]
+        })
+        .collect()
+}
+
+fn golden_path(dir: &Path, case: &GoldenCase) -> PathBuf {
+    dir.join(format!("{}.frames", case.name))
+}
+
+/// One case per `EffectConfig` variant (and per `AlarmType`), plus the stacks the app builds most.
+// `segments(&[5..25])` is one segment over leds 5 to 24, not a list of them
+#[allow(clippy::single_range_in_vec_init)]
+pub fn cases() -> Vec<GoldenCase> {
+    let steady = || Timeline {
+        frame_count: 60,
+        mspf: 50,
+        rt_start: None,
+    };
+    let around_alarm = || Timeline {
+        frame_count: 100,
+        mspf: 500,
+        rt_start: Some(ALARM_AT - Duration::from_secs(35)),
+    };
+    let alarm = |alarm_type| {
+        EffectConfig::Alarm(alarm::AlarmConfig {
+            at_ms_since_1970: ALARM_AT,
+            alarm_type,
+        })
+    };
+    let rainbow = EffectConfig::HueShift(hue::HueShiftConfig {
+        degrees_per_second: 90.0,
+        degrees_per_led: 12.0,
+    });
+    let red = EffectConfig::SolidColor(solid::SolidColorConfig {
+        color: Color::red(),
+    });
+    let spatial_hue = |field| {
+        EffectConfig::SpatialHue(spatial::SpatialHueConfig {
+            field,
+            degrees_per_second: 45.0,
+            degrees_per_unit: 120.0,
+        })
+    };
+    let plasma = EffectConfig::Plasma(plasma::PlasmaConfig {
+        speed: 2.0,
+        scale: 0.7,
+    });
+
+    vec![
+        GoldenCase {
+            name: "solid",
+            led_count: 30,
+            segments: segments(&[5..25]),
+            matrix: None,
+            pixel_map: None,
+            layers: vec![on(
+                1,
+                EffectConfig::SolidColor(solid::SolidColorConfig {
+                    color: Color::orange(),
+                }),
+            )],
+            timeline: steady(),
+        },
+        GoldenCase {
+            name: "invert",
+            led_count: 30,
+            segments: segments(&[10..20]),
+            matrix: None,
+            pixel_map: None,
+            layers: vec![
+                red.clone().into(),
+                on(1, EffectConfig::Invert(invert::InversionConfig {})),
+            ],
+            timeline: steady(),
+        },
+        GoldenCase {
+            name: "hue",
+            led_count: 30,
+            segments: Vec::new(),
+            matrix: None,
+            pixel_map: None,
+            layers: plain(vec![red.clone(), rainbow.clone()]),
+            timeline: steady(),
+        },
+        GoldenCase {
+            name: "strobo",
+            led_count: 30,
+            segments: Vec::new(),
+            matrix: None,
+            pixel_map: None,
+            layers: plain(vec![
+                red.clone(),
+                EffectConfig::Strobo(strobo::StroboConfig::default()),
+            ]),
+            timeline: steady(),
+        },
+        GoldenCase {
+            name: "strobo_over_hue",
+            led_count: 30,
+            segments: Vec::new(),
+            matrix: None,
+            pixel_map: None,
+            layers: vec![
+                red.clone().into(),
+                rainbow.clone().into(),
+                Layer {
+                    id: 0,
+                    segment: WHOLE_STRIP,
+                    effect: EffectConfig::Strobo(strobo::StroboConfig::default()),
+                    opacity: 0.3,
+                    blend: BlendMode::Normal,
+                    enabled: true,
+                },
+            ],
+            timeline: steady(),
+        },
+        GoldenCase {
+            name: "blend_modes",
+            led_count: 30,
+            segments: segments(&[0..10, 10..20, 20..30]),
+            matrix: None,
+            pixel_map: None,
+            layers: vec![
+                red.clone().into(),
+                Layer {
+                    id: 0,
+                    segment: 1,
+                    effect: EffectConfig::SolidColor(solid::SolidColorConfig {
+                        color: Color::blue(),
+                    }),
+                    opacity: 0.5,
+                    blend: BlendMode::Add,
+                    enabled: true,
+                },
+                Layer {
+                    id: 0,
+                    segment: 2,
+                    effect: EffectConfig::SolidColor(solid::SolidColorConfig {
+                        color: Color::gray(),
+                    }),
+                    opacity: 1.0,
+                    blend: BlendMode::Multiply,
+                    enabled: true,
+                },
+                Layer {
+                    id: 0,
+                    segment: 3,
+                    effect: EffectConfig::SolidColor(solid::SolidColorConfig {
+                        color: Color::green(),
+                    }),
+                    opacity: 1.0,
+                    blend: BlendMode::Screen,
+                    enabled: true,
+                },
+            ],
+            timeline: steady(),
+        },
+        GoldenCase {
+            name: "alarm_sunrise",
+            led_count: 30,
+            segments: Vec::new(),
+            matrix: None,
+            pixel_map: None,
+            layers: plain(vec![alarm(alarm::AlarmType::Sunrise)]),
+            timeline: around_alarm(),
+        },
+        GoldenCase {
+            name: "alarm_silvester",
+            led_count: 30,
+            segments: Vec::new(),
+            matrix: None,
+            pixel_map: None,
+            layers: plain(vec![alarm(alarm::AlarmType::Silvester)]),
+            timeline: around_alarm(),
+        },
+        GoldenCase {
+            name: "alarm_strobo",
+            led_count: 30,
+            segments: Vec::new(),
+            matrix: None,
+            pixel_map: None,
+            layers: plain(vec![alarm(alarm::AlarmType::Strobo)]),
+            timeline: around_alarm(),
+        },
+        GoldenCase {
+            name: "plasma",
+            led_count: 30,
+            segments: Vec::new(),
+            matrix: None,
+            pixel_map: None,
+            layers: plain(vec![plasma.clone()]),
+            timeline: steady(),
+        },
+        GoldenCase {
+            name: "plasma_matrix",
+            led_count: 40,
+            segments: segments(&[0..20]),
+            matrix: Some(MatrixConfig {
+                start: 4,
+                width: 4,
+                height: 4,
+                serpentine: true,
+                panels: Panels {
+                    columns: 2,
+                    rows: 1,
+                    serpentine: false,
+                },
+                rotation: Rotation::Quarter,
+            }),
+            pixel_map: None,
+            layers: vec![red.clone().into(), on(1, plasma)],
+            timeline: steady(),
+        },
+        GoldenCase {
+            name: "spatial_hue",
+            led_count: 30,
+            segments: Vec::new(),
+            matrix: None,
+            pixel_map: None,
+            layers: plain(vec![
+                red.clone(),
+                spatial_hue(Field::Plane {
+                    normal: [1.0, 0.0, 0.0],
+                }),
+            ]),
+            timeline: steady(),
+        },
+        GoldenCase {
+            name: "spatial_tree",
+            led_count: 30,
+            segments: Vec::new(),
+            matrix: None,
+            pixel_map: Some(tree(30)),
+            layers: plain(vec![
+                red.clone(),
+                spatial_hue(Field::Sphere {
+                    center: [0.0, -1.0, 0.0],
+                }),
+                spatial_hue(Field::Noise {
+                    scale: 2.0,
+                    speed: 0.5,
+                }),
+            ]),
+            timeline: steady(),
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, path::Path};
+
+    #[test]
+    fn golden_frames() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("golden");
+        let update = env::var_os("UPDATE_GOLDEN").is_some();
+        super::check_all(&dir, update).unwrap();
+    }
+}
@@ -0,0 +1,210 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    effects::{self, EffectConfig},
+    matrix::{Grid, Matrix},
+    pixelmap::{self, PixelMap},
+    segment::{Segments, WHOLE_STRIP},
+    strip::color::default::Color,
+};
+
+/// How a layer is combined with everything below it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum BlendMode {
+    /// the layer replaces what is below
+    #[default]
+    Normal,
+    Add,
+    Multiply,
+    Screen,
+    Max,
+    Min,
+    /// black is fully transparent, the brightest channel of a pixel is its alpha
+    AlphaOver,
+}
+
+/// One entry of the effect stack: an effect plus how it is composited.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Layer {
+    /// assigned by the `EffectStack`, 0 means "not assigned yet"
+    #[serde(default)]
+    pub id: u32,
+    /// where the effect is drawn, the whole strip if not given
+    #[serde(default)]
+    pub segment: u32,
+    pub effect: EffectConfig,
+    /// 0 = invisible, 1 = fully blended
+    #[serde(default = "full_opacity")]
+    pub opacity: f32,
+    #[serde(default)]
+    pub blend: BlendMode,
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+}
+
+fn full_opacity() -> f32 {
+    1.0
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl Layer {
+    pub fn new(effect: EffectConfig) -> Self {
+        Self {
+            id: 0,
+            segment: WHOLE_STRIP,
+            effect,
+            opacity: full_opacity(),
+            blend: BlendMode::Normal,
+            enabled: true,
+        }
+    }
+}
+
+impl From<EffectConfig> for Layer {
+    fn from(effect: EffectConfig) -> Self {
+        Self::new(effect)
+    }
+}
+
+/// What `POST /effects` takes: layers, or bare effects like clients sent before there were
+/// layers, which become layers on the whole strip.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum LayerOrEffect {
+    Layer(Layer),
+    Effect(EffectConfig),
+}
+
+impl From<LayerOrEffect> for Layer {
+    fn from(entry: LayerOrEffect) -> Self {
+        match entry {
+            LayerOrEffect::Layer(layer) => layer,
+            LayerOrEffect::Effect(effect) => Self::new(effect),
+        }
+    }
+}
+
+/// Renders every layer on top of `colors`, bottom to top.
+/// Each effect sees the composite below it on the pixels of its segment (so hue-shift and invert
+/// keep working), then every pixel is blended back onto the leds that show it.
+/// Layers on a segment that does not exist (anymore) are skipped.
+/// With a `matrix`, 2D effects draw on it and leave the pixels that are not on it alone.
+/// Spatial effects get the positions from `pixel_map`, see `pixelmap::positions_of`.
+pub fn compose(
+    layers: &[Layer],
+    segments: &Segments,
+    matrix: Option<&Matrix>,
+    pixel_map: Option<&PixelMap>,
+    colors: &mut [Color],
+    dt: Duration,
+    rt: Option<Duration>,
+) -> anyhow::Result<()> {
+    let led_count = colors.len();
+    let mut layer_colors = Vec::with_capacity(led_count);
+    let mut grid = Grid::default();
+    let mut cells = Vec::new();
+    let mut positions = Vec::new();
+    for layer in layers {
+        if !layer.enabled || layer.opacity <= 0.0 {
+            continue;
+        }
+        let segment = match segments.get(layer.segment, led_count as u16) {
+            Some(segment) => segment,
+            None => continue,
+        };
+        // the segment may reach past the strip, e.g. after it was shortened
+        layer_colors.clear();
+        for pixel in 0..segment.pixel_count() {
+            layer_colors.push(match segment.leds(pixel).next() {
+                Some(led) if led < led_count => colors[led],
+                _ => Color::black(),
+            });
+        }
+        match matrix {
+            Some(matrix) if layer.effect.is_2d() => {
+                matrix.cells_of(&segment, &mut cells);
+                grid.reset(matrix.width(), matrix.height());
+                for (color, cell) in layer_colors.iter().zip(&cells) {
+                    if let Some(cell) = cell {
+                        grid.as_mut_slice()[*cell] = *color;
+                    }
+                }
+                effects::apply_effect_2d(&layer.effect, &mut grid, dt, rt)?;
+                for (color, cell) in layer_colors.iter_mut().zip(&cells) {
+                    if let Some(cell) = cell {
+                        *color = grid.as_mut_slice()[*cell];
+                    }
+                }
+            }
+            _ if layer.effect.is_spatial() => {
+                pixelmap::positions_of(&segment, pixel_map, matrix, led_count, &mut positions);
+                effects::apply_effect_3d(&layer.effect, &mut layer_colors, &positions, dt, rt)?;
+            }
+            _ => effects::apply_effect(&layer.effect, &mut layer_colors, dt, rt)?,
+        }
+
+        for (pixel, color) in layer_colors.iter().enumerate() {
+            for led in segment.leds(pixel).filter(|led| *led < led_count) {
+                colors[led] = blend(colors[led], *color, layer.blend, layer.opacity);
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn blend(below: Color, layer: Color, mode: BlendMode, opacity: f32) -> Color {
+    let alpha = layer.red.max(layer.green).max(layer.blue).clamp(0.0, 1.0);
+    let channel = |a: f32, b: f32| -> f32 {
+        let blended = match mode {
+            BlendMode::Normal => b,
+            BlendMode::Add => a + b,
+            BlendMode::Multiply => a * b,
+            BlendMode::Screen => 1.0 - (1.0 - a) * (1.0 - b),
+            BlendMode::Max => a.max(b),
+            BlendMode::Min => a.min(b),
+            BlendMode::AlphaOver => b + a * (1.0 - alpha),
+        }
+        .clamp(0.0, 1.0);
+        if opacity >= 1.0 {
+            blended
+        } else {
+            a + (blended - a) * opacity
+        }
+    };
+    Color::new(
+        channel(below.red, layer.red),
+        channel(below.green, layer.green),
+        channel(below.blue, layer.blue),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bare_effects_become_layers_on_the_whole_strip() {
+        let json = r#"[
+            {"SolidColor": {"color": {"red": 1.0, "green": 0.0, "blue": 0.0}}},
+            {"segment": 2, "effect": {"Invert": {}}, "opacity": 0.5, "blend": "Add"}
+        ]"#;
+        let entries: Vec<LayerOrEffect> = serde_json::from_str(json).unwrap();
+        let layers: Vec<Layer> = entries.into_iter().map(Layer::from).collect();
+
+        assert!(matches!(layers[0].effect, EffectConfig::SolidColor(_)));
+        assert_eq!(layers[0].segment, WHOLE_STRIP);
+        assert_eq!(layers[0].opacity, 1.0);
+        assert_eq!(layers[0].blend, BlendMode::Normal);
+        assert!(layers[0].enabled);
+
+        assert!(matches!(layers[1].effect, EffectConfig::Invert(_)));
+        assert_eq!(layers[1].segment, 2);
+        assert_eq!(layers[1].opacity, 0.5);
+        assert_eq!(layers[1].blend, BlendMode::Add);
+    }
+}
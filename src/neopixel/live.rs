@@ -0,0 +1,150 @@
+//! `/live` WebSocket for editors: a preview of what the strip shows and edits without HTTP round trips.
+//!
+//! Client to server, JSON text frames:
+//! - `{"type":"preview","fps":10,"max_pixels":100}` starts pushing preview frames, `fps` 0 stops,
+//!   anything else is kept between 0.1 and 30
+//! - `{"type":"patch","id":3,"layer":{"opacity":0.5},"rev":7}` merges `layer` into the layer with
+//!   that id (JSON merge patch, `rev` is optional). It is shown right away but not stored, so
+//!   sliders can send one per step. Switching the effect type needs the old one nulled:
+//!   `{"effect":{"HueShift":null,"Strobo":{...}}}`
+//! - `{"type":"commit"}` stores the stack, which also happens when the last editor disconnects
+//!
+//! Server to client: a `Reply` as text for every patch/commit, and binary preview frames:
+//! `u16` strip length, `u16` pixel count (little endian), then RGB8 per pixel,
+//! each the average of its share of the strip.
+
+use serde_json::Value;
+
+use super::{
+    layer::Layer,
+    strip::{color::default::Color, sim::channel_u8},
+};
+
+#[cfg(feature = "esp")]
+mod socket;
+#[cfg(feature = "esp")]
+pub use socket::add_routes;
+
+/// `current` with the JSON merge patch `patch` applied, the id can not be changed.
+pub fn merged(current: &Layer, patch: &Value) -> Result<Layer, String> {
+    let mut value = serde_json::to_value(current).map_err(|e| e.to_string())?;
+    merge(&mut value, patch);
+    let mut layer: Layer = serde_json::from_value(value).map_err(|e| e.to_string())?;
+    layer.id = current.id;
+    Ok(layer)
+}
+
+/// RFC 7396
+pub fn merge(target: &mut Value, patch: &Value) {
+    let patch = match patch {
+        Value::Object(patch) => patch,
+        _ => {
+            *target = patch.clone();
+            return;
+        }
+    };
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+    if let Value::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(key);
+            } else {
+                merge(target.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+/// Averages `frame` down to at most `max_pixels` pixels, in the preview message format.
+pub fn encode_preview(frame: &[Color], max_pixels: u16, out: &mut Vec<u8>) {
+    let count = frame.len().min(max_pixels as usize);
+    out.clear();
+    out.extend_from_slice(&(frame.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(count as u16).to_le_bytes());
+    for i in 0..count {
+        let bucket = &frame[i * frame.len() / count..(i + 1) * frame.len() / count];
+        let n = bucket.len() as f32;
+        let sum = bucket.iter().fold(Color::black(), |sum, c| {
+            Color::new(sum.red + c.red, sum.green + c.green, sum.blue + c.blue)
+        });
+        out.push(channel_u8(sum.red / n));
+        out.push(channel_u8(sum.green / n));
+        out.push(channel_u8(sum.blue / n));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::neopixel::effects::EffectConfig;
+
+    fn merged_into(mut target: Value, patch: Value) -> Value {
+        merge(&mut target, &patch);
+        target
+    }
+
+    #[test]
+    fn merge_follows_rfc_7396() {
+        let target = json!({"a": 1, "b": {"c": 2, "d": 3}, "e": [1, 2]});
+        assert_eq!(
+            merged_into(target.clone(), json!({"a": null, "b": {"c": null, "f": 4}})),
+            json!({"b": {"d": 3, "f": 4}, "e": [1, 2]})
+        );
+        // arrays and scalars are replaced as a whole
+        assert_eq!(
+            merged_into(target.clone(), json!({"e": [3], "b": 5})),
+            json!({"a": 1, "b": 5, "e": [3]})
+        );
+        assert_eq!(
+            merged_into(json!({"a": 1}), json!({"a": {"b": null, "c": 1}})),
+            json!({"a": {"c": 1}})
+        );
+        assert_eq!(merged_into(target, json!(null)), json!(null));
+    }
+
+    #[test]
+    fn merged_keeps_the_id_and_switches_effects() {
+        let current = Layer {
+            id: 3,
+            ..Layer::new(EffectConfig::default_named("HueShift").unwrap())
+        };
+        let switched = merged(
+            &current,
+            &json!({"effect": {"HueShift": null, "Strobo": {"frequency_hz": 5.0}}}),
+        )
+        .unwrap();
+        assert_eq!(switched.effect.name(), "Strobo");
+        // without nulling the old one there would be two
+        let both = json!({"effect": {"Strobo": {"frequency_hz": 5.0}}});
+        assert!(merged(&current, &both).is_err());
+
+        let patched = merged(&current, &json!({"id": 9, "opacity": 0.5})).unwrap();
+        assert_eq!((patched.id, patched.opacity), (3, 0.5));
+        assert_eq!(patched.effect.name(), "HueShift");
+        assert!(merged(&current, &json!({"opacity": "full"})).is_err());
+    }
+
+    #[test]
+    fn previews_average_down_to_max_pixels() {
+        let frame = [
+            Color::new(1.0, 0.0, 0.0),
+            Color::new(0.0, 0.0, 0.0),
+            Color::new(0.0, 1.0, 0.0),
+            Color::new(0.0, 1.0, 0.0),
+            Color::new(0.0, 0.0, 1.0),
+        ];
+        let mut out = Vec::new();
+        encode_preview(&frame, 2, &mut out);
+        assert_eq!(out, [5, 0, 2, 0, 127, 0, 0, 0, 170, 85]);
+
+        // never more pixels than the strip has
+        encode_preview(&frame[..1], 100, &mut out);
+        assert_eq!(out, [1, 0, 1, 0, 255, 0, 0]);
+        encode_preview(&[], 100, &mut out);
+        assert_eq!(out, [0, 0, 0, 0]);
+    }
+}
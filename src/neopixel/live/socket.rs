@@ -0,0 +1,252 @@
+//! The `/live` WebSocket itself, one session per connected editor.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use embedded_svc::ws::{FrameType, Sender as _};
+use esp_idf_svc::http::server::ws::{EspHttpWsConnection, EspHttpWsDetachedSender};
+use esp_idf_sys::EspError;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{add_new_ws_route, connection::ConnectionRelevantEvent, store::DStore};
+
+use super::{
+    super::{stack::LayerPatch, NeopixelManager},
+    encode_preview, merged,
+};
+
+const MAX_MESSAGE_LEN: usize = 2048;
+const MAX_FPS: f32 = 30.0;
+/// slower previews get this rate, the interval of a tiny `fps` would overflow `Duration`
+const MIN_FPS: f32 = 0.1;
+const DEFAULT_MAX_PIXELS: u16 = 128;
+/// how often the preview thread checks whether a client is due for a frame
+const TICK: Duration = Duration::from_millis(10);
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    Preview {
+        fps: f32,
+        #[serde(default = "default_max_pixels")]
+        max_pixels: u16,
+    },
+    Patch {
+        id: u32,
+        layer: Value,
+        rev: Option<u32>,
+    },
+    Commit,
+}
+
+fn default_max_pixels() -> u16 {
+    DEFAULT_MAX_PIXELS
+}
+
+#[derive(Serialize)]
+struct Reply {
+    ok: bool,
+    revision: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+struct Session {
+    sender: EspHttpWsDetachedSender,
+    /// `None` while the client does not want a preview
+    interval: Option<Duration>,
+    max_pixels: u16,
+    next_frame: Instant,
+}
+
+type Sessions = Arc<Mutex<HashMap<i32, Session>>>;
+
+pub fn add_routes(
+    tx: &Sender<ConnectionRelevantEvent>,
+    nm: Arc<NeopixelManager<'static>>,
+    store: Arc<Mutex<DStore>>,
+) {
+    let sessions: Sessions = Arc::new(Mutex::new(HashMap::new()));
+    // there are changes that were shown but not stored yet
+    let dirty = Arc::new(AtomicBool::new(false));
+
+    let (nm2, sessions2) = (nm.clone(), sessions.clone());
+    if let Err(e) = thread::Builder::new()
+        .stack_size(6 * 1024)
+        .spawn(move || push_previews(nm2, sessions2))
+    {
+        warn!("Failed to start the live preview thread: {}", e);
+    }
+
+    add_new_ws_route!(tx; "/live", move |ws| handle(ws, &nm, &store, &sessions, &dirty));
+}
+
+fn handle(
+    ws: &mut EspHttpWsConnection,
+    nm: &NeopixelManager,
+    store: &Mutex<DStore>,
+    sessions: &Mutex<HashMap<i32, Session>>,
+    dirty: &AtomicBool,
+) -> Result<(), EspError> {
+    if ws.is_new() {
+        let session = Session {
+            sender: ws.create_detached_sender()?,
+            interval: None,
+            max_pixels: DEFAULT_MAX_PIXELS,
+            next_frame: Instant::now(),
+        };
+        sessions.lock().unwrap().insert(ws.session(), session);
+        info!("Live editor {} connected", ws.session());
+        return Ok(());
+    }
+    if ws.is_closed() {
+        let mut sessions = sessions.lock().unwrap();
+        sessions.remove(&ws.session());
+        info!("Live editor {} left", ws.session());
+        if sessions.is_empty() && dirty.swap(false, Ordering::SeqCst) {
+            if let Err(e) = persist(nm, store) {
+                warn!("{}", e);
+            }
+        }
+        return Ok(());
+    }
+
+    let (frame_type, len) = ws.recv(&mut [])?;
+    if len > MAX_MESSAGE_LEN {
+        ws.send(FrameType::Text(false), b"message too big")?;
+        ws.send(FrameType::Close, &[])?;
+        return Ok(());
+    }
+    let mut buf = vec![0u8; len];
+    ws.recv(&mut buf)?;
+    if !matches!(frame_type, FrameType::Text(_)) {
+        return Ok(());
+    }
+    // esp-idf counts the terminating nul
+    while buf.last() == Some(&0) {
+        buf.pop();
+    }
+
+    let message = match serde_json::from_slice::<ClientMessage>(&buf) {
+        Ok(message) => message,
+        Err(e) => {
+            let revision = nm.effects.lock().unwrap().revision();
+            return send_reply(ws, revision, Err(e.to_string()));
+        }
+    };
+    match message {
+        ClientMessage::Preview { fps, max_pixels } => {
+            if let Some(session) = sessions.lock().unwrap().get_mut(&ws.session()) {
+                session.interval = match fps > 0.0 {
+                    true => Some(Duration::from_secs_f32(1.0 / fps.clamp(MIN_FPS, MAX_FPS))),
+                    false => None,
+                };
+                session.max_pixels = max_pixels.max(1);
+                session.next_frame = Instant::now();
+            }
+            Ok(())
+        }
+        ClientMessage::Patch { id, layer, rev } => {
+            let mut stack = nm.effects.lock().unwrap();
+            let result = match (stack.get(id).cloned(), rev) {
+                (_, Some(rev)) if rev != stack.revision() => Err("revision conflict".to_owned()),
+                (None, _) => Err(format!("no effect with id {}", id)),
+                (Some(current), _) => merged(&current, &layer).and_then(|patched| {
+                    if !nm.segments.lock().unwrap().contains(patched.segment) {
+                        return Err(format!("no segment with id {}", patched.segment));
+                    }
+                    stack.patch(
+                        id,
+                        LayerPatch {
+                            segment: Some(patched.segment),
+                            effect: Some(patched.effect),
+                            opacity: Some(patched.opacity),
+                            blend: Some(patched.blend),
+                            enabled: Some(patched.enabled),
+                        },
+                    );
+                    dirty.store(true, Ordering::SeqCst);
+                    Ok(())
+                }),
+            };
+            let revision = stack.revision();
+            drop(stack);
+            send_reply(ws, revision, result)
+        }
+        ClientMessage::Commit => {
+            dirty.store(false, Ordering::SeqCst);
+            let result = persist(nm, store).map_err(|e| e.to_string());
+            let revision = nm.effects.lock().unwrap().revision();
+            send_reply(ws, revision, result)
+        }
+    }
+}
+
+fn send_reply(
+    ws: &mut EspHttpWsConnection,
+    revision: u32,
+    result: Result<(), String>,
+) -> Result<(), EspError> {
+    let reply = Reply {
+        ok: result.is_ok(),
+        revision,
+        error: result.err(),
+    };
+    // serializing this cannot fail
+    let body = serde_json::to_vec(&reply).unwrap_or_default();
+    ws.send(FrameType::Text(false), &body)
+}
+
+fn persist(nm: &NeopixelManager, store: &Mutex<DStore>) -> anyhow::Result<()> {
+    let layers = nm.effects.lock().unwrap().layers().to_vec();
+    if let Err(e) = store.lock().unwrap().set("effects", &layers) {
+        anyhow::bail!("effects changed but could not be stored: {:?}", e);
+    }
+    Ok(())
+}
+
+fn push_previews(nm: Arc<NeopixelManager<'static>>, sessions: Sessions) {
+    let mut frame = Vec::new();
+    let mut message = Vec::new();
+    loop {
+        thread::sleep(TICK);
+        let now = Instant::now();
+        let mut sessions = sessions.lock().unwrap();
+        let mut fetched = false;
+        let mut gone = Vec::new();
+        for (id, session) in sessions.iter_mut() {
+            let interval = match session.interval {
+                Some(interval) if session.next_frame <= now => interval,
+                _ => continue,
+            };
+            if !fetched {
+                nm.frame_into(&mut frame);
+                fetched = true;
+            }
+            encode_preview(&frame, session.max_pixels, &mut message);
+            if session
+                .sender
+                .send(FrameType::Binary(false), &message)
+                .is_err()
+            {
+                gone.push(*id);
+                continue;
+            }
+            // skip frames instead of bursting if we fell behind
+            session.next_frame = (session.next_frame + interval).max(now);
+        }
+        for id in gone {
+            sessions.remove(&id);
+        }
+    }
+}
@@ -0,0 +1,322 @@
+//! LED panels and matrices: where on a grid every led of the strip is.
+//!
+//! Panels are wired row by row from the top left, `serpentine` ones turn around at the end of
+//! every row. Tiled panels are wired one after the other, again row by row from the top left.
+//! `rotation` turns the whole matrix as effects see it, which also covers panels that start in
+//! another corner.
+//!
+//! 2D effects draw on a `Grid` of the size of the matrix, see `effects::Effect2D`.
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{segment::Segment, strip::color::default::Color};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MatrixConfig {
+    /// first led of the matrix
+    #[serde(default)]
+    pub start: u16,
+    /// pixels per row of one panel
+    pub width: u16,
+    /// rows of one panel
+    pub height: u16,
+    /// every other row runs right to left
+    #[serde(default)]
+    pub serpentine: bool,
+    #[serde(default)]
+    pub panels: Panels,
+    #[serde(default)]
+    pub rotation: Rotation,
+}
+
+/// Identical panels tiled into one matrix.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Panels {
+    pub columns: u16,
+    pub rows: u16,
+    /// every other row of panels runs right to left
+    #[serde(default)]
+    pub serpentine: bool,
+}
+
+impl Default for Panels {
+    fn default() -> Self {
+        Self {
+            columns: 1,
+            rows: 1,
+            serpentine: false,
+        }
+    }
+}
+
+/// clockwise
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Rotation {
+    #[default]
+    None,
+    Quarter,
+    Half,
+    ThreeQuarters,
+}
+
+impl MatrixConfig {
+    /// width and height of all panels together, before rotating
+    fn wired_size(&self) -> (u32, u32) {
+        (
+            self.width as u32 * self.panels.columns as u32,
+            self.height as u32 * self.panels.rows as u32,
+        )
+    }
+
+    pub fn led_count(&self) -> u32 {
+        let (width, height) = self.wired_size();
+        width * height
+    }
+
+    pub fn check(&self, led_count: u16) -> Result<()> {
+        if self.width == 0 || self.height == 0 || self.panels.columns == 0 || self.panels.rows == 0
+        {
+            bail!("panels need at least one row and column, and there has to be one panel");
+        }
+        if self.start as u32 + self.led_count() > led_count as u32 {
+            bail!(
+                "the matrix has {} leds from {}, the strips only {}",
+                self.led_count(),
+                self.start,
+                led_count
+            );
+        }
+        Ok(())
+    }
+
+    /// The led offset from `start` at `(x, y)` as effects see it on a grid of `(width, height)`.
+    fn offset(&self, x: u32, y: u32, (width, height): (u32, u32)) -> u32 {
+        let (x, y) = match self.rotation {
+            Rotation::None => (x, y),
+            Rotation::Quarter => (y, width - 1 - x),
+            Rotation::Half => (width - 1 - x, height - 1 - y),
+            Rotation::ThreeQuarters => (height - 1 - y, x),
+        };
+        let (panel_w, panel_h) = (self.width as u32, self.height as u32);
+        let (panel_row, mut panel_column) = (y / panel_h, x / panel_w);
+        if self.panels.serpentine && panel_row % 2 == 1 {
+            panel_column = self.panels.columns as u32 - 1 - panel_column;
+        }
+        let panel = panel_row * self.panels.columns as u32 + panel_column;
+        let (row, mut column) = (y % panel_h, x % panel_w);
+        if self.serpentine && row % 2 == 1 {
+            column = panel_w - 1 - column;
+        }
+        panel * panel_w * panel_h + row * panel_w + column
+    }
+}
+
+/// `MatrixConfig` turned into a lookup table.
+#[derive(Debug, Clone)]
+pub struct Matrix {
+    config: MatrixConfig,
+    width: u16,
+    height: u16,
+    /// the grid cell of every led from `start`
+    cells: Vec<u32>,
+}
+
+impl Matrix {
+    pub fn new(config: MatrixConfig) -> Self {
+        let (wired_w, wired_h) = config.wired_size();
+        let (width, height) = match config.rotation {
+            Rotation::None | Rotation::Half => (wired_w, wired_h),
+            Rotation::Quarter | Rotation::ThreeQuarters => (wired_h, wired_w),
+        };
+        let mut cells = vec![0; config.led_count() as usize];
+        for y in 0..height {
+            for x in 0..width {
+                cells[config.offset(x, y, (width, height)) as usize] = y * width + x;
+            }
+        }
+        Self {
+            config,
+            width: width as u16,
+            height: height as u16,
+            cells,
+        }
+    }
+
+    pub fn config(&self) -> &MatrixConfig {
+        &self.config
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// index of the grid cell `led` is at, `None` if it is not on the matrix
+    pub fn cell(&self, led: usize) -> Option<usize> {
+        let offset = led.checked_sub(self.config.start as usize)?;
+        self.cells.get(offset).map(|cell| *cell as usize)
+    }
+
+    /// The cell of every pixel of `segment`, by the first led that shows it.
+    pub fn cells_of(&self, segment: &Segment, out: &mut Vec<Option<usize>>) {
+        out.clear();
+        out.extend((0..segment.pixel_count()).map(|pixel| self.cell(segment.leds(pixel).next()?)));
+    }
+}
+
+/// What a 2D effect draws on: `(0, 0)` is the top left, cells without a pixel are black.
+#[derive(Debug, Clone, Default)]
+pub struct Grid {
+    width: u16,
+    height: u16,
+    cells: Vec<Color>,
+}
+
+impl Grid {
+    /// a black grid of `width * height` cells, reusing the memory of this one
+    pub fn reset(&mut self, width: u16, height: u16) {
+        self.width = width;
+        self.height = height;
+        self.cells.clear();
+        self.cells
+            .resize(width as usize * height as usize, Color::black());
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// black outside of the grid
+    pub fn get(&self, x: u16, y: u16) -> Color {
+        match x < self.width && y < self.height {
+            true => self.cells[self.index(x, y)],
+            false => Color::black(),
+        }
+    }
+
+    /// ignored outside of the grid
+    pub fn set(&mut self, x: u16, y: u16, color: Color) {
+        if x < self.width && y < self.height {
+            let index = self.index(x, y);
+            self.cells[index] = color;
+        }
+    }
+
+    /// every cell with its `(x, y)`, row by row
+    pub fn cells_mut(&mut self) -> impl Iterator<Item = ((u16, u16), &mut Color)> {
+        let width = self.width.max(1) as usize;
+        self.cells
+            .iter_mut()
+            .enumerate()
+            .map(move |(i, color)| (((i % width) as u16, (i / width) as u16), color))
+    }
+
+    /// the cells row by row, indexed like `Matrix::cell`
+    pub(crate) fn as_mut_slice(&mut self) -> &mut [Color] {
+        &mut self.cells
+    }
+
+    fn index(&self, x: u16, y: u16) -> usize {
+        y as usize * self.width as usize + x as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn panel(width: u16, height: u16) -> MatrixConfig {
+        MatrixConfig {
+            start: 0,
+            width,
+            height,
+            serpentine: false,
+            panels: Panels::default(),
+            rotation: Rotation::None,
+        }
+    }
+
+    /// the cell of every led of the matrix, in wiring order
+    fn cells(config: MatrixConfig) -> Vec<usize> {
+        let (start, count) = (config.start as usize, config.led_count() as usize);
+        let matrix = Matrix::new(config);
+        (start..start + count)
+            .map(|led| matrix.cell(led).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn serpentine_rows_turn_around() {
+        let config = MatrixConfig {
+            start: 2,
+            serpentine: true,
+            ..panel(4, 3)
+        };
+        let matrix = Matrix::new(config.clone());
+        assert_eq!((matrix.width(), matrix.height()), (4, 3));
+        assert_eq!(matrix.cell(1), None);
+        assert_eq!(matrix.cell(14), None);
+        assert_eq!(cells(config), [0, 1, 2, 3, 7, 6, 5, 4, 8, 9, 10, 11]);
+    }
+
+    #[test]
+    fn serpentine_panels_turn_around_row_by_row() {
+        let config = MatrixConfig {
+            panels: Panels {
+                columns: 2,
+                rows: 2,
+                serpentine: true,
+            },
+            ..panel(2, 2)
+        };
+        assert_eq!(Matrix::new(config.clone()).width(), 4);
+        assert_eq!(
+            cells(config),
+            [
+                0, 1, 4, 5, // top left
+                2, 3, 6, 7, // top right
+                10, 11, 14, 15, // bottom right
+                8, 9, 12, 13, // bottom left
+            ]
+        );
+    }
+
+    #[test]
+    fn rotations_turn_clockwise() {
+        let rotated = |rotation| {
+            let config = MatrixConfig {
+                rotation,
+                ..panel(3, 2)
+            };
+            let matrix = Matrix::new(config.clone());
+            ((matrix.width(), matrix.height()), cells(config))
+        };
+        assert_eq!(rotated(Rotation::None), ((3, 2), vec![0, 1, 2, 3, 4, 5]));
+        // the first row ends up as the right column, top to bottom
+        assert_eq!(rotated(Rotation::Quarter), ((2, 3), vec![1, 3, 5, 0, 2, 4]));
+        assert_eq!(rotated(Rotation::Half), ((3, 2), vec![5, 4, 3, 2, 1, 0]));
+        assert_eq!(
+            rotated(Rotation::ThreeQuarters),
+            ((2, 3), vec![4, 2, 0, 5, 3, 1])
+        );
+    }
+
+    #[test]
+    fn check_wants_the_whole_matrix_on_the_strips() {
+        let config = MatrixConfig {
+            start: 4,
+            ..panel(4, 4)
+        };
+        assert!(config.check(20).is_ok());
+        assert!(config.check(19).is_err());
+        assert!(panel(0, 4).check(20).is_err());
+    }
+}
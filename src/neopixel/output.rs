@@ -0,0 +1,133 @@
+use serde::{Deserialize, Serialize};
+
+use super::strip::color::default::Color;
+
+/// Settings applied to the finished frame, after all effects and before it is sent to the strip.
+/// `PowerConfig` runs after this, on what would actually be shown.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputConfig {
+    /// master brightness, 0 to 1
+    pub brightness: f32,
+    /// `false` blanks the strip but keeps the brightness for when it is switched on again
+    #[serde(default = "on")]
+    pub on: bool,
+}
+
+fn on() -> bool {
+    true
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            brightness: 1.0,
+            on: true,
+        }
+    }
+}
+
+impl OutputConfig {
+    /// Writes the frame that actually goes to the strip into `out`.
+    /// `colors` is left alone, effects keep their state in it between frames.
+    pub fn apply(&self, colors: &[Color], out: &mut Vec<Color>) {
+        out.clear();
+        let brightness = match self.on {
+            true => self.brightness.clamp(0.0, 1.0),
+            false => 0.0,
+        };
+        out.extend(colors.iter().map(|c| *c * brightness));
+    }
+}
+
+/// Estimated current draw of the strip and the supply it may not exceed.
+/// Kept apart from `OutputConfig` since it belongs to the installation, not to a look or preset.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PowerConfig {
+    /// what the supply can deliver to the strip, 0 disables the limiter
+    pub max_ma: u32,
+    /// draw of one color channel at full brightness, about 20 mA for a ws2812b
+    pub ma_per_channel: f32,
+    /// draw of a pixel that is off, the controller chips are never free
+    pub idle_ma_per_pixel: f32,
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        Self {
+            max_ma: 0,
+            ma_per_channel: 20.0,
+            idle_ma_per_pixel: 1.0,
+        }
+    }
+}
+
+impl PowerConfig {
+    /// Estimated draw of `frame` in mA. It is taken before gamma correction,
+    /// which darkens for any gamma above 1, so it errs on the safe side.
+    pub fn estimate_ma(&self, frame: &[Color]) -> f32 {
+        let channels: f32 = frame
+            .iter()
+            .map(|c| c.red.clamp(0.0, 1.0) + c.green.clamp(0.0, 1.0) + c.blue.clamp(0.0, 1.0))
+            .sum();
+        frame.len() as f32 * self.idle_ma_per_pixel + channels * self.ma_per_channel
+    }
+
+    /// Scales `frame` down evenly so its estimated draw stays within `max_ma`.
+    /// Returns the estimate before limiting.
+    pub fn limit(&self, frame: &mut [Color]) -> f32 {
+        let draw = self.estimate_ma(frame);
+        if self.max_ma == 0 || draw <= self.max_ma as f32 {
+            return draw;
+        }
+        let idle = frame.len() as f32 * self.idle_ma_per_pixel;
+        let scale = ((self.max_ma as f32 - idle) / (draw - idle)).max(0.0);
+        for c in frame.iter_mut() {
+            *c = *c * scale;
+        }
+        draw
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn supply(max_ma: u32) -> PowerConfig {
+        PowerConfig {
+            max_ma,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn full_white_is_scaled_down_to_the_supply() {
+        let power = supply(10_000);
+        let mut frame = vec![Color::white(); 500];
+        let draw = power.limit(&mut frame);
+        assert_eq!(draw, 500.0 + 500.0 * 3.0 * 20.0);
+        let limited = power.estimate_ma(&frame);
+        assert!(
+            (limited - 10_000.0).abs() < 1.0,
+            "still draws {} mA",
+            limited
+        );
+        // evenly, so the colors stay the same
+        assert!(frame.iter().all(|c| c.red == c.green && c.green == c.blue));
+        assert!(frame.iter().all(|c| c.red == frame[0].red));
+    }
+
+    #[test]
+    fn max_ma_0_changes_nothing() {
+        let mut frame = vec![Color::white(); 500];
+        let draw = supply(0).limit(&mut frame);
+        assert_eq!(draw, 500.0 + 500.0 * 3.0 * 20.0);
+        assert!(frame.iter().all(|c| *c == Color::white()));
+    }
+
+    #[test]
+    fn a_budget_below_the_idle_draw_gives_black() {
+        let mut frame = vec![Color::white(); 500];
+        supply(100).limit(&mut frame);
+        assert!(frame.iter().all(|c| *c == Color::black()), "{:?}", frame[0]);
+    }
+}
@@ -0,0 +1,260 @@
+//! Where every led is in space, for installations that are neither a strip nor a grid: trees,
+//! spirals, outlines on a wall.
+//!
+//! A map is uploaded as JSON (`[[x, y, z], ..]`, `z` may be left out) or as CSV (one `x,y[,z]`
+//! line per led, `#` comments and one header line in front of the first point are skipped), in
+//! strip order and in any unit.
+//! Effects see positions scaled so the whole map fits into -1 to 1 around its center, keeping its
+//! proportions, with y pointing up. Leds past the end of the map sit at the center.
+//!
+//! Without a map positions come from the matrix, see `matrix.rs`, or else the strip is a line
+//! along x. Effects that use them implement `effects::Effect3D`.
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+
+use super::{matrix::Matrix, segment::Segment, strip::MAX_TOTAL_LEDS};
+
+pub type Point = [f32; 3];
+
+/// stored that is 12 bytes a point, `partitions.csv` leaves room for a full map in nvs
+pub const MAX_POINTS: usize = MAX_TOTAL_LEDS as usize;
+
+/// The uploaded points and their scaled positions.
+#[derive(Debug, Clone)]
+pub struct PixelMap {
+    points: Vec<Point>,
+    positions: Vec<Point>,
+}
+
+impl PixelMap {
+    pub fn new(points: Vec<Point>) -> Self {
+        let bounds = Bounds::of(&points);
+        let positions = points.iter().map(|p| bounds.scale(*p)).collect();
+        Self { points, positions }
+    }
+
+    /// as uploaded
+    pub fn points(&self) -> &[Point] {
+        &self.points
+    }
+
+    pub fn position(&self, led: usize) -> Point {
+        self.positions.get(led).copied().unwrap_or_default()
+    }
+}
+
+/// The points in a JSON or CSV map, see the module docs.
+pub fn parse(map: &str) -> Result<Vec<Point>> {
+    let points = match map.trim_start().starts_with('[') {
+        true => parse_json(map)?,
+        false => parse_csv(map)?,
+    };
+    if points.is_empty() || points.len() > MAX_POINTS {
+        bail!("a map has 1 to {} points, not {}", MAX_POINTS, points.len());
+    }
+    Ok(points)
+}
+
+fn parse_json(map: &str) -> Result<Vec<Point>> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum JsonPoint {
+        Xy([f32; 2]),
+        Xyz([f32; 3]),
+    }
+    let points: Vec<JsonPoint> = serde_json::from_str(map)?;
+    points
+        .into_iter()
+        .enumerate()
+        .map(|(led, p)| {
+            let p = match p {
+                JsonPoint::Xy([x, y]) => [x, y, 0.0],
+                JsonPoint::Xyz(p) => p,
+            };
+            checked(led, p)
+        })
+        .collect()
+}
+
+fn parse_csv(map: &str) -> Result<Vec<Point>> {
+    let mut points = Vec::new();
+    let mut header_allowed = true;
+    for (line_index, line) in map.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let is_first = std::mem::replace(&mut header_allowed, false);
+        let values: Result<Vec<f32>, _> = line.split(',').map(|v| v.trim().parse()).collect();
+        let p = match values {
+            Ok(v) if v.len() == 2 => [v[0], v[1], 0.0],
+            Ok(v) if v.len() == 3 => [v[0], v[1], v[2]],
+            Err(_) if is_first => continue,
+            _ => bail!("line {} is not 'x,y' or 'x,y,z'", line_index + 1),
+        };
+        points.push(checked(points.len(), p)?);
+    }
+    Ok(points)
+}
+
+fn checked(led: usize, p: Point) -> Result<Point> {
+    if p.iter().any(|v| !v.is_finite()) {
+        bail!("the point of led {} is not a number", led);
+    }
+    Ok(p)
+}
+
+/// Where every pixel of `segment` is, by the first led that shows it. Taken from `map` if there
+/// is one, else from `matrix`, else along the strip of `led_count` leds.
+pub fn positions_of(
+    segment: &Segment,
+    map: Option<&PixelMap>,
+    matrix: Option<&Matrix>,
+    led_count: usize,
+    out: &mut Vec<Point>,
+) {
+    let first_leds = (0..segment.pixel_count()).map(|pixel| segment.leds(pixel).next());
+    out.clear();
+    match (map, matrix) {
+        (Some(map), _) => {
+            out.extend(first_leds.map(|led| led.map_or([0.0; 3], |l| map.position(l))))
+        }
+        (None, Some(matrix)) => {
+            let (width, height) = (matrix.width() as f32, matrix.height() as f32);
+            let bounds = Bounds::of(&[[0.0; 3], [width - 1.0, height - 1.0, 0.0]]);
+            out.extend(
+                first_leds.map(|led| match led.and_then(|l| matrix.cell(l)) {
+                    // rows count down, y goes up
+                    Some(cell) => {
+                        let (x, y) = (cell as f32 % width, (cell as f32 / width).floor());
+                        bounds.scale([x, height - 1.0 - y, 0.0])
+                    }
+                    None => [0.0; 3],
+                }),
+            )
+        }
+        (None, None) => {
+            let bounds = Bounds::line(led_count);
+            out.extend(first_leds.map(|led| match led {
+                Some(led) => bounds.scale([led as f32, 0.0, 0.0]),
+                None => [0.0; 3],
+            }))
+        }
+    }
+}
+
+/// `positions_of` for pixels that are just `count` leds in a row
+pub fn line(count: usize, out: &mut Vec<Point>) {
+    let bounds = Bounds::line(count);
+    out.clear();
+    out.extend((0..count).map(|led| bounds.scale([led as f32, 0.0, 0.0])));
+}
+
+/// center and half of the largest extent of some points
+struct Bounds {
+    center: Point,
+    half: f32,
+}
+
+impl Bounds {
+    fn of(points: &[Point]) -> Self {
+        let mut min = [f32::MAX; 3];
+        let mut max = [f32::MIN; 3];
+        for p in points {
+            for (axis, v) in p.iter().enumerate() {
+                min[axis] = min[axis].min(*v);
+                max[axis] = max[axis].max(*v);
+            }
+        }
+        if points.is_empty() {
+            return Self {
+                center: [0.0; 3],
+                half: 1.0,
+            };
+        }
+        let half = (0..3).map(|a| (max[a] - min[a]) / 2.0).fold(0.0, f32::max);
+        Self {
+            center: [0, 1, 2].map(|a| (min[a] + max[a]) / 2.0),
+            // a single point, or all of them in the same spot
+            half: if half > 0.0 { half } else { 1.0 },
+        }
+    }
+
+    /// `count` leds one unit apart along x
+    fn line(count: usize) -> Self {
+        Self::of(&[[0.0; 3], [count.saturating_sub(1) as f32, 0.0, 0.0]])
+    }
+
+    fn scale(&self, p: Point) -> Point {
+        [0, 1, 2].map(|a| (p[a] - self.center[a]) / self.half)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neopixel::matrix::{MatrixConfig, Panels, Rotation};
+
+    #[test]
+    fn json_takes_xy_and_xyz() {
+        assert_eq!(
+            parse("[[0, 0], [1.5, 2, 3]]").unwrap(),
+            vec![[0.0, 0.0, 0.0], [1.5, 2.0, 3.0]]
+        );
+        assert!(parse("[]").is_err());
+        assert!(parse("[[1]]").is_err());
+    }
+
+    #[test]
+    fn csv_skips_comments_and_one_header() {
+        let map = "x,y,z\n# front side\n0,0\n\n 1, 2, 3 \n";
+        assert_eq!(parse(map).unwrap(), vec![[0.0, 0.0, 0.0], [1.0, 2.0, 3.0]]);
+        // a typo must not shift every led after it
+        assert!(parse("x,y\n0,O\n1,1\n").is_err());
+        assert!(parse("0,0\n1;1\n").is_err());
+        assert!(parse("x,y\n").is_err());
+    }
+
+    #[test]
+    fn positions_fit_into_minus_1_to_1_keeping_proportions() {
+        let map = PixelMap::new(vec![[0.0, 0.0, 0.0], [4.0, 0.0, 0.0], [4.0, 2.0, 0.0]]);
+        assert_eq!(map.position(0), [-1.0, -0.5, 0.0]);
+        assert_eq!(map.position(1), [1.0, -0.5, 0.0]);
+        assert_eq!(map.position(2), [1.0, 0.5, 0.0]);
+        // past the end of the map
+        assert_eq!(map.position(3), [0.0; 3]);
+        assert_eq!(map.points()[2], [4.0, 2.0, 0.0]);
+    }
+
+    #[test]
+    fn a_single_point_sits_at_the_center() {
+        let map = PixelMap::new(vec![[3.0, -7.0, 1.0]]);
+        assert_eq!(map.position(0), [0.0; 3]);
+    }
+
+    #[test]
+    fn positions_come_from_the_map_then_the_matrix_then_the_strip() {
+        let segment = Segment::plain(String::new(), 1..3);
+        let mut out = Vec::new();
+
+        let map = PixelMap::new(vec![[0.0, 0.0, 0.0], [2.0, 0.0, 0.0], [2.0, 2.0, 0.0]]);
+        positions_of(&segment, Some(&map), None, 5, &mut out);
+        assert_eq!(out, vec![[1.0, -1.0, 0.0], [1.0, 1.0, 0.0]]);
+
+        let matrix = Matrix::new(MatrixConfig {
+            start: 0,
+            width: 3,
+            height: 2,
+            serpentine: false,
+            panels: Panels::default(),
+            rotation: Rotation::None,
+        });
+        positions_of(&segment, None, Some(&matrix), 6, &mut out);
+        // leds 1 and 2 are the middle and the right of the top row
+        assert_eq!(out, vec![[0.0, 0.5, 0.0], [1.0, 0.5, 0.0]]);
+
+        positions_of(&segment, None, None, 5, &mut out);
+        assert_eq!(out, vec![[-0.5, 0.0, 0.0], [0.0, 0.0, 0.0]]);
+    }
+}
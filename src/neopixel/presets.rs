@@ -0,0 +1,254 @@
+//! Named snapshots of the effect stack, output settings and strip calibration, kept in NVS.
+//!
+//! NVS keys are limited to 15 characters, so presets are stored in numbered slots (`preset<N>`)
+//! and the `presets` key maps slot numbers to names.
+
+use std::sync::Mutex;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::store::DStore;
+
+use super::{
+    layer::Layer, output::OutputConfig, strip::calibration::CalibrationConfig, NeopixelManager,
+};
+
+const INDEX_KEY: &str = "presets";
+pub const MAX_NAME_LEN: usize = 32;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Preset {
+    pub effects: Vec<Layer>,
+    pub output: OutputConfig,
+    /// gamma and white balance of the strips, `None` leaves them as they are.
+    /// Which strips there are and whether they dither is wiring, not part of a preset.
+    #[serde(default)]
+    pub calibration: Option<CalibrationConfig>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct PresetIndex {
+    slots: Vec<(u16, String)>,
+}
+
+impl PresetIndex {
+    fn load(store: &DStore) -> Result<Self> {
+        match store.get::<PresetIndex>(INDEX_KEY) {
+            Ok(index) => Ok(index.unwrap_or_default()),
+            Err(e) => bail!("failed reading preset index: {:?}", e),
+        }
+    }
+
+    fn store_in(&self, store: &mut DStore) -> Result<()> {
+        if let Err(e) = store.set(INDEX_KEY, self) {
+            bail!("failed storing preset index: {:?}", e);
+        }
+        Ok(())
+    }
+
+    fn slot_of(&self, name: &str) -> Option<u16> {
+        self.slots
+            .iter()
+            .find(|(_, n)| n == name)
+            .map(|(slot, _)| *slot)
+    }
+
+    fn free_slot(&self) -> u16 {
+        (0..)
+            .find(|s| self.slots.iter().all(|(slot, _)| slot != s))
+            .unwrap()
+    }
+}
+
+fn slot_key(slot: u16) -> String {
+    format!("preset{}", slot)
+}
+
+pub fn check_name(name: &str) -> Result<()> {
+    if name.trim().is_empty() {
+        bail!("preset name must not be empty");
+    }
+    if name.len() > MAX_NAME_LEN {
+        bail!("preset name is longer than {} bytes", MAX_NAME_LEN);
+    }
+    Ok(())
+}
+
+pub fn list(store: &DStore) -> Result<Vec<String>> {
+    Ok(PresetIndex::load(store)?
+        .slots
+        .into_iter()
+        .map(|(_, name)| name)
+        .collect())
+}
+
+pub fn load(store: &DStore, name: &str) -> Result<Option<Preset>> {
+    let slot = match PresetIndex::load(store)?.slot_of(name) {
+        Some(slot) => slot,
+        None => return Ok(None),
+    };
+    match store.get::<Preset>(&slot_key(slot)) {
+        Ok(preset) => Ok(preset),
+        Err(e) => bail!("failed reading preset '{}': {:?}", name, e),
+    }
+}
+
+/// the store keys of all presets, for migrations
+pub(crate) fn keys(store: &DStore) -> Result<Vec<String>> {
+    Ok(PresetIndex::load(store)?
+        .slots
+        .into_iter()
+        .map(|(slot, _)| slot_key(slot))
+        .collect())
+}
+
+/// Stores `preset` under `name`, replacing a preset with the same name.
+pub fn save(store: &mut DStore, name: &str, preset: &Preset) -> Result<()> {
+    check_name(name)?;
+    let mut index = PresetIndex::load(store)?;
+    let (slot, is_new) = match index.slot_of(name) {
+        Some(slot) => (slot, false),
+        None => (index.free_slot(), true),
+    };
+    if let Err(e) = store.set(&slot_key(slot), preset) {
+        bail!("failed storing preset '{}': {:?}", name, e);
+    }
+    if is_new {
+        index.slots.push((slot, name.to_owned()));
+        index.store_in(store)?;
+    }
+    Ok(())
+}
+
+/// Returns `false` if there is no preset called `from`.
+pub fn rename(store: &mut DStore, from: &str, to: &str) -> Result<bool> {
+    check_name(to)?;
+    let mut index = PresetIndex::load(store)?;
+    if index.slot_of(to).is_some() {
+        bail!("there already is a preset called '{}'", to);
+    }
+    match index.slots.iter_mut().find(|(_, n)| n == from) {
+        Some((_, name)) => *name = to.to_owned(),
+        None => return Ok(false),
+    }
+    index.store_in(store)?;
+    Ok(true)
+}
+
+/// Returns `false` if there is no preset called `name`.
+pub fn delete(store: &mut DStore, name: &str) -> Result<bool> {
+    let mut index = PresetIndex::load(store)?;
+    let slot = match index.slot_of(name) {
+        Some(slot) => slot,
+        None => return Ok(false),
+    };
+    index.slots.retain(|(s, _)| *s != slot);
+    index.store_in(store)?;
+    if let Err(e) = store.remove(&slot_key(slot)) {
+        bail!("failed removing preset '{}': {:?}", name, e);
+    }
+    Ok(true)
+}
+
+/// Plays `preset`, called `name`, and stores it as what is played after a restart.
+pub fn recall(
+    nm: &NeopixelManager,
+    store: &Mutex<DStore>,
+    name: &str,
+    preset: Preset,
+) -> Result<()> {
+    if let Err(e) = store.lock().unwrap().set("output", &preset.output) {
+        bail!("couldn't store output settings: {:?}", e);
+    }
+    *nm.output.lock().unwrap() = preset.output;
+
+    if let Some(calibration) = preset.calibration {
+        if let Err(e) = store.lock().unwrap().set("calibration", &calibration) {
+            bail!("couldn't store calibration: {:?}", e);
+        }
+        nm.set_calibration(calibration);
+    }
+
+    let mut stack = nm.effects.lock().unwrap();
+    stack.replace(preset.effects);
+    *nm.recalled.lock().unwrap() = Some((stack.revision(), name.to_owned()));
+    if let Err(e) = store
+        .lock()
+        .unwrap()
+        .set("effects", &stack.layers().to_vec())
+    {
+        bail!("effects changed but could not be stored: {:?}", e);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{neopixel::effects::EffectConfig, store};
+
+    fn preset(brightness: f32) -> Preset {
+        Preset {
+            effects: vec![Layer::new(EffectConfig::default_named("Plasma").unwrap())],
+            output: OutputConfig {
+                brightness,
+                on: true,
+            },
+            calibration: None,
+        }
+    }
+
+    fn brightness(store: &DStore, name: &str) -> Option<f32> {
+        load(store, name).unwrap().map(|p| p.output.brightness)
+    }
+
+    #[test]
+    fn save_rename_and_delete() {
+        let mut store = store::default();
+        save(&mut store, "calm", &preset(0.2)).unwrap();
+        save(&mut store, "party", &preset(0.9)).unwrap();
+        assert_eq!(list(&store).unwrap(), ["calm", "party"]);
+        assert_eq!(keys(&store).unwrap(), ["preset0", "preset1"]);
+
+        // saving under a taken name replaces that preset in its slot
+        save(&mut store, "calm", &preset(0.3)).unwrap();
+        assert_eq!(brightness(&store, "calm"), Some(0.3));
+        assert_eq!(keys(&store).unwrap(), ["preset0", "preset1"]);
+
+        assert!(rename(&mut store, "calm", "evening").unwrap());
+        assert!(!rename(&mut store, "calm", "night").unwrap());
+        assert!(rename(&mut store, "evening", "party").is_err());
+        assert_eq!(list(&store).unwrap(), ["evening", "party"]);
+        assert_eq!(brightness(&store, "evening"), Some(0.3));
+        assert_eq!(brightness(&store, "calm"), None);
+
+        assert!(delete(&mut store, "evening").unwrap());
+        assert!(!delete(&mut store, "evening").unwrap());
+        assert_eq!(list(&store).unwrap(), ["party"]);
+        assert!(!store.contains("preset0").unwrap());
+        assert_eq!(brightness(&store, "party"), Some(0.9));
+    }
+
+    #[test]
+    fn deleting_frees_the_slot() {
+        let mut store = store::default();
+        for name in ["a", "b", "c"] {
+            save(&mut store, name, &preset(0.5)).unwrap();
+        }
+        delete(&mut store, "b").unwrap();
+        save(&mut store, "d", &preset(0.7)).unwrap();
+        assert_eq!(list(&store).unwrap(), ["a", "c", "d"]);
+        assert_eq!(keys(&store).unwrap(), ["preset0", "preset2", "preset1"]);
+        assert_eq!(brightness(&store, "d"), Some(0.7));
+    }
+
+    #[test]
+    fn names_are_checked() {
+        let mut store = store::default();
+        assert!(save(&mut store, " ", &preset(0.5)).is_err());
+        let long = "x".repeat(MAX_NAME_LEN + 1);
+        assert!(save(&mut store, &long, &preset(0.5)).is_err());
+        assert!(list(&store).unwrap().is_empty());
+    }
+}
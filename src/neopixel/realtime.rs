@@ -0,0 +1,111 @@
+use std::{
+    net::Ipv4Addr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use super::strip::color::default::Color;
+
+/// Who is currently streaming pixels to us.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// sACN, identified by the sender's CID
+    E131([u8; 16]),
+    /// Art-Net, identified by the controller's address
+    ArtNet(Ipv4Addr),
+    /// DDP, identified by the sender's address
+    Ddp(Ipv4Addr),
+}
+
+struct Active {
+    source: Source,
+    priority: u8,
+    timeout: Duration,
+    updated: Instant,
+    frame: Vec<Color>,
+}
+
+/// Frames streamed in over the network. While a source keeps sending they are shown instead of the
+/// effect stack; once it has been quiet for its timeout the manager falls back to the effects.
+#[derive(Default)]
+pub struct Realtime {
+    active: Mutex<Option<Active>>,
+}
+
+#[allow(dead_code)]
+impl Realtime {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lets `source` update the realtime frame through `write`.
+    /// A different source only takes over if it has a higher priority or the current one timed out,
+    /// otherwise `write` is not called and `false` is returned.
+    pub fn submit(
+        &self,
+        source: Source,
+        priority: u8,
+        timeout: Duration,
+        led_count: usize,
+        write: impl FnOnce(&mut [Color]),
+    ) -> bool {
+        let mut active = self.active.lock().unwrap();
+        let now = Instant::now();
+        match active.as_mut() {
+            Some(a) if a.source == source => {
+                a.priority = priority;
+                a.timeout = timeout;
+                a.updated = now;
+                a.frame.resize(led_count, Color::black());
+                write(&mut a.frame);
+            }
+            Some(a) if now - a.updated <= a.timeout && priority <= a.priority => return false,
+            _ => {
+                let mut frame = vec![Color::black(); led_count];
+                write(&mut frame);
+                *active = Some(Active {
+                    source,
+                    priority,
+                    timeout,
+                    updated: now,
+                    frame,
+                });
+            }
+        }
+        true
+    }
+
+    /// `source` stopped sending on purpose, go back to the effects right away.
+    pub fn release(&self, source: &Source) {
+        let mut active = self.active.lock().unwrap();
+        if active.as_ref().is_some_and(|a| &a.source == source) {
+            *active = None;
+        }
+    }
+
+    /// Copies the realtime frame into `colors`. Returns `false` if no source is active (anymore).
+    pub fn render_into(&self, colors: &mut [Color]) -> bool {
+        let mut active = self.active.lock().unwrap();
+        match active.as_ref() {
+            Some(a) if a.updated.elapsed() <= a.timeout => {
+                let len = colors.len().min(a.frame.len());
+                colors[..len].copy_from_slice(&a.frame[..len]);
+                true
+            }
+            Some(_) => {
+                *active = None;
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// the active source and its priority
+    pub fn status(&self) -> Option<(Source, u8)> {
+        let active = self.active.lock().unwrap();
+        active
+            .as_ref()
+            .filter(|a| a.updated.elapsed() <= a.timeout)
+            .map(|a| (a.source.clone(), a.priority))
+    }
+}
@@ -0,0 +1,296 @@
+//! Named parts of the strip that layers are drawn on.
+//!
+//! An effect never sees the strip, only the pixels of its layer's segment, numbered from 0.
+//! The segment decides which leds show them: `grouping` leds per pixel with `spacing` leds left
+//! alone in between, optionally in reverse and mirrored around the middle.
+//!
+//! Segment 0 is always the whole strip, it is not stored and can not be changed.
+
+use std::{borrow::Cow, ops::Range};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+pub const WHOLE_STRIP: u32 = 0;
+pub const MAX_NAME_LEN: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Segment {
+    /// assigned by `Segments`
+    #[serde(default)]
+    pub id: u32,
+    pub name: String,
+    /// first led
+    pub start: u16,
+    /// leds covered, spacing included
+    pub len: u16,
+    #[serde(default)]
+    pub reverse: bool,
+    /// the effect only draws the first half, the second half is its mirror image
+    #[serde(default)]
+    pub mirror: bool,
+    /// leds that show the same pixel
+    #[serde(default = "one")]
+    pub grouping: u16,
+    /// leds left alone after every group
+    #[serde(default)]
+    pub spacing: u16,
+}
+
+fn one() -> u16 {
+    1
+}
+
+impl Segment {
+    /// plain leds `range`, e.g. for what effects covered before there were segments
+    pub fn plain(name: String, range: Range<u16>) -> Self {
+        Self {
+            id: 0,
+            name,
+            start: range.start,
+            len: range.end.saturating_sub(range.start),
+            reverse: false,
+            mirror: false,
+            grouping: 1,
+            spacing: 0,
+        }
+    }
+
+    fn whole_strip(led_count: u16) -> Self {
+        Self {
+            id: WHOLE_STRIP,
+            ..Self::plain(String::new(), 0..led_count)
+        }
+    }
+
+    pub fn end(&self) -> u16 {
+        self.start.saturating_add(self.len)
+    }
+
+    fn stride(&self) -> usize {
+        self.grouping.max(1) as usize + self.spacing as usize
+    }
+
+    /// groups of leds, the last one may be cut short by `len`
+    fn groups(&self) -> usize {
+        (self.len as usize).div_ceil(self.stride())
+    }
+
+    /// how many pixels an effect on this segment draws
+    pub fn pixel_count(&self) -> usize {
+        match self.mirror {
+            true => self.groups().div_ceil(2),
+            false => self.groups(),
+        }
+    }
+
+    /// The leds that show `pixel`, which may lie past the end of a strip that got shorter.
+    pub fn leds(&self, pixel: usize) -> impl Iterator<Item = usize> + '_ {
+        let groups = self.groups();
+        let group = match self.reverse {
+            true => self.pixel_count() - 1 - pixel,
+            false => pixel,
+        };
+        let mirrored = groups - 1 - group;
+        let mirrored = match self.mirror && mirrored != group {
+            true => Some(mirrored),
+            false => None,
+        };
+        std::iter::once(group)
+            .chain(mirrored)
+            .flat_map(move |group| self.group_leds(group))
+    }
+
+    fn group_leds(&self, group: usize) -> Range<usize> {
+        let first = self.start as usize + group * self.stride();
+        first..(first + self.grouping.max(1) as usize).min(self.end() as usize)
+    }
+
+    fn same_leds(&self, other: &Segment) -> bool {
+        let leds = |s: &Segment| (s.start, s.len, s.reverse, s.mirror, s.grouping, s.spacing);
+        leds(self) == leds(other)
+    }
+
+    fn check(&self, led_count: u16) -> Result<()> {
+        if self.name.trim().is_empty() || self.name.len() > MAX_NAME_LEN {
+            bail!("segment names have 1 to {} bytes", MAX_NAME_LEN);
+        }
+        if self.len == 0 || self.end() > led_count {
+            bail!(
+                "segment '{}' has to cover 1 to {} leds from {}",
+                self.name,
+                led_count.saturating_sub(self.start),
+                self.start
+            );
+        }
+        if self.grouping == 0 {
+            bail!("grouping has to be at least 1");
+        }
+        Ok(())
+    }
+}
+
+/// The registry, stored as `Vec<Segment>` under `segments`.
+#[derive(Debug, Clone, Default)]
+pub struct Segments {
+    segments: Vec<Segment>,
+    next_id: u32,
+}
+
+impl Segments {
+    pub fn new(segments: Vec<Segment>) -> Self {
+        let next_id = segments.iter().map(|s| s.id).max().unwrap_or(WHOLE_STRIP);
+        Self { segments, next_id }
+    }
+
+    /// the stored segments, without the whole strip
+    pub fn list(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        id == WHOLE_STRIP || self.segments.iter().any(|s| s.id == id)
+    }
+
+    pub fn get(&self, id: u32, led_count: u16) -> Option<Cow<'_, Segment>> {
+        match id {
+            WHOLE_STRIP => Some(Cow::Owned(Segment::whole_strip(led_count))),
+            id => self.segments.iter().find(|s| s.id == id).map(Cow::Borrowed),
+        }
+    }
+
+    /// Whether `segment` fits on the strip and its name is not taken by another segment.
+    pub fn check(&self, segment: &Segment, led_count: u16) -> Result<()> {
+        segment.check(led_count)?;
+        if self
+            .segments
+            .iter()
+            .any(|s| s.name == segment.name && s.id != segment.id)
+        {
+            bail!("there already is a segment called '{}'", segment.name);
+        }
+        Ok(())
+    }
+
+    /// Adds `segment` with a fresh id and returns that.
+    pub fn insert(&mut self, mut segment: Segment) -> u32 {
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        segment.id = self.next_id;
+        self.segments.push(segment);
+        self.next_id
+    }
+
+    /// Replaces the segment with the id of `segment`, `false` if there is none.
+    pub fn update(&mut self, segment: Segment) -> bool {
+        match self.segments.iter_mut().find(|s| s.id == segment.id) {
+            Some(current) => {
+                *current = segment;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove(&mut self, id: u32) -> Option<Segment> {
+        let index = self.segments.iter().position(|s| s.id == id)?;
+        Some(self.segments.remove(index))
+    }
+
+    /// The id of a plain segment over `range`, which is added if there is none yet.
+    pub fn plain(&mut self, range: Range<u16>, led_count: u16) -> u32 {
+        let mut plain = Segment::plain(format!("{}-{}", range.start, range.end), range);
+        if plain.same_leds(&Segment::whole_strip(led_count)) {
+            return WHOLE_STRIP;
+        }
+        if let Some(segment) = self.segments.iter().find(|s| s.same_leds(&plain)) {
+            return segment.id;
+        }
+        let base = plain.name.clone();
+        for n in 2.. {
+            if self.segments.iter().all(|s| s.name != plain.name) {
+                break;
+            }
+            plain.name = format!("{} ({})", base, n);
+        }
+        self.insert(plain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(start: u16, len: u16) -> Segment {
+        Segment::plain("test".into(), start..start + len)
+    }
+
+    /// the leds of every pixel
+    fn leds(segment: &Segment) -> Vec<Vec<usize>> {
+        (0..segment.pixel_count())
+            .map(|pixel| segment.leds(pixel).collect())
+            .collect()
+    }
+
+    #[test]
+    fn mirror_shares_the_middle_on_odd_lengths() {
+        let even = Segment {
+            mirror: true,
+            ..segment(0, 6)
+        };
+        assert_eq!(leds(&even), [vec![0, 5], vec![1, 4], vec![2, 3]]);
+        let odd = Segment {
+            mirror: true,
+            ..segment(0, 5)
+        };
+        assert_eq!(leds(&odd), [vec![0, 4], vec![1, 3], vec![2]]);
+    }
+
+    #[test]
+    fn reverse_and_mirror_start_in_the_middle() {
+        let reversed = Segment {
+            reverse: true,
+            ..segment(3, 4)
+        };
+        assert_eq!(leds(&reversed), [[6], [5], [4], [3]]);
+        let both = Segment {
+            reverse: true,
+            mirror: true,
+            ..segment(0, 5)
+        };
+        assert_eq!(leds(&both), [vec![2], vec![1, 3], vec![0, 4]]);
+    }
+
+    #[test]
+    fn len_cuts_the_last_group_short() {
+        let spaced = Segment {
+            grouping: 3,
+            spacing: 1,
+            ..segment(2, 10)
+        };
+        assert_eq!(leds(&spaced), [vec![2, 3, 4], vec![6, 7, 8], vec![10, 11]]);
+        let shorter = Segment { len: 9, ..spaced };
+        assert_eq!(leds(&shorter), [vec![2, 3, 4], vec![6, 7, 8], vec![10]]);
+        // the spacing after the last group is not a pixel of its own
+        let exact = Segment { len: 8, ..shorter };
+        assert_eq!(exact.pixel_count(), 2);
+    }
+
+    #[test]
+    fn plain_reuses_segments_over_the_same_leds() {
+        let mut segments = Segments::default();
+        assert_eq!(segments.plain(0..10, 10), WHOLE_STRIP);
+
+        let taken = segments.insert(Segment {
+            name: "2-5".into(),
+            grouping: 2,
+            ..segment(2, 3)
+        });
+        let id = segments.plain(2..5, 10);
+        assert_ne!(id, taken);
+        assert_eq!(segments.plain(2..5, 10), id);
+        let plain = segments.get(id, 10).unwrap();
+        assert_eq!((plain.start, plain.len), (2, 3));
+        assert_eq!(plain.name, "2-5 (2)");
+        assert_eq!(segments.list().len(), 2);
+    }
+}
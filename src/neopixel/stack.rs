@@ -0,0 +1,223 @@
+use serde::{Deserialize, Serialize};
+
+use super::{
+    effects::EffectConfig,
+    layer::{BlendMode, Layer},
+};
+
+/// The effect stack the manager renders, with stable ids per layer
+/// and a revision that is bumped on every change (for optimistic locking by clients).
+#[derive(Debug, Clone, Default)]
+pub struct EffectStack {
+    layers: Vec<Layer>,
+    next_id: u32,
+    revision: u32,
+}
+
+/// Fields of a layer that can be changed in place, `None` keeps the current value.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LayerPatch {
+    #[serde(default)]
+    pub segment: Option<u32>,
+    #[serde(default)]
+    pub effect: Option<EffectConfig>,
+    #[serde(default)]
+    pub opacity: Option<f32>,
+    #[serde(default)]
+    pub blend: Option<BlendMode>,
+    #[serde(default)]
+    pub enabled: Option<bool>,
+}
+
+#[allow(dead_code)]
+impl EffectStack {
+    pub fn layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    pub fn revision(&self) -> u32 {
+        self.revision
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Layer> {
+        self.layers.iter().find(|l| l.id == id)
+    }
+
+    /// Replaces the whole stack. Layers without an id (or with one that is already taken) get a fresh one.
+    pub fn replace(&mut self, layers: Vec<Layer>) {
+        self.layers.clear();
+        self.next_id = layers.iter().map(|l| l.id).max().unwrap_or(0);
+        for mut layer in layers {
+            if layer.id == 0 || self.get(layer.id).is_some() {
+                layer.id = self.fresh_id();
+            }
+            self.layers.push(layer);
+        }
+        self.revision = self.revision.wrapping_add(1);
+    }
+
+    /// Inserts at `index` (appends if `None` or past the end) and returns the new layer's id.
+    pub fn insert(&mut self, index: Option<usize>, mut layer: Layer) -> u32 {
+        layer.id = self.fresh_id();
+        let id = layer.id;
+        let index = index.unwrap_or(self.layers.len()).min(self.layers.len());
+        self.layers.insert(index, layer);
+        self.revision = self.revision.wrapping_add(1);
+        id
+    }
+
+    pub fn patch(&mut self, id: u32, patch: LayerPatch) -> Option<&Layer> {
+        let layer = self.layers.iter_mut().find(|l| l.id == id)?;
+        if let Some(segment) = patch.segment {
+            layer.segment = segment;
+        }
+        if let Some(effect) = patch.effect {
+            layer.effect = effect;
+        }
+        if let Some(opacity) = patch.opacity {
+            layer.opacity = opacity;
+        }
+        if let Some(blend) = patch.blend {
+            layer.blend = blend;
+        }
+        if let Some(enabled) = patch.enabled {
+            layer.enabled = enabled;
+        }
+        self.revision = self.revision.wrapping_add(1);
+        self.get(id)
+    }
+
+    pub fn remove(&mut self, id: u32) -> Option<Layer> {
+        let index = self.index_of(id)?;
+        self.revision = self.revision.wrapping_add(1);
+        Some(self.layers.remove(index))
+    }
+
+    /// Moves the layer to `index` (clamped to the stack), 0 is the bottom.
+    pub fn move_to(&mut self, id: u32, index: usize) -> Option<()> {
+        let from = self.index_of(id)?;
+        let layer = self.layers.remove(from);
+        let index = index.min(self.layers.len());
+        self.layers.insert(index, layer);
+        self.revision = self.revision.wrapping_add(1);
+        Some(())
+    }
+
+    /// Sets `enabled`, or flips it if `None`. Returns the new state.
+    pub fn set_enabled(&mut self, id: u32, enabled: Option<bool>) -> Option<bool> {
+        let layer = self.layers.iter_mut().find(|l| l.id == id)?;
+        layer.enabled = enabled.unwrap_or(!layer.enabled);
+        let enabled = layer.enabled;
+        self.revision = self.revision.wrapping_add(1);
+        Some(enabled)
+    }
+
+    fn index_of(&self, id: u32) -> Option<usize> {
+        self.layers.iter().position(|l| l.id == id)
+    }
+
+    fn fresh_id(&mut self) -> u32 {
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        self.next_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(id: u32) -> Layer {
+        Layer {
+            id,
+            ..Layer::new(EffectConfig::default_named("Invert").unwrap())
+        }
+    }
+
+    fn ids(stack: &EffectStack) -> Vec<u32> {
+        stack.layers().iter().map(|l| l.id).collect()
+    }
+
+    #[test]
+    fn replace_keeps_ids_and_gives_fresh_ones_to_unassigned_and_duplicates() {
+        let mut stack = EffectStack::default();
+        stack.replace(vec![layer(5), layer(0), layer(5), layer(2)]);
+        assert_eq!(ids(&stack), [5, 6, 7, 2]);
+        assert_eq!(stack.insert(None, layer(2)), 8);
+        assert_eq!(stack.insert(Some(0), layer(0)), 9);
+        assert_eq!(ids(&stack), [9, 5, 6, 7, 2, 8]);
+    }
+
+    #[test]
+    fn fresh_ids_wrap_around_past_0() {
+        let mut stack = EffectStack::default();
+        stack.replace(vec![layer(u32::MAX)]);
+        assert_eq!(stack.insert(None, layer(0)), 1);
+        assert_eq!(stack.insert(None, layer(0)), 2);
+    }
+
+    #[test]
+    fn move_to_clamps_to_the_stack() {
+        let mut stack = EffectStack::default();
+        stack.replace(vec![layer(1), layer(2), layer(3)]);
+        stack.move_to(1, 10).unwrap();
+        assert_eq!(ids(&stack), [2, 3, 1]);
+        stack.move_to(1, 0).unwrap();
+        assert_eq!(ids(&stack), [1, 2, 3]);
+        stack.move_to(3, 1).unwrap();
+        assert_eq!(ids(&stack), [1, 3, 2]);
+        assert_eq!(stack.move_to(4, 0), None);
+    }
+
+    #[test]
+    fn patch_only_changes_what_it_is_given() {
+        let mut stack = EffectStack::default();
+        stack.replace(vec![layer(1)]);
+        let patched = stack
+            .patch(
+                1,
+                LayerPatch {
+                    opacity: Some(0.5),
+                    blend: Some(BlendMode::Add),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+            .clone();
+        assert_eq!(patched.opacity, 0.5);
+        assert_eq!(patched.blend, BlendMode::Add);
+        assert_eq!(patched.effect.name(), "Invert");
+        assert_eq!((patched.segment, patched.enabled), (0, true));
+        assert!(stack.patch(2, LayerPatch::default()).is_none());
+    }
+
+    #[test]
+    fn every_change_bumps_the_revision() {
+        let mut stack = EffectStack::default();
+        let mut revision = stack.revision();
+        let mut changed = |stack: &EffectStack| {
+            let bumped = stack.revision() != revision;
+            revision = stack.revision();
+            bumped
+        };
+
+        stack.replace(vec![layer(1)]);
+        assert!(changed(&stack));
+        let id = stack.insert(None, layer(0));
+        assert!(changed(&stack));
+        stack.patch(id, LayerPatch::default());
+        assert!(changed(&stack));
+        stack.move_to(id, 0);
+        assert!(changed(&stack));
+        stack.set_enabled(id, None);
+        assert!(changed(&stack));
+        stack.remove(id);
+        assert!(changed(&stack));
+
+        // nothing to change, nothing for clients to refetch
+        stack.remove(id);
+        stack.patch(id, LayerPatch::default());
+        stack.move_to(id, 0);
+        stack.set_enabled(id, Some(true));
+        assert!(!changed(&stack));
+    }
+}
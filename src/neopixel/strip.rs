@@ -1,133 +1,308 @@
-use std::{
-    sync::{Arc, Mutex},
-    time::Duration,
-};
-
-use anyhow::Result;
-use esp_idf_hal::{
-    delay::Ets,
-    gpio::{OutputPin, Pin},
-    peripheral::Peripheral,
-    rmt::{config::TransmitConfig, PinState, Pulse, RmtChannel, TxRmtDriver, VariableLengthSignal},
-};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
 
+pub mod apa102;
+pub mod calibration;
 pub mod color;
+pub mod multi;
+#[cfg(feature = "esp")]
+mod rmt;
+pub mod sim;
 
-#[allow(dead_code)]
+#[cfg(feature = "esp")]
+use apa102::Apa102;
+use calibration::Calibration;
+#[cfg(feature = "esp")]
+use multi::MultiSink;
+#[cfg(feature = "esp")]
+pub use rmt::Strip;
+
+#[cfg(feature = "esp")]
+use super::Sink;
+
+/// Anything a finished frame can be pushed to.
+/// `Strip` and `apa102::Apa102` drive the real hardware, `multi::MultiSink` spreads a frame over
+/// several of them, `sim::SimStrip` only records what it was sent.
+pub trait LedSink {
+    fn led_count(&self) -> u16;
+    fn send_colors(&self, colors: &[color::default::Color]) -> Result<()>;
+    /// Gamma and white balance for every following frame.
+    /// Sinks that keep the linear values, like `sim::SimStrip`, ignore it.
+    fn set_calibration(&self, _calibration: Calibration) {}
+}
 
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum LedColorOrder {
     RGB,
+    RBG,
     GRB,
+    GBR,
+    BRG,
+    BGR,
+    RGBW,
+    GRBW,
+}
+
+impl LedColorOrder {
+    pub fn has_white(&self) -> bool {
+        matches!(self, LedColorOrder::RGBW | LedColorOrder::GRBW)
+    }
+
+    /// bits per pixel on the wire
+    pub fn bits(&self) -> u8 {
+        match self.has_white() {
+            true => 32,
+            false => 24,
+        }
+    }
+
+    /// the `bits()` bits of one pixel, in the order they go on the wire
+    pub fn pack(&self, [r, g, b, w]: [u8; 4]) -> u32 {
+        let [first, second, third] = match self {
+            LedColorOrder::RGB | LedColorOrder::RGBW => [r, g, b],
+            LedColorOrder::RBG => [r, b, g],
+            LedColorOrder::GRB | LedColorOrder::GRBW => [g, r, b],
+            LedColorOrder::GBR => [g, b, r],
+            LedColorOrder::BRG => [b, r, g],
+            LedColorOrder::BGR => [b, g, r],
+        };
+        let rgb = (first as u32) << 16 | (second as u32) << 8 | third as u32;
+        match self.has_white() {
+            true => rgb << 8 | w as u32,
+            false => rgb,
+        }
+    }
 }
 
-pub struct Strip<'d> {
+/// Bit timings of the one-wire chips, in ns.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Timings {
     pub zero_high_ns: u16,
     pub zero_low_ns: u16,
     pub one_high_ns: u16,
     pub one_low_ns: u16,
     pub reset_ns: u16,
-    pub led_count: u16,
-    rmt: Arc<Mutex<TxRmtDriver<'d>>>,
-    pub led_color_order: LedColorOrder,
 }
 
-#[allow(dead_code)]
-impl<'d> Strip<'d> {
-    pub fn ws2812b<T: Pin + OutputPin, C: RmtChannel>(
-        pin: impl Peripheral<P = T> + 'd,
-        rmt_channel: impl Peripheral<P = C> + 'd,
-        pixel_count: u16,
-    ) -> Self {
-        let config = TransmitConfig::new().clock_divider(1);
-        let tx = Arc::new(Mutex::new(
-            TxRmtDriver::new(rmt_channel, pin, &config).unwrap(),
-        ));
-        Self {
-            zero_high_ns: 400,
-            zero_low_ns: 850,
-            one_high_ns: 800,
-            one_low_ns: 450,
-            reset_ns: 55000,
-            led_count: pixel_count,
-            rmt: tx,
-            led_color_order: LedColorOrder::GRB,
-        }
-    }
-    pub fn ws2812<T: Pin + OutputPin, C: RmtChannel>(
-        pin: impl Peripheral<P = T> + 'd,
-        rmt_channel: impl Peripheral<P = C> + 'd,
-        pixel_count: u16,
-    ) -> Self {
-        let config = TransmitConfig::new().clock_divider(1);
-        let tx = Arc::new(Mutex::new(
-            TxRmtDriver::new(rmt_channel, pin, &config).unwrap(),
-        ));
-        Self {
-            zero_high_ns: 350,
-            zero_low_ns: 800,
-            one_high_ns: 700,
-            one_low_ns: 600,
-            reset_ns: 55000,
-            led_count: pixel_count,
-            rmt: tx,
-            led_color_order: LedColorOrder::GRB,
-        }
-    }
+/// Settings of the strips with a clock line.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Clocked {
+    pub clock_gpio: u8,
+    pub clock_hz: u32,
+    /// use the global brightness of every pixel for dark colors, see `apa102`
+    pub hdr: bool,
+}
 
-    pub fn custom<T: Pin + OutputPin, C: RmtChannel>(
-        pin: impl Peripheral<P = T> + 'd,
-        rmt_channel: impl Peripheral<P = C> + 'd,
-        pixel_count: u16,
-        zero_high_ns: u16,
-        zero_low_ns: u16,
-        one_high_ns: u16,
-        one_low_ns: u16,
-        reset_ns: u16,
-    ) -> Self {
-        let config = TransmitConfig::new().clock_divider(1);
-        let tx = Arc::new(Mutex::new(
-            TxRmtDriver::new(rmt_channel, pin, &config).unwrap(),
-        ));
-        Self {
+/// postcard stores the variant index, new chipsets go at the end
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Chipset {
+    Ws2812b,
+    Ws2812,
+    Sk6812,
+    Custom(Timings),
+    Apa102(Clocked),
+    Sk9822(Clocked),
+}
+
+impl Chipset {
+    /// `None` for the chipsets with a clock line
+    pub fn timings(&self) -> Option<Timings> {
+        let t = |zero_high_ns, zero_low_ns, one_high_ns, one_low_ns, reset_ns| Timings {
             zero_high_ns,
             zero_low_ns,
             one_high_ns,
             one_low_ns,
             reset_ns,
-            led_count: pixel_count,
-            rmt: tx,
-            led_color_order: LedColorOrder::GRB,
+        };
+        match self {
+            Chipset::Ws2812b => Some(t(400, 850, 800, 450, 55000)),
+            Chipset::Ws2812 => Some(t(350, 800, 700, 600, 55000)),
+            // the datasheet wants 80 µs of reset, which does not fit, frames are further apart anyway
+            Chipset::Sk6812 => Some(t(300, 900, 600, 600, 65000)),
+            Chipset::Custom(timings) => Some(timings.clone()),
+            Chipset::Apa102(_) | Chipset::Sk9822(_) => None,
         }
     }
 
-    pub fn send_colors(&self, colors: &[color::default::Color]) -> Result<()> {
-        let ticks_hz = self.rmt.lock().unwrap().counter_clock()?;
-        let t0h = Pulse::new_with_duration(ticks_hz, PinState::High, &ns(self.zero_high_ns))?;
-        let t0l = Pulse::new_with_duration(ticks_hz, PinState::Low, &ns(self.zero_low_ns))?;
-        let t1h = Pulse::new_with_duration(ticks_hz, PinState::High, &ns(self.one_high_ns))?;
-        let t1l = Pulse::new_with_duration(ticks_hz, PinState::Low, &ns(self.one_low_ns))?;
-        drop(ticks_hz);
-
-        let mut signal = VariableLengthSignal::with_capacity(24 * colors.len());
-        for color in colors {
-            // for bit in color.to_bit_iter(self.led_color_order) {
-            //     let (high_pulse, low_pulse) = if bit { (t1h, t1l) } else { (t0h, t0l) };
-            //     signal.push(&(high_pulse, low_pulse))?;
-            // }
-            signal.push(
-                color
-                    .to_bit_iter(&self.led_color_order)
-                    .map(|bit| if bit { [&t1h, &t1l] } else { [&t0h, &t0l] })
-                    .flatten(),
-            )?;
+    pub fn clocked(&self) -> Option<&Clocked> {
+        match self {
+            Chipset::Apa102(clocked) | Chipset::Sk9822(clocked) => Some(clocked),
+            _ => None,
+        }
+    }
+}
+
+/// One physical strip as stored in NVS, so it can be changed without flashing.
+/// The board drives a list of them, see `from_configs`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StripConfig {
+    pub chipset: Chipset,
+    pub order: LedColorOrder,
+    pub led_count: u16,
+    /// data pin
+    pub gpio: u8,
+    /// rmt channel 0 to 7, or for chipsets with a clock line the SPI host, 2 or 3
+    pub channel: u8,
+    /// the strip is wired starting from its far end
+    pub reversed: bool,
+    /// temporal dithering, smooths dark fades at the cost of a slight shimmer,
+    /// see `calibration`
+    pub dither: bool,
+}
+
+impl Default for StripConfig {
+    fn default() -> Self {
+        Self {
+            chipset: Chipset::Ws2812b,
+            order: LedColorOrder::GRB,
+            led_count: 500,
+            gpio: 14,
+            channel: 1,
+            reversed: false,
+            dither: true,
+        }
+    }
+}
+
+/// what one rmt channel can still push at a usable frame rate
+pub const MAX_LEDS: u16 = 1024;
+/// what the frame buffers of all strips together still fit in ram
+pub const MAX_TOTAL_LEDS: u16 = 2048;
+const MAX_OUTPUTS: usize = 8;
+/// APA102 and SK9822 manage more on short runs, but not much more over a meter or two of wire
+const MAX_CLOCK_HZ: u32 = 20_000_000;
+/// ESP32 pins that exist, can drive an output and are not wired to the flash or to UART0,
+/// taking over GPIO1 or GPIO3 would cut off the console. The strapping pins 0, 12 and 15 are left
+/// out as well, a strip's data line can hold them at the wrong level during a reset and the board
+/// then boots into the download mode, at the wrong flash voltage or without its boot log.
+const OUTPUT_GPIOS: &[u8] = &[
+    2, 4, 5, 13, 14, 16, 17, 18, 19, 21, 22, 23, 25, 26, 27, 32, 33,
+];
+
+impl StripConfig {
+    pub fn check(&self) -> Result<()> {
+        if self.led_count == 0 || self.led_count > MAX_LEDS {
+            bail!("led_count has to be between 1 and {}", MAX_LEDS);
+        }
+        if !OUTPUT_GPIOS.contains(&self.gpio) {
+            bail!("gpio {} can not drive a strip", self.gpio);
+        }
+        if let Some(t) = self.chipset.timings() {
+            if [t.zero_high_ns, t.zero_low_ns, t.one_high_ns, t.one_low_ns].contains(&0) {
+                bail!("bit timings can not be 0");
+            }
+            if self.channel > 7 {
+                bail!("there is no rmt channel {}", self.channel);
+            }
+        }
+        if let Some(clocked) = self.chipset.clocked() {
+            if !OUTPUT_GPIOS.contains(&clocked.clock_gpio) || clocked.clock_gpio == self.gpio {
+                bail!("gpio {} can not be the clock", clocked.clock_gpio);
+            }
+            if clocked.clock_hz == 0 || clocked.clock_hz > MAX_CLOCK_HZ {
+                bail!("clock_hz has to be between 1 and {}", MAX_CLOCK_HZ);
+            }
+            if self.order.has_white() {
+                bail!("{:?} strips have no white channel", self.chipset);
+            }
+            if !(2..=3).contains(&self.channel) {
+                bail!("there is no SPI{} for strips", self.channel);
+            }
         }
-        // println!("colors: {:?}", colors);
-        self.rmt.clone().lock().unwrap().start(signal)?;
-        Ets::delay_us((self.reset_ns / 1000) as u32);
         Ok(())
     }
+
+    fn pins(&self) -> Vec<u8> {
+        let mut pins = vec![self.gpio];
+        pins.extend(self.chipset.clocked().map(|c| c.clock_gpio));
+        pins
+    }
 }
 
-fn ns(nanos: u16) -> Duration {
-    Duration::from_nanos(nanos as u64)
+/// Checks every strip, and that they neither share pins nor peripherals.
+pub fn check_configs(configs: &[StripConfig]) -> Result<()> {
+    if configs.is_empty() || configs.len() > MAX_OUTPUTS {
+        bail!("there have to be between 1 and {} strips", MAX_OUTPUTS);
+    }
+    let total: u32 = configs.iter().map(|c| c.led_count as u32).sum();
+    if total > MAX_TOTAL_LEDS as u32 {
+        bail!(
+            "all strips together can have at most {} leds",
+            MAX_TOTAL_LEDS
+        );
+    }
+    for (i, config) in configs.iter().enumerate() {
+        config.check()?;
+        for other in &configs[..i] {
+            if let Some(pin) = config.pins().iter().find(|p| other.pins().contains(p)) {
+                bail!("gpio {} is used by more than one strip", pin);
+            }
+            let clocked = config.chipset.clocked().is_some();
+            if config.channel == other.channel && clocked == other.chipset.clocked().is_some() {
+                bail!("channel {} is used by more than one strip", config.channel);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sets up whatever drives `config.chipset`, see `Strip::from_config` and `Apa102::from_config`.
+#[cfg(feature = "esp")]
+pub fn from_config(config: &StripConfig) -> Result<Sink<'static>> {
+    Ok(match config.chipset.clocked() {
+        Some(clocked) => Box::new(Apa102::from_config(config, clocked)?),
+        None => Box::new(Strip::from_config(config)?),
+    })
+}
+
+/// All strips as one, in the order of `configs`.
+#[cfg(feature = "esp")]
+pub fn from_configs(configs: &[StripConfig]) -> Result<Sink<'static>> {
+    check_configs(configs)?;
+    let mut sink = MultiSink::default();
+    for config in configs {
+        sink.push(from_config(config)?, config.reversed);
+    }
+    Ok(Box::new(sink))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_in_wire_order() {
+        let pixel = [0x11, 0x22, 0x33, 0x44];
+        assert_eq!(LedColorOrder::RGB.pack(pixel), 0x11_22_33);
+        assert_eq!(LedColorOrder::GRB.pack(pixel), 0x22_11_33);
+        assert_eq!(LedColorOrder::BGR.pack(pixel), 0x33_22_11);
+        assert_eq!(LedColorOrder::RGBW.pack(pixel), 0x11_22_33_44);
+        assert_eq!(LedColorOrder::GRBW.pack(pixel), 0x22_11_33_44);
+        assert_eq!(LedColorOrder::GRB.bits(), 24);
+        assert_eq!(LedColorOrder::GRBW.bits(), 32);
+    }
+
+    #[test]
+    fn keeps_strips_off_the_console() {
+        assert!(StripConfig::default().check().is_ok());
+        for gpio in [1, 3] {
+            let config = StripConfig {
+                gpio,
+                ..Default::default()
+            };
+            assert!(config.check().is_err(), "gpio {} was let through", gpio);
+        }
+    }
+
+    #[test]
+    fn keeps_strips_off_the_strapping_pins() {
+        for gpio in [0, 12, 15] {
+            let config = StripConfig {
+                gpio,
+                ..Default::default()
+            };
+            assert!(config.check().is_err(), "gpio {} was let through", gpio);
+        }
+    }
 }
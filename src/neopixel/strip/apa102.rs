@@ -0,0 +1,100 @@
+//! APA102 and SK9822: strips with a clock line, driven over SPI.
+//!
+//! Every pixel takes a 5 bit global brightness on top of its three 8 bit channels. With `hdr` the
+//! smallest global brightness that still fits the brightest channel is picked per pixel and the
+//! channels are scaled up to make up for it, so dark colors keep far more than the handful of
+//! steps gamma correction leaves them. No dithering is needed then.
+//!
+//! On the APA102 the global brightness is a slow (~580 Hz) PWM on top of the fast one, which
+//! can show on camera. The SK9822 lowers the LED current instead, `hdr` is always fine there.
+
+use super::calibration::FULL;
+
+#[cfg(feature = "esp")]
+mod spi;
+
+#[cfg(feature = "esp")]
+pub use spi::Apa102;
+
+/// the largest global brightness, full current
+pub const MAX_GLOBAL: u32 = 31;
+
+/// `0b111` and the global brightness, then the 24 bit word
+pub fn push_pixel(frame: &mut Vec<u8>, global: u8, word: u32) {
+    frame.push(0xe0 | global);
+    frame.extend_from_slice(&word.to_be_bytes()[1..]);
+}
+
+/// The smallest global brightness the brightest channel fits into, and the channels scaled to it.
+pub fn split_global(fixed: [u16; 4]) -> (u8, [u8; 4]) {
+    let full = FULL as u32;
+    let max = fixed[..3].iter().cloned().max().unwrap_or(0) as u32;
+    let global = (max * MAX_GLOBAL).div_ceil(full).clamp(1, MAX_GLOBAL);
+    let scale = |v: u16| {
+        let divisor = global * full;
+        ((v as u32 * MAX_GLOBAL * 255 + divisor / 2) / divisor).min(255) as u8
+    };
+    (
+        global as u8,
+        [scale(fixed[0]), scale(fixed[1]), scale(fixed[2]), 0],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// what the LED shows for `global` and `channel`, back in 8.8 fixed point
+    fn shown(global: u8, channel: u8) -> u32 {
+        global as u32 * channel as u32 * FULL as u32 / (MAX_GLOBAL * 255)
+    }
+
+    fn assert_round_trips(fixed: [u16; 4]) {
+        let (global, channels) = split_global(fixed);
+        assert!(
+            global >= 1 && global as u32 <= MAX_GLOBAL,
+            "global {}",
+            global
+        );
+        for c in 0..3 {
+            let shown = shown(global, channels[c]);
+            assert!(
+                (shown as i32 - fixed[c] as i32).abs() <= 256,
+                "{:?} came back as {} on channel {}",
+                fixed,
+                shown,
+                c
+            );
+        }
+        assert_eq!(channels[3], 0);
+    }
+
+    #[test]
+    fn black_keeps_the_smallest_global() {
+        assert_eq!(split_global([0; 4]), (1, [0; 4]));
+    }
+
+    #[test]
+    fn full_scale_takes_full_global() {
+        assert_eq!(
+            split_global([FULL, FULL, FULL, 0]),
+            (MAX_GLOBAL as u8, [255, 255, 255, 0])
+        );
+    }
+
+    #[test]
+    fn dark_values_get_a_small_global_and_bright_channels() {
+        let (global, channels) = split_global([100, 300, 1000, 0]);
+        assert_eq!(global, 1);
+        // 1000 is below 4 of the 255 steps, with global 1 it gets 121 of them
+        assert_eq!(channels, [12, 36, 121, 0]);
+        assert_round_trips([100, 300, 1000, 0]);
+    }
+
+    #[test]
+    fn every_level_round_trips_within_one_step() {
+        for v in (0..=FULL).step_by(37).chain([FULL]) {
+            assert_round_trips([v, v / 2, v / 7, 0]);
+        }
+    }
+}
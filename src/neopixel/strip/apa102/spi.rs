@@ -0,0 +1,128 @@
+//! The SPI driver behind `Apa102`.
+
+use std::sync::Mutex;
+
+use anyhow::{bail, Result};
+use esp_idf_hal::{
+    gpio::{AnyIOPin, AnyOutputPin, OutputPin},
+    peripheral::Peripheral,
+    spi::{config::Config, Dma, SpiAnyPins, SpiDeviceDriver, SpiDriver, SPI2, SPI3},
+    units::Hertz,
+};
+
+use super::{
+    super::{
+        calibration::Calibration, color::default::Color, Clocked, LedColorOrder, LedSink,
+        StripConfig,
+    },
+    push_pixel, split_global, MAX_GLOBAL,
+};
+
+/// largest transfer the driver sets dma up for, longer frames are sent in pieces
+const MAX_TRANSFER: usize = 4092;
+
+pub struct Apa102<'d> {
+    spi: Mutex<SpiDeviceDriver<'d, SpiDriver<'d>>>,
+    led_count: u16,
+    /// wire order of the three channels, APA102 and SK9822 usually want BGR
+    order: LedColorOrder,
+    hdr: bool,
+    calibration: Mutex<Calibration>,
+    dither: bool,
+}
+
+impl<'d> Apa102<'d> {
+    pub fn new<SPI: SpiAnyPins>(
+        spi: impl Peripheral<P = SPI> + 'd,
+        clock: impl Peripheral<P = impl OutputPin> + 'd,
+        data: impl Peripheral<P = impl OutputPin> + 'd,
+        led_count: u16,
+        clock_hz: u32,
+        order: LedColorOrder,
+        hdr: bool,
+    ) -> Result<Self> {
+        let driver = SpiDriver::new(
+            spi,
+            clock,
+            data,
+            Option::<AnyIOPin>::None,
+            Dma::Auto(MAX_TRANSFER),
+        )?;
+        let config = Config::new().baudrate(Hertz(clock_hz));
+        let spi = SpiDeviceDriver::new(driver, Option::<AnyOutputPin>::None, &config)?;
+        Ok(Self {
+            spi: Mutex::new(spi),
+            led_count,
+            order,
+            hdr,
+            calibration: Mutex::new(Calibration::default()),
+            dither: true,
+        })
+    }
+}
+
+impl Apa102<'static> {
+    /// Takes both pins and the SPI host by their number, like `Strip::from_config` the
+    /// previous strip has to be dropped first.
+    pub fn from_config(config: &StripConfig, clocked: &Clocked) -> Result<Self> {
+        config.check()?;
+        let (count, hz, order, hdr) = (
+            config.led_count,
+            clocked.clock_hz,
+            config.order,
+            clocked.hdr,
+        );
+        // Safety: `check` only lets distinct output capable pins off the flash and the console and
+        // SPI2/3 through, and apart from strips nothing in the firmware touches those
+        let strip = unsafe {
+            let clock = AnyOutputPin::new(clocked.clock_gpio as i32);
+            let data = AnyOutputPin::new(config.gpio as i32);
+            match config.channel {
+                2 => Self::new(SPI2::new(), clock, data, count, hz, order, hdr),
+                3 => Self::new(SPI3::new(), clock, data, count, hz, order, hdr),
+                n => bail!("there is no SPI{} for strips", n),
+            }
+        }?;
+        Ok(Self {
+            dither: config.dither,
+            ..strip
+        })
+    }
+}
+
+impl<'d> LedSink for Apa102<'d> {
+    fn led_count(&self) -> u16 {
+        self.led_count
+    }
+
+    fn send_colors(&self, colors: &[Color]) -> Result<()> {
+        // the end frame clocks the data through to the last pixel, one bit per two pixels,
+        // the SK9822 also needs 32 more to latch this frame instead of with the next one
+        let end_len = 4 + (colors.len() + 15) / 16;
+        let mut frame = Vec::with_capacity(4 + colors.len() * 4 + end_len);
+        frame.extend_from_slice(&[0; 4]);
+
+        let mut calibration = self.calibration.lock().unwrap();
+        if self.hdr {
+            for color in colors {
+                let (global, pixel) = split_global(calibration.apply_fixed(color, false));
+                push_pixel(&mut frame, global, self.order.pack(pixel));
+            }
+        } else {
+            let mut pixels = Vec::with_capacity(colors.len());
+            calibration.apply_frame(colors, false, self.dither, &mut pixels);
+            for pixel in pixels {
+                push_pixel(&mut frame, MAX_GLOBAL as u8, self.order.pack(pixel));
+            }
+        }
+        drop(calibration);
+
+        frame.resize(frame.len() + end_len, 0);
+        self.spi.lock().unwrap().write(&frame)?;
+        Ok(())
+    }
+
+    fn set_calibration(&self, calibration: Calibration) {
+        *self.calibration.lock().unwrap() = calibration;
+    }
+}
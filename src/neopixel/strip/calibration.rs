@@ -0,0 +1,330 @@
+//! Gamma correction and white balance, the last step before a color goes on the wire.
+//!
+//! Effects work with linear values from 0 to 1, LEDs are far from linear: without a gamma curve
+//! fades jump at the dark end and mixed colors look washed out.
+//!
+//! The curve leaves only a few of the 256 steps for the dark end, so slow fades there still step.
+//! Dithering carries what rounding drops over to the next frame of the same pixel, so over a few
+//! frames the LED averages out to the value in between. Whether a strip dithers is up to its
+//! `StripConfig`, the shimmer shows more on some strips than on others.
+//!
+//! On RGBW strips the white LED takes over part of the mix first, see `WhiteExtraction`.
+
+use serde::{Deserialize, Serialize};
+
+use super::color::default::Color;
+
+const LUT_SIZE: usize = 1024;
+/// full scale of a corrected channel in 8.8 fixed point, the high byte is what the LED gets
+pub const FULL: u16 = 255 << 8;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CalibrationConfig {
+    /// 1 is linear, most LEDs look right somewhere between 2.2 and 2.8
+    pub gamma: f32,
+    /// white balance: how far each channel may light up, 0 to 1
+    pub red: f32,
+    pub green: f32,
+    pub blue: f32,
+    /// only used on strips with a white channel
+    pub white: WhiteExtraction,
+}
+
+/// How much of a color the white LED of an RGBW pixel shows instead of the RGB LEDs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum WhiteExtraction {
+    /// the white LED stays off
+    None,
+    /// the part all three channels share, for cold white LEDs
+    MinSubtract,
+    /// the part that matches the color of the white LED, e.g. 2700 for warm white
+    ColorTemperature { kelvin: u16 },
+}
+
+impl Default for CalibrationConfig {
+    fn default() -> Self {
+        Self {
+            gamma: 2.2,
+            red: 1.0,
+            green: 1.0,
+            blue: 1.0,
+            white: WhiteExtraction::MinSubtract,
+        }
+    }
+}
+
+impl WhiteExtraction {
+    /// the white LED in linear RGB, brightest channel at 1
+    fn white_point(&self) -> Option<[f32; 3]> {
+        match self {
+            WhiteExtraction::None => None,
+            WhiteExtraction::MinSubtract => Some([1.0, 1.0, 1.0]),
+            WhiteExtraction::ColorTemperature { kelvin } => Some(kelvin_to_rgb(*kelvin)),
+        }
+    }
+}
+
+/// `CalibrationConfig` turned into a lookup table, plus the dithering state of every pixel.
+#[derive(Debug, Clone)]
+pub struct Calibration {
+    lut: Vec<u16>,
+    /// 256 is 1, white is never trimmed
+    trim: [u32; 4],
+    white_point: Option<[f32; 3]>,
+    /// per pixel and channel, what rounding dropped last frame
+    residual: Vec<[u8; 4]>,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self::new(&CalibrationConfig::default())
+    }
+}
+
+impl Calibration {
+    pub fn new(config: &CalibrationConfig) -> Self {
+        let gamma = match config.gamma.is_finite() && config.gamma > 0.0 {
+            true => config.gamma,
+            false => 1.0,
+        };
+        let lut = (0..LUT_SIZE)
+            .map(|i| ((i as f32 / (LUT_SIZE - 1) as f32).powf(gamma) * FULL as f32).round() as u16)
+            .collect();
+        let trim = |t: f32| (t.clamp(0.0, 1.0) * 256.0).round() as u32;
+        Self {
+            lut,
+            trim: [trim(config.red), trim(config.green), trim(config.blue), 256],
+            white_point: config.white.white_point(),
+            residual: Vec::new(),
+        }
+    }
+
+    /// `color` split into linear red, green, blue and white
+    fn split(&self, color: &Color, white: bool) -> [f32; 4] {
+        let rgb = [color.red, color.green, color.blue].map(|v| v.clamp(0.0, 1.0));
+        let wp = match (white, self.white_point) {
+            (true, Some(wp)) => wp,
+            _ => return [rgb[0], rgb[1], rgb[2], 0.0],
+        };
+        let w = (0..3)
+            .filter(|c| wp[*c] > 0.0)
+            .map(|c| rgb[c] / wp[c])
+            .fold(1.0f32, f32::min);
+        [
+            rgb[0] - w * wp[0],
+            rgb[1] - w * wp[1],
+            rgb[2] - w * wp[2],
+            w,
+        ]
+    }
+
+    /// The corrected channels of `color` in 8.8 fixed point (see `FULL`),
+    /// white is 0 unless the strip has a `white` LED.
+    pub fn apply_fixed(&self, color: &Color, white: bool) -> [u16; 4] {
+        let split = self.split(color, white);
+        let mut fixed = [0u16; 4];
+        for (c, v) in split.iter().enumerate() {
+            let i = (v.clamp(0.0, 1.0) * (LUT_SIZE - 1) as f32 + 0.5) as usize;
+            fixed[c] = ((self.lut[i] as u32 * self.trim[c]) >> 8) as u16;
+        }
+        fixed
+    }
+
+    /// The corrected channels of `color`, rounded to what the LED takes.
+    pub fn apply(&self, color: &Color, white: bool) -> [u8; 4] {
+        self.apply_fixed(color, white).map(round)
+    }
+
+    /// The bytes for every pixel of `colors`, dithered if `dither`, into `out`.
+    /// `white` tells whether the strip has a white LED.
+    pub fn apply_frame(
+        &mut self,
+        colors: &[Color],
+        white: bool,
+        dither: bool,
+        out: &mut Vec<[u8; 4]>,
+    ) {
+        out.clear();
+        if !dither {
+            out.extend(colors.iter().map(|c| self.apply(c, white)));
+            return;
+        }
+        self.residual.resize(colors.len(), [0; 4]);
+        for (i, color) in colors.iter().enumerate() {
+            let fixed = self.apply_fixed(color, white);
+            let residual = &mut self.residual[i];
+            let mut pixel = [0u8; 4];
+            for (c, fixed) in fixed.iter().enumerate() {
+                let v = *fixed as u32 + residual[c] as u32;
+                match v >> 8 {
+                    high if high >= 255 => {
+                        pixel[c] = 255;
+                        residual[c] = 0;
+                    }
+                    high => {
+                        pixel[c] = high as u8;
+                        residual[c] = v as u8;
+                    }
+                }
+            }
+            out.push(pixel);
+        }
+    }
+}
+
+fn round(fixed: u16) -> u8 {
+    ((fixed as u32 + 0x80) >> 8).min(255) as u8
+}
+
+/// Tanner Helland's fit of the black body color in sRGB, made linear and scaled to a maximum of 1.
+fn kelvin_to_rgb(kelvin: u16) -> [f32; 3] {
+    let t = (kelvin.clamp(1000, 40000) as f32) / 100.0;
+    let red = match t <= 66.0 {
+        true => 255.0,
+        false => 329.699 * (t - 60.0).powf(-0.133_205),
+    };
+    let green = match t <= 66.0 {
+        true => 99.470_8 * t.ln() - 161.119_57,
+        false => 288.122_17 * (t - 60.0).powf(-0.075_514_85),
+    };
+    let blue = match t {
+        t if t >= 66.0 => 255.0,
+        t if t <= 19.0 => 0.0,
+        t => 138.517_73 * (t - 10.0).ln() - 305.044_8,
+    };
+    let rgb = [red, green, blue].map(|v| (v.clamp(0.0, 255.0) / 255.0).powf(2.2));
+    let max = rgb.iter().cloned().fold(0.0f32, f32::max);
+    rgb.map(|v| v / max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn linear() -> CalibrationConfig {
+        CalibrationConfig {
+            gamma: 1.0,
+            white: WhiteExtraction::None,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn gamma_1_is_the_identity() {
+        let calibration = Calibration::new(&linear());
+        for v in 0..=255 {
+            let color = Color::from_u8(v, v, v);
+            assert_eq!(calibration.apply(&color, false), [v, v, v, 0]);
+        }
+        assert_eq!(
+            calibration.apply_fixed(&Color::white(), false),
+            [FULL, FULL, FULL, 0]
+        );
+    }
+
+    #[test]
+    fn trims_scale_their_channel() {
+        let calibration = Calibration::new(&CalibrationConfig {
+            red: 0.5,
+            green: 1.0,
+            blue: 0.0,
+            ..linear()
+        });
+        assert_eq!(
+            calibration.apply_fixed(&Color::white(), false),
+            [FULL / 2, FULL, 0, 0]
+        );
+        assert_eq!(calibration.apply(&Color::white(), false), [128, 255, 0, 0]);
+    }
+
+    #[test]
+    fn dithering_averages_to_what_rounding_drops() {
+        const FRAMES: usize = 100;
+        let mut calibration = Calibration::default();
+        let color = Color {
+            red: 0.1,
+            green: 0.1,
+            blue: 0.1,
+        };
+        let fixed = calibration.apply_fixed(&color, false)[0];
+        assert_ne!(fixed % 256, 0, "{} has nothing to dither", fixed);
+        let wanted = fixed as f32 / 256.0;
+
+        let mut out = Vec::new();
+        let mut sum = 0;
+        for _ in 0..FRAMES {
+            calibration.apply_frame(&[color], false, true, &mut out);
+            sum += out[0][0] as u32;
+        }
+        let average = sum as f32 / FRAMES as f32;
+        assert!(
+            (average - wanted).abs() <= 1.0 / FRAMES as f32,
+            "averaged {}, wanted {}",
+            average,
+            wanted
+        );
+    }
+
+    fn close(a: [f32; 4], b: [f32; 4]) -> bool {
+        a.iter().zip(&b).all(|(a, b)| (a - b).abs() < 1e-3)
+    }
+
+    #[test]
+    fn min_subtract_moves_grey_to_the_white_led() {
+        let calibration = Calibration::new(&CalibrationConfig {
+            white: WhiteExtraction::MinSubtract,
+            ..linear()
+        });
+        let grey = Color {
+            red: 0.5,
+            green: 0.5,
+            blue: 0.5,
+        };
+        assert!(close(calibration.split(&grey, true), [0.0, 0.0, 0.0, 0.5]));
+        // the RGB LEDs only keep what goes beyond the shared part
+        let orange = Color {
+            red: 1.0,
+            green: 0.5,
+            blue: 0.25,
+        };
+        assert!(close(
+            calibration.split(&orange, true),
+            [0.75, 0.25, 0.0, 0.25]
+        ));
+        // saturated colors share nothing
+        assert!(close(
+            calibration.split(&Color::red(), true),
+            [1.0, 0.0, 0.0, 0.0]
+        ));
+        // and strips without a white LED get the color as it is
+        assert!(close(calibration.split(&grey, false), [0.5, 0.5, 0.5, 0.0]));
+    }
+
+    #[test]
+    fn warm_white_points_are_red_heavy() {
+        let [red, green, blue] = kelvin_to_rgb(2700);
+        assert_eq!(red, 1.0);
+        assert!(green < red && blue < green, "{:?}", [red, green, blue]);
+        // around 6600 K the fit is neutral
+        let daylight = kelvin_to_rgb(6600);
+        assert!(
+            daylight.iter().all(|c| (c - 1.0).abs() < 0.01),
+            "{:?}",
+            daylight
+        );
+    }
+
+    #[test]
+    fn color_temperature_takes_its_own_white_point_as_white() {
+        let calibration = Calibration::new(&CalibrationConfig {
+            white: WhiteExtraction::ColorTemperature { kelvin: 2700 },
+            ..linear()
+        });
+        let [red, green, blue] = kelvin_to_rgb(2700);
+        let warm = Color { red, green, blue };
+        assert!(close(calibration.split(&warm, true), [0.0, 0.0, 0.0, 1.0]));
+        // cold white is more than the warm LED alone can give, blue makes up for it
+        let split = calibration.split(&Color::white(), true);
+        assert!(close(split, [0.0, 1.0 - green, 1.0 - blue, 1.0]));
+    }
+}
@@ -4,13 +4,18 @@ pub mod i;
 pub mod f;
 pub use f as default;
 
+/// the lower `bits` bits of `word`, most significant first
+pub fn bit_iter(word: u32, bits: u8) -> impl Iterator<Item = bool> {
+    ColorBitString::new(word, bits)
+}
+
 struct ColorBitString {
     color_u32: u32,
     current_bit_pos: u8,
 }
 impl ColorBitString {
-    fn new(color_u32: u32) -> Self {
-        let current_bit_pos = 24;
+    fn new(color_u32: u32, bits: u8) -> Self {
+        let current_bit_pos = bits;
         Self {
             color_u32,
             current_bit_pos,
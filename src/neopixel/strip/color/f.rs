@@ -55,14 +55,9 @@ impl Color {
         }
     }
 
-    pub fn from_hex(hex: u32, order: &LedColorOrder) -> Self {
-        let red = ((hex >> 16) & 0xFF) as f32 / 255.0;
-        let green = ((hex >> 8) & 0xFF) as f32 / 255.0;
-        let blue = (hex & 0xFF) as f32 / 255.0;
-        match order {
-            LedColorOrder::RGB => Self::new(red, green, blue),
-            LedColorOrder::GRB => Self::new(green, red, blue),
-        }
+    /// `0xRRGGBB`, whatever order the strip wants its channels in
+    pub fn from_hex(hex: u32) -> Self {
+        Self::from_u8((hex >> 16) as u8, (hex >> 8) as u8, hex as u8)
     }
 
     pub fn black() -> Self {
@@ -186,18 +181,14 @@ impl Color {
         self
     }
 
-    /// Returns the color as a 24-bit RGB value.
+    /// Returns the color as it goes on the wire in `order`, uncalibrated and with white off.
     pub fn to_u32(&self, order: &LedColorOrder) -> u32 {
-        let (r, g, b) = match order {
-            LedColorOrder::RGB => (self.red, self.green, self.blue),
-            LedColorOrder::GRB => (self.green, self.red, self.blue),
-        };
-
-        (((r * 255.0) as u32) << 16) | (((g * 255.0) as u32) << 8) | (b * 255.0) as u32
+        let channel = |v: f32| (v.max(0.0).min(1.0) * 255.0) as u8;
+        order.pack([channel(self.red), channel(self.green), channel(self.blue), 0])
     }
 
     pub fn to_bit_iter(&self, order: &LedColorOrder) -> impl Iterator<Item = bool> + '_ {
-        ColorBitString::new(self.to_u32(order))
+        ColorBitString::new(self.to_u32(order), order.bits())
     }
 }
 
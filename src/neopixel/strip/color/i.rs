@@ -47,14 +47,12 @@ impl Color {
         }
     }
 
-    pub fn from_hex(hex: u32, order: &LedColorOrder) -> Self {
+    /// `0xRRGGBB`, whatever order the strip wants its channels in
+    pub fn from_hex(hex: u32) -> Self {
         let red:u8 = ((hex >> 16) & 0xFF).try_into().unwrap();
         let green:u8 = ((hex >> 8) & 0xFF).try_into().unwrap();
         let blue:u8 = (hex & 0xFF).try_into().unwrap();
-        match order {
-            LedColorOrder::RGB => Self::new(red, green, blue),
-            LedColorOrder::GRB => Self::new(green, red, blue),
-        }
+        Self::new(red, green, blue)
     }
 
     pub fn black() -> Self {
@@ -214,18 +212,13 @@ impl Color {
         self
     }
 
-    /// Returns the color as a 24-bit RGB value.
+    /// Returns the color as it goes on the wire in `order`, with white off.
     pub fn to_u32(&self, order: &LedColorOrder) -> u32 {
-        let (r, g, b) = match order {
-            LedColorOrder::RGB => (self.red, self.green, self.blue),
-            LedColorOrder::GRB => (self.green, self.red, self.blue),
-        };
-
-        ((r as u32) << 16) | ((g as u32) << 8) | b as u32
+        order.pack([self.red, self.green, self.blue, 0])
     }
 
     pub fn to_bit_iter(&self, order: &LedColorOrder) -> impl Iterator<Item = bool> + '_ {
-        ColorBitString::new(self.to_u32(order))
+        ColorBitString::new(self.to_u32(order), order.bits())
     }
 }
 
@@ -0,0 +1,99 @@
+//! Several physical strips as one: the first `led_count` pixels of a frame go to the first
+//! strip, the next ones to the second and so on.
+
+use anyhow::Result;
+
+use super::{calibration::Calibration, color::default::Color, LedSink};
+use crate::neopixel::Sink;
+
+struct Output<'a> {
+    sink: Sink<'a>,
+    reversed: bool,
+}
+
+#[derive(Default)]
+pub struct MultiSink<'a> {
+    outputs: Vec<Output<'a>>,
+}
+
+impl<'a> MultiSink<'a> {
+    /// Appends `sink` after the pixels of the strips pushed so far.
+    pub fn push(&mut self, sink: Sink<'a>, reversed: bool) {
+        self.outputs.push(Output { sink, reversed });
+    }
+}
+
+impl<'a> LedSink for MultiSink<'a> {
+    fn led_count(&self) -> u16 {
+        self.outputs.iter().map(|o| o.sink.led_count()).sum()
+    }
+
+    fn send_colors(&self, colors: &[Color]) -> Result<()> {
+        let mut start = 0;
+        let mut reversed = Vec::new();
+        let mut result = Ok(());
+        for output in &self.outputs {
+            let end = (start + output.sink.led_count() as usize).min(colors.len());
+            let part = &colors[start..end];
+            start = end;
+            let sent = match output.reversed {
+                true => {
+                    reversed.clear();
+                    reversed.extend(part.iter().rev().cloned());
+                    output.sink.send_colors(&reversed)
+                }
+                false => output.sink.send_colors(part),
+            };
+            // the strips after a failing one still get their part
+            if result.is_ok() {
+                result = sent;
+            }
+        }
+        result
+    }
+
+    fn set_calibration(&self, calibration: Calibration) {
+        for output in &self.outputs {
+            output.sink.set_calibration(calibration.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neopixel::strip::sim::SimStrip;
+
+    /// lends a `SimStrip` to the `MultiSink`, so its frames can still be looked at
+    struct Lent<'a>(&'a SimStrip);
+
+    impl LedSink for Lent<'_> {
+        fn led_count(&self) -> u16 {
+            self.0.led_count()
+        }
+
+        fn send_colors(&self, colors: &[Color]) -> Result<()> {
+            self.0.send_colors(colors)
+        }
+    }
+
+    #[test]
+    fn splits_the_frame_and_reverses_per_strip() {
+        let (first, second) = (SimStrip::new(3), SimStrip::new(4));
+        let mut multi = MultiSink::default();
+        multi.push(Box::new(Lent(&first)), false);
+        multi.push(Box::new(Lent(&second)), true);
+        assert_eq!(multi.led_count(), 7);
+
+        let frame: Vec<Color> = (0..7).map(|i| Color::from_u8(i * 10, 0, 0)).collect();
+        multi.send_colors(&frame).unwrap();
+        assert_eq!(first.last_frame().unwrap(), &frame[..3]);
+        let reversed: Vec<Color> = frame[3..].iter().rev().cloned().collect();
+        assert_eq!(second.last_frame().unwrap(), reversed);
+
+        // a short frame only reaches the strips it covers
+        multi.send_colors(&frame[..2]).unwrap();
+        assert_eq!(first.last_frame().unwrap(), &frame[..2]);
+        assert_eq!(second.last_frame().unwrap(), []);
+    }
+}
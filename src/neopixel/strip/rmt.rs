@@ -0,0 +1,208 @@
+//! One-wire strips (WS2812 and the like), driven by an rmt channel.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use esp_idf_hal::{
+    delay::Ets,
+    gpio::{AnyOutputPin, OutputPin, Pin},
+    peripheral::Peripheral,
+    rmt::{
+        config::TransmitConfig, PinState, Pulse, RmtChannel, TxRmtDriver, VariableLengthSignal,
+        CHANNEL0, CHANNEL1, CHANNEL2, CHANNEL3, CHANNEL4, CHANNEL5, CHANNEL6, CHANNEL7,
+    },
+};
+
+use super::{
+    calibration::Calibration, color, Chipset, LedColorOrder, LedSink, StripConfig, Timings,
+};
+
+pub struct Strip<'d> {
+    pub zero_high_ns: u16,
+    pub zero_low_ns: u16,
+    pub one_high_ns: u16,
+    pub one_low_ns: u16,
+    pub reset_ns: u16,
+    pub led_count: u16,
+    rmt: Arc<Mutex<TxRmtDriver<'d>>>,
+    pub led_color_order: LedColorOrder,
+    calibration: Mutex<Calibration>,
+    dither: bool,
+}
+
+#[allow(dead_code)]
+impl<'d> Strip<'d> {
+    pub fn ws2812b<T: Pin + OutputPin, C: RmtChannel>(
+        pin: impl Peripheral<P = T> + 'd,
+        rmt_channel: impl Peripheral<P = C> + 'd,
+        pixel_count: u16,
+    ) -> Self {
+        Self::new(
+            pin,
+            rmt_channel,
+            pixel_count,
+            Chipset::Ws2812b.timings().unwrap(),
+            LedColorOrder::GRB,
+        )
+        .unwrap()
+    }
+
+    /// RGBW, e.g. the warm white variants
+    pub fn sk6812_rgbw<T: Pin + OutputPin, C: RmtChannel>(
+        pin: impl Peripheral<P = T> + 'd,
+        rmt_channel: impl Peripheral<P = C> + 'd,
+        pixel_count: u16,
+    ) -> Self {
+        Self::new(
+            pin,
+            rmt_channel,
+            pixel_count,
+            Chipset::Sk6812.timings().unwrap(),
+            LedColorOrder::GRBW,
+        )
+        .unwrap()
+    }
+
+    pub fn ws2812<T: Pin + OutputPin, C: RmtChannel>(
+        pin: impl Peripheral<P = T> + 'd,
+        rmt_channel: impl Peripheral<P = C> + 'd,
+        pixel_count: u16,
+    ) -> Self {
+        Self::new(
+            pin,
+            rmt_channel,
+            pixel_count,
+            Chipset::Ws2812.timings().unwrap(),
+            LedColorOrder::GRB,
+        )
+        .unwrap()
+    }
+
+    pub fn custom<T: Pin + OutputPin, C: RmtChannel>(
+        pin: impl Peripheral<P = T> + 'd,
+        rmt_channel: impl Peripheral<P = C> + 'd,
+        pixel_count: u16,
+        zero_high_ns: u16,
+        zero_low_ns: u16,
+        one_high_ns: u16,
+        one_low_ns: u16,
+        reset_ns: u16,
+    ) -> Self {
+        let timings = Timings {
+            zero_high_ns,
+            zero_low_ns,
+            one_high_ns,
+            one_low_ns,
+            reset_ns,
+        };
+        Self::new(pin, rmt_channel, pixel_count, timings, LedColorOrder::GRB).unwrap()
+    }
+
+    pub fn new<T: Pin + OutputPin, C: RmtChannel>(
+        pin: impl Peripheral<P = T> + 'd,
+        rmt_channel: impl Peripheral<P = C> + 'd,
+        pixel_count: u16,
+        timings: Timings,
+        led_color_order: LedColorOrder,
+    ) -> Result<Self> {
+        let config = TransmitConfig::new().clock_divider(1);
+        let tx = Arc::new(Mutex::new(TxRmtDriver::new(rmt_channel, pin, &config)?));
+        Ok(Self {
+            zero_high_ns: timings.zero_high_ns,
+            zero_low_ns: timings.zero_low_ns,
+            one_high_ns: timings.one_high_ns,
+            one_low_ns: timings.one_low_ns,
+            reset_ns: timings.reset_ns,
+            led_count: pixel_count,
+            rmt: tx,
+            led_color_order,
+            calibration: Mutex::new(Calibration::default()),
+            dither: true,
+        })
+    }
+}
+
+impl Strip<'static> {
+    /// Takes the pin and rmt channel by their number, so whatever used them before,
+    /// usually the previous `Strip`, has to be dropped first.
+    pub fn from_config(config: &StripConfig) -> Result<Self> {
+        config.check()?;
+        let timings = match config.chipset.timings() {
+            Some(timings) => timings,
+            None => bail!("{:?} is not driven over rmt", config.chipset),
+        };
+        let (count, order) = (config.led_count, config.order);
+        // Safety: `check` only lets output capable pins off the flash and the console and existing
+        // channels through, and apart from strips nothing in the firmware touches those
+        let strip = unsafe {
+            let pin = AnyOutputPin::new(config.gpio as i32);
+            match config.channel {
+                0 => Self::new(pin, CHANNEL0::new(), count, timings, order),
+                1 => Self::new(pin, CHANNEL1::new(), count, timings, order),
+                2 => Self::new(pin, CHANNEL2::new(), count, timings, order),
+                3 => Self::new(pin, CHANNEL3::new(), count, timings, order),
+                4 => Self::new(pin, CHANNEL4::new(), count, timings, order),
+                5 => Self::new(pin, CHANNEL5::new(), count, timings, order),
+                6 => Self::new(pin, CHANNEL6::new(), count, timings, order),
+                7 => Self::new(pin, CHANNEL7::new(), count, timings, order),
+                n => bail!("there is no rmt channel {}", n),
+            }
+        }?;
+        Ok(Self {
+            dither: config.dither,
+            ..strip
+        })
+    }
+}
+
+impl<'d> LedSink for Strip<'d> {
+    fn led_count(&self) -> u16 {
+        self.led_count
+    }
+
+    fn send_colors(&self, colors: &[color::default::Color]) -> Result<()> {
+        let ticks_hz = self.rmt.lock().unwrap().counter_clock()?;
+        let t0h = Pulse::new_with_duration(ticks_hz, PinState::High, &ns(self.zero_high_ns))?;
+        let t0l = Pulse::new_with_duration(ticks_hz, PinState::Low, &ns(self.zero_low_ns))?;
+        let t1h = Pulse::new_with_duration(ticks_hz, PinState::High, &ns(self.one_high_ns))?;
+        let t1l = Pulse::new_with_duration(ticks_hz, PinState::Low, &ns(self.one_low_ns))?;
+        drop(ticks_hz);
+
+        let mut pixels = Vec::with_capacity(colors.len());
+        self.calibration.lock().unwrap().apply_frame(
+            colors,
+            self.led_color_order.has_white(),
+            self.dither,
+            &mut pixels,
+        );
+        let bits = self.led_color_order.bits();
+        let mut signal = VariableLengthSignal::with_capacity(bits as usize * colors.len());
+        for pixel in pixels {
+            // for bit in color.to_bit_iter(self.led_color_order) {
+            //     let (high_pulse, low_pulse) = if bit { (t1h, t1l) } else { (t0h, t0l) };
+            //     signal.push(&(high_pulse, low_pulse))?;
+            // }
+            let word = self.led_color_order.pack(pixel);
+            signal.push(
+                color::bit_iter(word, bits)
+                    .map(|bit| if bit { [&t1h, &t1l] } else { [&t0h, &t0l] })
+                    .flatten(),
+            )?;
+        }
+        // println!("colors: {:?}", colors);
+        self.rmt.clone().lock().unwrap().start(signal)?;
+        Ets::delay_us((self.reset_ns / 1000) as u32);
+        Ok(())
+    }
+
+    fn set_calibration(&self, calibration: Calibration) {
+        *self.calibration.lock().unwrap() = calibration;
+    }
+}
+
+fn ns(nanos: u16) -> Duration {
+    Duration::from_nanos(nanos as u64)
+}
@@ -0,0 +1,131 @@
+use std::{fmt::Write, sync::Mutex, time::Duration};
+
+use anyhow::Result;
+
+use super::{color::default::Color, LedSink};
+use crate::neopixel::{
+    layer::{self, Layer},
+    matrix::Matrix,
+    pixelmap::PixelMap,
+    segment::Segments,
+};
+
+/// A strip that only exists in memory.
+/// Every frame pushed through `send_colors` is recorded (and optionally drawn to the terminal),
+/// so the effect pipeline can be run without an ESP32 attached.
+#[allow(dead_code)]
+pub struct SimStrip {
+    pub led_count: u16,
+    /// only the newest `keep_frames` frames are kept, `None` keeps everything
+    pub keep_frames: Option<usize>,
+    /// print every frame as a row of ANSI truecolor blocks
+    pub render_to_terminal: bool,
+    frames: Mutex<Vec<Vec<Color>>>,
+}
+
+#[allow(dead_code)]
+impl SimStrip {
+    pub fn new(led_count: u16) -> Self {
+        Self {
+            led_count,
+            keep_frames: None,
+            render_to_terminal: false,
+            frames: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn keep_frames(mut self, n: usize) -> Self {
+        self.keep_frames = Some(n);
+        self
+    }
+
+    pub fn render_to_terminal(mut self, render: bool) -> Self {
+        self.render_to_terminal = render;
+        self
+    }
+
+    /// all recorded frames, oldest first
+    pub fn frames(&self) -> Vec<Vec<Color>> {
+        self.frames.lock().unwrap().clone()
+    }
+
+    /// removes and returns all recorded frames, oldest first
+    pub fn take_frames(&self) -> Vec<Vec<Color>> {
+        std::mem::take(&mut *self.frames.lock().unwrap())
+    }
+
+    pub fn last_frame(&self) -> Option<Vec<Color>> {
+        self.frames.lock().unwrap().last().cloned()
+    }
+}
+
+impl LedSink for SimStrip {
+    fn led_count(&self) -> u16 {
+        self.led_count
+    }
+
+    fn send_colors(&self, colors: &[Color]) -> Result<()> {
+        if self.render_to_terminal {
+            println!("{}", to_ansi(colors));
+        }
+        let mut frames = self.frames.lock().unwrap();
+        frames.push(colors.to_vec());
+        if let Some(keep) = self.keep_frames {
+            if frames.len() > keep {
+                let overflow = frames.len() - keep;
+                frames.drain(..overflow);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Renders a frame of `layers` into `sink` for every time in `frames`, counted from the first one,
+/// without threads or sleeping.
+/// `rt_start` is the wall clock of the first frame, `None` behaves like a device without time sync.
+#[allow(dead_code)]
+pub fn play(
+    layers: &[Layer],
+    segments: &Segments,
+    matrix: Option<&Matrix>,
+    pixel_map: Option<&PixelMap>,
+    sink: &dyn LedSink,
+    frames: impl IntoIterator<Item = Duration>,
+    rt_start: Option<Duration>,
+) -> Result<()> {
+    let mut colors = vec![Color::black(); sink.led_count() as usize];
+    for dt in frames {
+        layer::compose(
+            layers,
+            segments,
+            matrix,
+            pixel_map,
+            &mut colors,
+            dt,
+            rt_start.map(|rt| rt + dt),
+        )?;
+        sink.send_colors(&colors)?;
+    }
+    Ok(())
+}
+
+/// one truecolor block per pixel, values are clamped to 0..=255 like on the wire
+#[allow(dead_code)]
+pub fn to_ansi(colors: &[Color]) -> String {
+    let mut out = String::with_capacity(colors.len() * 24);
+    for c in colors {
+        let _ = write!(
+            out,
+            "\x1b[48;2;{};{};{}m ",
+            channel_u8(c.red),
+            channel_u8(c.green),
+            channel_u8(c.blue)
+        );
+    }
+    out.push_str("\x1b[0m");
+    out
+}
+
+pub(crate) fn channel_u8(v: f32) -> u8 {
+    (v.clamp(0.0, 1.0) * 255.0) as u8
+}
@@ -0,0 +1,542 @@
+//! The part of the WLED JSON API that maps onto our effect stack, so WLED apps and Home Assistant
+//! can control us.
+//!
+//! Every layer shows up as a WLED segment (segment id = index in the stack, 0 is the bottom) with
+//! the leds of its own segment, `fx` indexes `EFFECTS`, `sx`/`ix` map onto the effect's
+//! speed/intensity-like parameter and `bri` onto the layer's opacity. Moving a segment's
+//! `start`/`stop` points the layer at a plain segment over the new range, see `Segments::plain`.
+//! The state's `on` and `bri` are the output settings. Presets (`ps`) are numbered from 1 in the
+//! order of `GET /presets`, the state shows the one recalled last until the effects change.
+
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::store::DStore;
+
+use super::{
+    effects::{alarm, hue, invert, plasma, solid, spatial, strobo, EffectConfig},
+    layer::Layer,
+    presets,
+    segment::{self, Segments},
+    stack::{EffectStack, LayerPatch},
+    strip::{color::default::Color, sim::channel_u8},
+    NeopixelManager,
+};
+
+#[cfg(feature = "esp")]
+mod routes;
+
+#[cfg(feature = "esp")]
+pub use routes::add_routes;
+
+/// `fx` is the index into this list
+pub const EFFECTS: &[&str] = &[
+    "Solid",
+    "Colorloop",
+    "Strobe",
+    "Invert",
+    "Alarm",
+    "Plasma",
+    "Spatial Hue",
+];
+const FX_SOLID: u8 = 0;
+const FX_COLORLOOP: u8 = 1;
+const FX_STROBE: u8 = 2;
+const FX_INVERT: u8 = 3;
+const FX_ALARM: u8 = 4;
+const FX_PLASMA: u8 = 5;
+const FX_SPATIAL_HUE: u8 = 6;
+
+const MAX_HUE_DEGREES_PER_SECOND: f32 = 360.0;
+const MAX_HUE_DEGREES_PER_LED: f32 = 36.0;
+const MAX_STROBE_HZ: f32 = 20.0;
+const MAX_PLASMA_SPEED: f32 = 10.0;
+const MAX_PLASMA_SCALE: f32 = 2.0;
+const MAX_HUE_DEGREES_PER_UNIT: f32 = 360.0;
+
+#[derive(Serialize)]
+pub struct State {
+    on: bool,
+    bri: u8,
+    transition: u8,
+    ps: i32,
+    pl: i32,
+    lor: u8,
+    mainseg: usize,
+    seg: Vec<Segment>,
+}
+
+#[derive(Serialize)]
+struct Segment {
+    id: usize,
+    start: u16,
+    stop: u16,
+    len: u16,
+    grp: u8,
+    spc: u8,
+    of: u8,
+    on: bool,
+    frz: bool,
+    bri: u8,
+    cct: u8,
+    col: [[u8; 3]; 3],
+    fx: u8,
+    sx: u8,
+    ix: u8,
+    pal: u8,
+    sel: bool,
+    rev: bool,
+    mi: bool,
+}
+
+#[derive(Deserialize)]
+pub struct StateUpdate {
+    /// `true`, `false` or `"t"` to toggle
+    on: Option<Value>,
+    bri: Option<u8>,
+    ps: Option<i32>,
+    seg: Option<OneOrMany>,
+    /// answer with the new state instead of `{"success":true}`
+    #[serde(default)]
+    pub v: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(SegmentUpdate),
+    Many(Vec<SegmentUpdate>),
+}
+
+#[derive(Deserialize)]
+struct SegmentUpdate {
+    id: Option<usize>,
+    start: Option<u16>,
+    /// `0` removes the segment
+    stop: Option<u16>,
+    on: Option<bool>,
+    bri: Option<u8>,
+    col: Option<Vec<WledColor>>,
+    fx: Option<u8>,
+    sx: Option<u8>,
+    ix: Option<u8>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum WledColor {
+    Channels(Vec<u8>),
+    Hex(String),
+}
+
+impl WledColor {
+    fn to_color(&self) -> Option<Color> {
+        match self {
+            WledColor::Channels(c) if c.len() >= 3 => Some(Color::from_u8(c[0], c[1], c[2])),
+            WledColor::Hex(hex) => {
+                let rgb = u32::from_str_radix(hex.trim_start_matches('#'), 16).ok()?;
+                Some(Color::from_u8(
+                    (rgb >> 16) as u8,
+                    (rgb >> 8) as u8,
+                    rgb as u8,
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn scale_to_u8(value: f32, max: f32) -> u8 {
+    (value / max * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+fn scale_from_u8(value: u8, max: f32) -> f32 {
+    value as f32 / 255.0 * max
+}
+
+fn fx_of(effect: &EffectConfig) -> u8 {
+    match effect {
+        EffectConfig::SolidColor(_) => FX_SOLID,
+        EffectConfig::HueShift(_) => FX_COLORLOOP,
+        EffectConfig::Strobo(_) => FX_STROBE,
+        EffectConfig::Invert(_) => FX_INVERT,
+        EffectConfig::Alarm(_) => FX_ALARM,
+        EffectConfig::Plasma(_) => FX_PLASMA,
+        EffectConfig::SpatialHue(_) => FX_SPATIAL_HUE,
+    }
+}
+
+/// `(sx, ix)` of `effect`
+fn speed_intensity_of(effect: &EffectConfig) -> (u8, u8) {
+    match effect {
+        EffectConfig::HueShift(c) => (
+            scale_to_u8(c.degrees_per_second, MAX_HUE_DEGREES_PER_SECOND),
+            scale_to_u8(c.degrees_per_led, MAX_HUE_DEGREES_PER_LED),
+        ),
+        EffectConfig::Strobo(c) => (scale_to_u8(c.frequency_hz, MAX_STROBE_HZ), 128),
+        EffectConfig::SpatialHue(c) => (
+            scale_to_u8(c.degrees_per_second, MAX_HUE_DEGREES_PER_SECOND),
+            scale_to_u8(c.degrees_per_unit, MAX_HUE_DEGREES_PER_UNIT),
+        ),
+        EffectConfig::Plasma(c) => (
+            scale_to_u8(c.speed, MAX_PLASMA_SPEED),
+            scale_to_u8(c.scale, MAX_PLASMA_SCALE),
+        ),
+        _ => (128, 128),
+    }
+}
+
+fn segment_of(index: usize, layer: &Layer, leds: &segment::Segment) -> Segment {
+    let color = match &layer.effect {
+        EffectConfig::SolidColor(c) => c.color,
+        _ => Color::black(),
+    };
+    let (sx, ix) = speed_intensity_of(&layer.effect);
+    Segment {
+        id: index,
+        start: leds.start,
+        stop: leds.end(),
+        len: leds.len,
+        grp: leds.grouping.min(255) as u8,
+        spc: leds.spacing.min(255) as u8,
+        of: 0,
+        on: layer.enabled,
+        frz: false,
+        bri: scale_to_u8(layer.opacity, 1.0),
+        cct: 127,
+        col: [
+            [
+                channel_u8(color.red),
+                channel_u8(color.green),
+                channel_u8(color.blue),
+            ],
+            [0; 3],
+            [0; 3],
+        ],
+        fx: fx_of(&layer.effect),
+        sx,
+        ix,
+        pal: 0,
+        sel: true,
+        rev: leds.reverse,
+        mi: leds.mirror,
+    }
+}
+
+/// The effect `update` turns `current` into (or a fresh one if there is no `current`).
+fn updated_effect(current: Option<&EffectConfig>, update: &SegmentUpdate) -> EffectConfig {
+    let fx = update.fx.or_else(|| current.map(fx_of)).unwrap_or(FX_SOLID);
+    let color = update
+        .col
+        .as_ref()
+        .and_then(|cols| cols.first())
+        .and_then(|c| c.to_color());
+
+    // parameters the client did not send keep their exact value, not a round trip through u8
+    match (fx, current) {
+        (FX_COLORLOOP, Some(EffectConfig::HueShift(c))) => {
+            EffectConfig::HueShift(hue::HueShiftConfig {
+                degrees_per_second: update.sx.map_or(c.degrees_per_second, |sx| {
+                    scale_from_u8(sx, MAX_HUE_DEGREES_PER_SECOND)
+                }),
+                degrees_per_led: update.ix.map_or(c.degrees_per_led, |ix| {
+                    scale_from_u8(ix, MAX_HUE_DEGREES_PER_LED)
+                }),
+            })
+        }
+        (FX_COLORLOOP, _) => EffectConfig::HueShift(hue::HueShiftConfig {
+            degrees_per_second: scale_from_u8(update.sx.unwrap_or(128), MAX_HUE_DEGREES_PER_SECOND),
+            degrees_per_led: scale_from_u8(update.ix.unwrap_or(128), MAX_HUE_DEGREES_PER_LED),
+        }),
+        (FX_STROBE, current) => {
+            let frequency_hz = match (update.sx, current) {
+                (Some(sx), _) => scale_from_u8(sx, MAX_STROBE_HZ),
+                (None, Some(EffectConfig::Strobo(c))) => c.frequency_hz,
+                (None, _) => scale_from_u8(128, MAX_STROBE_HZ),
+            };
+            EffectConfig::Strobo(strobo::StroboConfig { frequency_hz })
+        }
+        (FX_INVERT, _) => EffectConfig::Invert(invert::InversionConfig {}),
+        (FX_ALARM, Some(EffectConfig::Alarm(c))) => EffectConfig::Alarm(c.clone()),
+        (FX_ALARM, _) => EffectConfig::Alarm(alarm::AlarmConfig::default()),
+        (FX_PLASMA, Some(EffectConfig::Plasma(c))) => EffectConfig::Plasma(plasma::PlasmaConfig {
+            speed: update
+                .sx
+                .map_or(c.speed, |sx| scale_from_u8(sx, MAX_PLASMA_SPEED)),
+            scale: update
+                .ix
+                .map_or(c.scale, |ix| scale_from_u8(ix, MAX_PLASMA_SCALE)),
+        }),
+        (FX_PLASMA, _) => EffectConfig::Plasma(plasma::PlasmaConfig {
+            speed: scale_from_u8(update.sx.unwrap_or(128), MAX_PLASMA_SPEED),
+            scale: scale_from_u8(update.ix.unwrap_or(128), MAX_PLASMA_SCALE),
+        }),
+        // WLED has no notion of the field, it stays what it was
+        (FX_SPATIAL_HUE, current) => {
+            let c = match current {
+                Some(EffectConfig::SpatialHue(c)) => c.clone(),
+                _ => spatial::SpatialHueConfig::default(),
+            };
+            EffectConfig::SpatialHue(spatial::SpatialHueConfig {
+                degrees_per_second: update.sx.map_or(c.degrees_per_second, |sx| {
+                    scale_from_u8(sx, MAX_HUE_DEGREES_PER_SECOND)
+                }),
+                degrees_per_unit: update.ix.map_or(c.degrees_per_unit, |ix| {
+                    scale_from_u8(ix, MAX_HUE_DEGREES_PER_UNIT)
+                }),
+                ..c
+            })
+        }
+        (_, current) => {
+            let color = color.unwrap_or_else(|| match current {
+                Some(EffectConfig::SolidColor(c)) => c.color,
+                _ => Color::white(),
+            });
+            EffectConfig::SolidColor(solid::SolidColorConfig { color })
+        }
+    }
+}
+
+/// The segment a layer on `current` ends up on when `update` moves its `start` or `stop`,
+/// `None` if it stays where it is.
+fn moved_segment(
+    segments: &mut Segments,
+    current: u32,
+    update: &SegmentUpdate,
+    led_count: u16,
+) -> Option<u32> {
+    if update.start.is_none() && update.stop.is_none() {
+        return None;
+    }
+    let leds = segments.get(current, led_count)?;
+    let start = update.start.unwrap_or(leds.start).min(led_count);
+    let stop = update.stop.unwrap_or_else(|| leds.end()).min(led_count);
+    if (start, stop) == (leds.start, leds.end()) || start >= stop {
+        return None;
+    }
+    Some(segments.plain(start..stop, led_count))
+}
+
+fn apply_segment(
+    stack: &mut EffectStack,
+    segments: &mut Segments,
+    index: usize,
+    update: &SegmentUpdate,
+    led_count: u16,
+) {
+    let current = stack
+        .layers()
+        .get(index)
+        .map(|l| (l.id, l.segment, l.effect.clone()));
+    match current {
+        Some((id, _, _)) if update.stop == Some(0) => {
+            stack.remove(id);
+        }
+        Some((id, segment, effect)) => {
+            stack.patch(
+                id,
+                LayerPatch {
+                    segment: moved_segment(segments, segment, update, led_count),
+                    effect: Some(updated_effect(Some(&effect), update)),
+                    opacity: update.bri.map(|bri| scale_from_u8(bri, 1.0)),
+                    blend: None,
+                    enabled: update.on,
+                },
+            );
+        }
+        None if update.stop == Some(0) => {}
+        None => {
+            let mut layer = Layer::new(updated_effect(None, update));
+            if let Some(segment) = moved_segment(segments, layer.segment, update, led_count) {
+                layer.segment = segment;
+            }
+            if let Some(bri) = update.bri {
+                layer.opacity = scale_from_u8(bri, 1.0);
+            }
+            layer.enabled = update.on.unwrap_or(true);
+            stack.insert(None, layer);
+        }
+    }
+}
+
+/// the WLED number of the preset the effects still are, -1 for none
+fn preset_of(nm: &NeopixelManager, store: &Mutex<DStore>) -> i32 {
+    let revision = nm.effects.lock().unwrap().revision();
+    let name = match nm.recalled.lock().unwrap().clone() {
+        Some((at, name)) if at == revision => name,
+        _ => return -1,
+    };
+    match presets::list(&store.lock().unwrap()) {
+        Ok(names) => names
+            .iter()
+            .position(|n| *n == name)
+            .map_or(-1, |i| i as i32 + 1),
+        Err(_) => -1,
+    }
+}
+
+pub fn state_of(nm: &NeopixelManager, store: &Mutex<DStore>) -> State {
+    let output = nm.output.lock().unwrap().clone();
+    let led_count = nm.led_count();
+    let stack = nm.effects.lock().unwrap();
+    let segments = nm.segments.lock().unwrap();
+    let seg = stack
+        .layers()
+        .iter()
+        .enumerate()
+        .map(|(index, layer)| {
+            // segment ids are stack indices, a layer on a segment that is gone still needs one
+            let leds = segments
+                .get(layer.segment, led_count)
+                .or_else(|| segments.get(segment::WHOLE_STRIP, led_count))
+                .unwrap();
+            segment_of(index, layer, &leds)
+        })
+        .collect();
+    drop(segments);
+    drop(stack);
+    State {
+        on: output.on,
+        bri: scale_to_u8(output.brightness, 1.0),
+        transition: 0,
+        ps: preset_of(nm, store),
+        pl: -1,
+        lor: 0,
+        mainseg: 0,
+        seg,
+    }
+}
+
+/// Applies `update`, storing whatever it changed. `Err` is meant for the client.
+pub fn apply_update(
+    nm: &NeopixelManager,
+    store: &Mutex<DStore>,
+    update: StateUpdate,
+) -> anyhow::Result<()> {
+    if let Some(ps) = update.ps.filter(|ps| *ps > 0) {
+        let names = presets::list(&store.lock().unwrap())?;
+        let name = match names.get(ps as usize - 1) {
+            Some(name) => name,
+            None => anyhow::bail!("no preset {}", ps),
+        };
+        let preset = presets::load(&store.lock().unwrap(), name)?;
+        match preset {
+            Some(preset) => presets::recall(nm, store, name, preset)?,
+            None => anyhow::bail!("no preset {}", ps),
+        }
+    }
+
+    if update.on.is_some() || update.bri.is_some() {
+        let mut output = nm.output.lock().unwrap();
+        match update.on {
+            Some(Value::Bool(on)) => output.on = on,
+            Some(Value::String(ref t)) if t == "t" => output.on = !output.on,
+            _ => {}
+        }
+        if let Some(bri) = update.bri {
+            output.brightness = scale_from_u8(bri, 1.0);
+        }
+        if let Err(e) = store.lock().unwrap().set("output", &*output) {
+            anyhow::bail!("couldn't store output settings: {:?}", e);
+        }
+    }
+
+    if let Some(seg) = update.seg {
+        let updates = match seg {
+            OneOrMany::One(update) => vec![update],
+            OneOrMany::Many(updates) => updates,
+        };
+        let led_count = nm.led_count();
+        let mut stack = nm.effects.lock().unwrap();
+        let mut segments = nm.segments.lock().unwrap();
+        let segment_count = segments.list().len();
+        let mut indexed: Vec<(usize, SegmentUpdate)> = updates
+            .into_iter()
+            .enumerate()
+            .map(|(i, update)| (update.id.unwrap_or(i), update))
+            .collect();
+        indexed.sort_by_key(|(index, _)| *index);
+        let (removals, changes): (Vec<_>, Vec<_>) =
+            indexed.into_iter().partition(|(_, u)| u.stop == Some(0));
+        for (index, update) in &changes {
+            apply_segment(&mut stack, &mut segments, *index, update, led_count);
+        }
+        // removing shifts the indices above, so go from the top down
+        for (index, update) in removals.iter().rev() {
+            apply_segment(&mut stack, &mut segments, *index, update, led_count);
+        }
+        let mut store = store.lock().unwrap();
+        // new segments first, so the stored layers never point at a missing one
+        if segments.list().len() != segment_count {
+            if let Err(e) = store.set("segments", &segments.list().to_vec()) {
+                anyhow::bail!("segments changed but could not be stored: {:?}", e);
+            }
+        }
+        if let Err(e) = store.set("effects", &stack.layers().to_vec()) {
+            anyhow::bail!("effects changed but could not be stored: {:?}", e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        neopixel::{output::OutputConfig, presets::Preset, strip::sim::SimStrip},
+        store,
+    };
+
+    fn preset(effect: &str, brightness: f32) -> Preset {
+        Preset {
+            effects: vec![Layer::new(EffectConfig::default_named(effect).unwrap())],
+            output: OutputConfig {
+                brightness,
+                on: true,
+            },
+            calibration: None,
+        }
+    }
+
+    fn update(update: Value) -> StateUpdate {
+        serde_json::from_value(update).unwrap()
+    }
+
+    #[test]
+    fn ps_recalls_the_preset_and_shows_it_until_the_effects_change() {
+        let nm = NeopixelManager::new(Box::new(SimStrip::new(10)));
+        let store = Mutex::new(store::default());
+        presets::save(&mut store.lock().unwrap(), "calm", &preset("HueShift", 0.3)).unwrap();
+        presets::save(&mut store.lock().unwrap(), "party", &preset("Plasma", 0.8)).unwrap();
+
+        apply_update(&nm, &store, update(json!({ "ps": 2 }))).unwrap();
+        let names: Vec<_> = nm
+            .effects
+            .lock()
+            .unwrap()
+            .layers()
+            .iter()
+            .map(|l| l.effect.name())
+            .collect();
+        assert_eq!(names, vec!["Plasma"]);
+        assert_eq!(nm.output.lock().unwrap().brightness, 0.8);
+        assert_eq!(state_of(&nm, &store).ps, 2);
+        // what a restart plays
+        let stored: Vec<Layer> = store.lock().unwrap().get("effects").unwrap().unwrap();
+        assert_eq!(stored[0].effect.name(), "Plasma");
+
+        apply_update(
+            &nm,
+            &store,
+            update(json!({ "seg": [{ "id": 0, "fx": 0 }] })),
+        )
+        .unwrap();
+        assert_eq!(state_of(&nm, &store).ps, -1);
+
+        assert!(apply_update(&nm, &store, update(json!({ "ps": 3 }))).is_err());
+    }
+}
@@ -0,0 +1,188 @@
+//! The HTTP side of the WLED API, and `/json/info` which only the board can answer.
+
+use std::{
+    sync::{mpsc::Sender, Arc, Mutex},
+    time::Instant,
+};
+
+use embedded_svc::io::Write;
+use log::info;
+use serde::Serialize;
+
+use crate::{
+    add_new_route,
+    connection::{ConnectionRelevantEvent, NetworkInfo},
+    handler_bail, handler_soft_bail, match_parsed_json, parse_req_or_fail_with_message,
+    send_as_json,
+    store::DStore,
+};
+
+use super::{
+    super::{realtime::Source, strip::StripConfig, NeopixelManager},
+    apply_update, state_of, State, StateUpdate, EFFECTS,
+};
+
+/// the version we claim to be, clients check it to decide which fields they may use
+const WLED_VERSION: &str = "0.14.0";
+const WLED_VID: u32 = 2310130;
+
+#[derive(Serialize)]
+struct Info {
+    ver: &'static str,
+    vid: u32,
+    leds: Leds,
+    name: String,
+    udpport: u16,
+    live: bool,
+    lm: &'static str,
+    lip: String,
+    ws: i8,
+    fxcount: usize,
+    palcount: usize,
+    arch: &'static str,
+    freeheap: u32,
+    uptime: u64,
+    brand: &'static str,
+    product: &'static str,
+    mac: String,
+    ip: String,
+}
+
+#[derive(Serialize)]
+struct Leds {
+    count: u16,
+    rgbw: bool,
+    wv: u8,
+    pwr: u32,
+    maxpwr: u32,
+    maxseg: usize,
+    /// only there on a matrix, 2D capable clients look for it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    matrix: Option<MatrixSize>,
+}
+
+#[derive(Serialize)]
+struct MatrixSize {
+    w: u16,
+    h: u16,
+}
+
+fn info_of(
+    nm: &NeopixelManager,
+    network: &NetworkInfo,
+    strips: &[StripConfig],
+    started: Instant,
+) -> Info {
+    let (live, lm, lip) = match nm.realtime.status() {
+        Some((Source::E131(_), _)) => (true, "E1.31", String::new()),
+        Some((Source::ArtNet(ip), _)) => (true, "Art-Net", ip.to_string()),
+        Some((Source::Ddp(ip), _)) => (true, "DDP", ip.to_string()),
+        None => (false, "", String::new()),
+    };
+    Info {
+        ver: WLED_VERSION,
+        vid: WLED_VID,
+        leds: Leds {
+            count: nm.led_count(),
+            rgbw: strips.iter().any(|s| s.order.has_white()),
+            wv: 0,
+            pwr: 0,
+            maxpwr: 0,
+            maxseg: 32,
+            matrix: nm.matrix.lock().unwrap().as_ref().map(|m| MatrixSize {
+                w: m.width(),
+                h: m.height(),
+            }),
+        },
+        name: network.hostname.clone(),
+        udpport: 0,
+        live,
+        lm,
+        lip,
+        ws: -1,
+        fxcount: EFFECTS.len(),
+        palcount: 1,
+        arch: "esp32",
+        freeheap: unsafe { esp_idf_sys::esp_get_free_heap_size() },
+        uptime: started.elapsed().as_secs(),
+        brand: "WLED",
+        product: env!("CARGO_PKG_NAME"),
+        mac: network.mac.iter().map(|b| format!("{:02x}", b)).collect(),
+        ip: network.ip.map(|ip| ip.to_string()).unwrap_or_default(),
+    }
+}
+
+#[derive(Serialize)]
+struct Everything {
+    state: State,
+    info: Info,
+    effects: &'static [&'static str],
+    palettes: &'static [&'static str],
+}
+
+pub fn add_routes(
+    tx: &Sender<ConnectionRelevantEvent>,
+    nm: Arc<NeopixelManager<'static>>,
+    store: Arc<Mutex<DStore>>,
+    network: Arc<Mutex<NetworkInfo>>,
+    strip_config: Arc<Mutex<Vec<StripConfig>>>,
+) {
+    let started = Instant::now();
+
+    let (nm2, store2, network2, strips2) = (
+        nm.clone(),
+        store.clone(),
+        network.clone(),
+        strip_config.clone(),
+    );
+    add_new_route!(tx; "/json", Get, move |req| {
+        let network = network2.lock().unwrap().clone();
+        let strips = strips2.lock().unwrap().clone();
+        let everything = Everything {
+            state: state_of(&nm2, &store2),
+            info: info_of(&nm2, &network, &strips, started),
+            effects: EFFECTS,
+            palettes: &["Default"],
+        };
+        send_as_json!(req, everything)
+    });
+
+    let (nm2, store2) = (nm.clone(), store.clone());
+    add_new_route!(tx; "/json/state", Get, move |req| {
+        let state = state_of(&nm2, &store2);
+        send_as_json!(req, state)
+    });
+
+    let (nm2, network2) = (nm.clone(), network);
+    add_new_route!(tx; "/json/info", Get, move |req| {
+        let strips = strip_config.lock().unwrap().clone();
+        let info = info_of(&nm2, &network2.lock().unwrap(), &strips, started);
+        send_as_json!(req, info)
+    });
+
+    add_new_route!(tx; "/json/effects", Get, move |req| {
+        send_as_json!(req, EFFECTS)
+    });
+
+    add_new_route!(tx; "/json/palettes", Get, move |req| {
+        send_as_json!(req, ["Default"])
+    });
+
+    // WLED accepts state changes on both
+    for uri in &["/json", "/json/state"] {
+        let (nm2, store2) = (nm.clone(), store.clone());
+        add_new_route!(tx; *uri, Post, move |mut req| {
+            let update: StateUpdate = parse_req_or_fail_with_message!(req; "couldn't parse state.. {}");
+            let answer_with_state = update.v;
+            if let Err(e) = apply_update(&nm2, &store2, update) {
+                handler_soft_bail!(req; "{}", e)
+            }
+            if answer_with_state {
+                let state = state_of(&nm2, &store2);
+                send_as_json!(req, state)
+            } else {
+                send_as_json!(req, serde_json::json!({ "success": true }))
+            }
+        });
+    }
+}
@@ -0,0 +1,116 @@
+//! Realtime pixel protocols, every listener feeds `NeopixelManager::realtime`.
+
+use std::ops::Range;
+
+use crate::neopixel::strip::color::default::Color;
+
+pub mod artnet;
+pub mod ddp;
+pub mod e131;
+
+pub const CHANNELS_PER_UNIVERSE: usize = 512;
+pub const PIXELS_PER_UNIVERSE: usize = CHANNELS_PER_UNIVERSE / 3;
+
+/// Where the strip is patched in DMX space.
+///
+/// Pixels are 3 channels each and never span two universes: the first universe starts at
+/// `start_channel`, every following one holds 170 pixels from channel 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DmxPatch {
+    pub start_universe: u16,
+    /// DMX channel of the first pixel's red in `start_universe`, 1 to 510
+    pub start_channel: u16,
+}
+
+impl DmxPatch {
+    fn first_channel_offset(&self) -> usize {
+        (self.start_channel.max(1) as usize - 1).min(CHANNELS_PER_UNIVERSE - 3)
+    }
+
+    fn pixels_in_first_universe(&self) -> usize {
+        (CHANNELS_PER_UNIVERSE - self.first_channel_offset()) / 3
+    }
+
+    /// the universes needed to address `led_count` pixels
+    pub fn universes(&self, led_count: usize) -> Range<u16> {
+        let rest = led_count.saturating_sub(self.pixels_in_first_universe());
+        let count = 1 + rest.div_ceil(PIXELS_PER_UNIVERSE);
+        self.start_universe..self.start_universe.saturating_add(count as u16)
+    }
+
+    /// `(first pixel, channel offset)` of `universe`, `None` if it is not one of ours
+    fn placement(&self, universe: u16) -> Option<(usize, usize)> {
+        let index = universe.checked_sub(self.start_universe)? as usize;
+        match index {
+            0 => Some((0, self.first_channel_offset())),
+            _ => Some((
+                self.pixels_in_first_universe() + (index - 1) * PIXELS_PER_UNIVERSE,
+                0,
+            )),
+        }
+    }
+
+    /// Writes the DMX `data` of `universe` into `frame`.
+    pub fn write_universe(&self, universe: u16, data: &[u8], frame: &mut [Color]) {
+        let (first_pixel, offset) = match self.placement(universe) {
+            Some(placement) => placement,
+            None => return,
+        };
+        let data = match data.get(offset..) {
+            Some(data) => data,
+            None => return,
+        };
+        let pixels = frame.iter_mut().skip(first_pixel);
+        for (pixel, rgb) in pixels.zip(data.chunks_exact(3)) {
+            *pixel = Color::from_u8(rgb[0], rgb[1], rgb[2]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patch(start_universe: u16, start_channel: u16) -> DmxPatch {
+        DmxPatch {
+            start_universe,
+            start_channel,
+        }
+    }
+
+    #[test]
+    fn universes_hold_170_pixels() {
+        assert_eq!(patch(1, 1).universes(170), 1..2);
+        assert_eq!(patch(1, 1).universes(171), 1..3);
+        // 169 pixels fit behind channel 4
+        assert_eq!(patch(5, 4).universes(169), 5..6);
+        assert_eq!(patch(5, 4).universes(170), 5..7);
+        assert_eq!(patch(5, 4).universes(169 + 340), 5..8);
+    }
+
+    #[test]
+    fn pixels_spill_into_the_next_universe_from_channel_1() {
+        // room for one pixel at channels 508 to 510, 511 and 512 stay unused
+        let patch = patch(1, 508);
+        assert_eq!(patch.universes(3), 1..3);
+        let mut first = [0u8; CHANNELS_PER_UNIVERSE];
+        first[507..].copy_from_slice(&[10, 20, 30, 40, 50]);
+        let second = [1, 2, 3, 4, 5, 6, 7];
+
+        let mut frame = [Color::black(); 4];
+        patch.write_universe(1, &first, &mut frame);
+        patch.write_universe(2, &second, &mut frame);
+        // not ours
+        patch.write_universe(0, &second, &mut frame);
+        patch.write_universe(3, &[9, 9, 9], &mut frame);
+        assert_eq!(
+            frame,
+            [
+                Color::from_u8(10, 20, 30),
+                Color::from_u8(1, 2, 3),
+                Color::from_u8(4, 5, 6),
+                Color::black(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,369 @@
+//! Art-Net 4 node: answers `ArtPoll`, takes `ArtDmx` for our universes and honours `ArtSync`.
+//!
+//! Universes are 15 bit port addresses (net, sub-net and universe combined), see `DmxPatch` for how
+//! they map onto the strip.
+
+use std::{
+    net::Ipv4Addr,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    common::network::NetworkInfo,
+    neopixel::{
+        realtime::{Realtime, Source},
+        strip::color::default::Color,
+    },
+};
+
+use super::DmxPatch;
+
+#[cfg(feature = "esp")]
+mod listener;
+#[cfg(feature = "esp")]
+pub use listener::start;
+
+pub const PORT: u16 = 0x1936;
+
+const ID: &[u8; 8] = b"Art-Net\0";
+const OP_POLL: u16 = 0x2000;
+const OP_POLL_REPLY: u16 = 0x2100;
+const OP_DMX: u16 = 0x5000;
+const OP_SYNC: u16 = 0x5200;
+const PROTOCOL_VERSION: u16 = 14;
+
+const POLL_REPLY_LEN: usize = 239;
+const PORTS_PER_REPLY: usize = 4;
+/// Art-Net has no priorities, use the sACN default so neither protocol wins over the other
+const PRIORITY: u8 = 100;
+/// without an `ArtSync` for this long frames are shown as they come in again
+const SYNC_TIMEOUT: Duration = Duration::from_secs(4);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArtNetConfig {
+    pub enabled: bool,
+    /// port address of the first pixel, 0 to 32767
+    pub start_universe: u16,
+    /// DMX channel of the first pixel's red in `start_universe`, 1 to 510
+    pub start_channel: u16,
+    /// fall back to the effect stack when no data came in for this long
+    pub timeout_ms: u32,
+}
+
+impl Default for ArtNetConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            start_universe: 0,
+            start_channel: 1,
+            timeout_ms: 2500,
+        }
+    }
+}
+
+impl ArtNetConfig {
+    pub fn patch(&self) -> DmxPatch {
+        DmxPatch {
+            start_universe: self.start_universe & 0x7fff,
+            start_channel: self.start_channel,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Packet<'a> {
+    Poll,
+    Dmx { universe: u16, data: &'a [u8] },
+    Sync,
+}
+
+/// Parses the Art-Net packets a node has to handle, `None` for everything else.
+pub fn parse(packet: &[u8]) -> Option<Packet<'_>> {
+    if packet.len() < 12 || &packet[..8] != ID {
+        return None;
+    }
+    let opcode = u16::from_le_bytes([packet[8], packet[9]]);
+    if u16::from_be_bytes([packet[10], packet[11]]) < PROTOCOL_VERSION {
+        return None;
+    }
+    match opcode {
+        OP_POLL => Some(Packet::Poll),
+        OP_SYNC => Some(Packet::Sync),
+        OP_DMX if packet.len() >= 18 => {
+            let len = u16::from_be_bytes([packet[16], packet[17]]) as usize;
+            Some(Packet::Dmx {
+                universe: u16::from_le_bytes([packet[14], packet[15]]) & 0x7fff,
+                data: packet.get(18..18 + len)?,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// One `ArtPollReply` per 4 universes (`bind_index` counts from 1), all of them have to share net
+/// and sub-net, so `universes` must not cross a multiple of 16.
+pub fn poll_reply(
+    network: &NetworkInfo,
+    universes: &[u16],
+    bind_index: u8,
+    active: bool,
+) -> [u8; POLL_REPLY_LEN] {
+    let mut out = [0u8; POLL_REPLY_LEN];
+    let ip = network.ip.unwrap_or(Ipv4Addr::UNSPECIFIED).octets();
+    let first = universes.first().copied().unwrap_or_default();
+
+    out[..8].copy_from_slice(ID);
+    out[8..10].copy_from_slice(&OP_POLL_REPLY.to_le_bytes());
+    out[10..14].copy_from_slice(&ip);
+    out[14..16].copy_from_slice(&PORT.to_le_bytes());
+    // firmware version
+    out[16..18].copy_from_slice(&1u16.to_be_bytes());
+    out[18] = (first >> 8) as u8 & 0x7f;
+    out[19] = (first >> 4) as u8 & 0x0f;
+    // Oem unknown
+    out[20..22].copy_from_slice(&0x00ffu16.to_be_bytes());
+    // indicators normal, port addresses set over the network
+    out[23] = 0xe0;
+
+    copy_str(&mut out[26..44], &network.hostname);
+    let long_name = match network.ssid.is_empty() {
+        true => network.hostname.clone(),
+        false => format!("{} on {}", network.hostname, network.ssid),
+    };
+    copy_str(&mut out[44..108], &long_name);
+    copy_str(&mut out[108..172], "#0001 [0000] ok");
+
+    out[172..174].copy_from_slice(&(universes.len() as u16).to_be_bytes());
+    for (port, universe) in universes.iter().take(PORTS_PER_REPLY).enumerate() {
+        // output, DMX512
+        out[174 + port] = 0x80;
+        // data is being transmitted
+        out[182 + port] = if active { 0x80 } else { 0x00 };
+        out[190 + port] = (universe & 0x0f) as u8;
+    }
+    // StNode
+    out[200] = 0x00;
+    out[201..207].copy_from_slice(&network.mac);
+    out[207..211].copy_from_slice(&ip);
+    out[211] = bind_index;
+    // 15 bit port addresses
+    out[212] = 0x08;
+    out
+}
+
+fn copy_str(field: &mut [u8], s: &str) {
+    // keep the terminating nul
+    let len = s.len().min(field.len() - 1);
+    field[..len].copy_from_slice(&s.as_bytes()[..len]);
+}
+
+/// Groups `universes` into the ports of consecutive `ArtPollReply`s.
+pub fn reply_groups(universes: std::ops::Range<u16>) -> Vec<Vec<u16>> {
+    let mut groups: Vec<Vec<u16>> = Vec::new();
+    for universe in universes {
+        match groups.last_mut() {
+            Some(group) if group.len() < PORTS_PER_REPLY && group[0] >> 4 == universe >> 4 => {
+                group.push(universe)
+            }
+            _ => groups.push(vec![universe]),
+        }
+    }
+    groups
+}
+
+/// `ArtSync` state: once a controller sends it, its frames are only shown on the next sync.
+struct Synced {
+    controller: Ipv4Addr,
+    last_sync: Instant,
+    frame: Vec<Color>,
+}
+
+/// What the node does with `ArtDmx` and `ArtSync`, `ArtPoll` is answered by the listener.
+#[derive(Default)]
+pub struct Node {
+    synced: Option<Synced>,
+}
+
+impl Node {
+    /// Hands `packet` from `controller` to `realtime`, or keeps it for the next `ArtSync`.
+    pub fn receive(
+        &mut self,
+        controller: Ipv4Addr,
+        packet: &Packet,
+        config: &ArtNetConfig,
+        realtime: &Realtime,
+        led_count: usize,
+        now: Instant,
+    ) {
+        let timeout = Duration::from_millis(config.timeout_ms as u64);
+        match *packet {
+            Packet::Poll => {}
+            Packet::Dmx { universe, data } => {
+                if let Some(s) = self.synced.as_mut() {
+                    if now.saturating_duration_since(s.last_sync) > SYNC_TIMEOUT {
+                        self.synced = None;
+                    } else if s.controller == controller {
+                        s.frame.resize(led_count, Color::black());
+                        config.patch().write_universe(universe, data, &mut s.frame);
+                        return;
+                    }
+                }
+                realtime.submit(
+                    Source::ArtNet(controller),
+                    PRIORITY,
+                    timeout,
+                    led_count,
+                    |frame| config.patch().write_universe(universe, data, frame),
+                );
+            }
+            Packet::Sync => {
+                let s = match self.synced.as_mut() {
+                    Some(s) if now.saturating_duration_since(s.last_sync) <= SYNC_TIMEOUT => s,
+                    // frames up to now were shown right away, buffer from here on
+                    _ => {
+                        self.synced = Some(Synced {
+                            controller,
+                            last_sync: now,
+                            frame: vec![Color::black(); led_count],
+                        });
+                        return;
+                    }
+                };
+                if s.controller != controller {
+                    return;
+                }
+                s.last_sync = now;
+                let frame = &s.frame;
+                realtime.submit(
+                    Source::ArtNet(controller),
+                    PRIORITY,
+                    timeout,
+                    led_count,
+                    |out| {
+                        let len = out.len().min(frame.len());
+                        out[..len].copy_from_slice(&frame[..len]);
+                    },
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTROLLER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+
+    fn header(opcode: u16) -> Vec<u8> {
+        let mut packet = ID.to_vec();
+        packet.extend_from_slice(&opcode.to_le_bytes());
+        packet.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+        packet
+    }
+
+    fn dmx(universe: u16, data: &[u8]) -> Vec<u8> {
+        let mut packet = header(OP_DMX);
+        // sequence and physical port
+        packet.extend_from_slice(&[0, 0]);
+        packet.extend_from_slice(&universe.to_le_bytes());
+        packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    #[test]
+    fn parses_what_a_node_handles() {
+        assert!(matches!(parse(&header(OP_POLL)), Some(Packet::Poll)));
+        assert!(matches!(parse(&header(OP_SYNC)), Some(Packet::Sync)));
+        let packet = dmx(0x8123, &[1, 2, 3]);
+        match parse(&packet) {
+            Some(Packet::Dmx { universe, data }) => {
+                assert_eq!(universe, 0x0123);
+                assert_eq!(data, [1, 2, 3]);
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(parse(&packet[..packet.len() - 1]).is_none());
+        assert!(parse(&header(OP_POLL_REPLY)).is_none());
+        let mut old = header(OP_POLL);
+        old[11] = 13;
+        assert!(parse(&old).is_none());
+    }
+
+    #[test]
+    fn art_sync_latches_frames() {
+        let (mut node, realtime) = (Node::default(), Realtime::new());
+        let config = ArtNetConfig::default();
+        let start = Instant::now();
+        let mut receive = |packet: &[u8], after: u64| {
+            let packet = parse(packet).unwrap();
+            let now = start + Duration::from_secs(after);
+            node.receive(CONTROLLER, &packet, &config, &realtime, 1, now);
+            let mut frame = [Color::black()];
+            realtime.render_into(&mut frame);
+            frame[0]
+        };
+        let red = |red| dmx(0, &[red, 0, 0]);
+
+        // shown right away until the controller syncs
+        assert_eq!(receive(&red(1), 0), Color::from_u8(1, 0, 0));
+        assert_eq!(receive(&header(OP_SYNC), 0), Color::from_u8(1, 0, 0));
+        assert_eq!(receive(&red(2), 1), Color::from_u8(1, 0, 0));
+        assert_eq!(receive(&header(OP_SYNC), 1), Color::from_u8(2, 0, 0));
+        // the controller stopped syncing
+        assert_eq!(receive(&red(3), 6), Color::from_u8(3, 0, 0));
+    }
+
+    #[test]
+    fn syncs_of_other_controllers_are_ignored() {
+        let (mut node, realtime) = (Node::default(), Realtime::new());
+        let config = ArtNetConfig::default();
+        let now = Instant::now();
+        let other = Ipv4Addr::new(10, 0, 0, 3);
+        let sync = header(OP_SYNC);
+        let sync = parse(&sync).unwrap();
+        node.receive(CONTROLLER, &sync, &config, &realtime, 1, now);
+        let packet = dmx(0, &[1, 2, 3]);
+        node.receive(
+            CONTROLLER,
+            &parse(&packet).unwrap(),
+            &config,
+            &realtime,
+            1,
+            now,
+        );
+        node.receive(other, &sync, &config, &realtime, 1, now);
+        assert_eq!(realtime.status(), None);
+        node.receive(CONTROLLER, &sync, &config, &realtime, 1, now);
+        assert_eq!(
+            realtime.status(),
+            Some((Source::ArtNet(CONTROLLER), PRIORITY))
+        );
+    }
+
+    #[test]
+    fn replies_group_universes_by_sub_net() {
+        assert_eq!(
+            reply_groups(14..21),
+            [vec![14, 15], vec![16, 17, 18, 19], vec![20]]
+        );
+
+        let network = NetworkInfo {
+            ip: Some(Ipv4Addr::new(10, 0, 0, 7)),
+            hostname: "espoxi".into(),
+            ..Default::default()
+        };
+        let reply = poll_reply(&network, &[0x123, 0x124], 2, true);
+        assert_eq!(&reply[10..14], [10, 0, 0, 7]);
+        // net, sub-net and the universe of every port
+        assert_eq!((reply[18], reply[19]), (0x01, 0x02));
+        assert_eq!(&reply[172..174], [0, 2]);
+        assert_eq!(&reply[190..194], [3, 4, 0, 0]);
+        assert_eq!(&reply[182..186], [0x80, 0x80, 0, 0]);
+        assert_eq!(&reply[26..33], b"espoxi\0");
+        assert_eq!(reply[211], 2);
+    }
+}
@@ -0,0 +1,124 @@
+//! The socket side of Art-Net: answering `ArtPoll` and the `/artnet` routes.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    sync::{mpsc::Sender, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use embedded_svc::io::Write;
+use log::{info, warn};
+
+use crate::{
+    add_new_route,
+    connection::{ConnectionRelevantEvent, NetworkInfo},
+    handler_bail, handler_soft_bail, match_parsed_json,
+    neopixel::NeopixelManager,
+    parse_req_or_fail_with_message, send_as_json,
+    store::DStore,
+};
+
+use super::{parse, poll_reply, reply_groups, ArtNetConfig, Node, Packet, PORT};
+
+const STORE_KEY: &str = "artnet";
+
+/// Loads the stored config, adds the `/artnet` routes and starts listening.
+pub fn start(
+    nm: Arc<NeopixelManager<'static>>,
+    store: Arc<Mutex<DStore>>,
+    network: Arc<Mutex<NetworkInfo>>,
+    tx: &Sender<ConnectionRelevantEvent>,
+) -> Result<()> {
+    let config = match store.lock().unwrap().get::<ArtNetConfig>(STORE_KEY) {
+        Ok(Some(config)) => config,
+        Ok(None) => ArtNetConfig::default(),
+        Err(e) => {
+            warn!("Stored art-net config could not be loaded: {}", e);
+            ArtNetConfig::default()
+        }
+    };
+    let config = Arc::new(Mutex::new(config));
+    add_routes(tx, config.clone(), store);
+
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, PORT))?;
+    socket.set_broadcast(true)?;
+    socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+    thread::Builder::new()
+        .stack_size(6 * 1024)
+        .spawn(move || listen(socket, nm, config, network))?;
+    info!("Listening for art-net on port {}", PORT);
+    Ok(())
+}
+
+fn listen(
+    socket: UdpSocket,
+    nm: Arc<NeopixelManager<'static>>,
+    config: Arc<Mutex<ArtNetConfig>>,
+    network: Arc<Mutex<NetworkInfo>>,
+) {
+    let mut buf = vec![0u8; 18 + 512];
+    let mut node = Node::default();
+    loop {
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            // read timeout
+            Err(_) => continue,
+        };
+        let current = config.lock().unwrap().clone();
+        if !current.enabled {
+            continue;
+        }
+        let controller = match from.ip() {
+            IpAddr::V4(ip) => ip,
+            IpAddr::V6(_) => continue,
+        };
+        let led_count = nm.led_count() as usize;
+
+        match parse(&buf[..len]) {
+            Some(Packet::Poll) => {
+                let network = network.lock().unwrap().clone();
+                let active = nm.realtime.status().is_some();
+                let groups = reply_groups(current.patch().universes(led_count));
+                for (index, universes) in groups.iter().enumerate() {
+                    let reply = poll_reply(&network, universes, index as u8 + 1, active);
+                    let to = SocketAddr::new(IpAddr::V4(controller), PORT);
+                    if let Err(e) = socket.send_to(&reply, to) {
+                        warn!("Failed to send ArtPollReply to {}: {}", to, e);
+                    }
+                }
+            }
+            Some(packet) => node.receive(
+                controller,
+                &packet,
+                &current,
+                &nm.realtime,
+                led_count,
+                Instant::now(),
+            ),
+            None => {}
+        }
+    }
+}
+
+fn add_routes(
+    tx: &Sender<ConnectionRelevantEvent>,
+    config: Arc<Mutex<ArtNetConfig>>,
+    store: Arc<Mutex<DStore>>,
+) {
+    let config2 = config.clone();
+    add_new_route!(tx; "/artnet", Get, move |req| {
+        let config = config2.lock().unwrap().clone();
+        send_as_json!(req, config)
+    });
+
+    add_new_route!(tx; "/artnet", Post, move |mut req| {
+        let new_config: ArtNetConfig = parse_req_or_fail_with_message!(req; "couldn't parse art-net config.. {}");
+        if let Err(e) = store.lock().unwrap().set(STORE_KEY, &new_config) {
+            handler_soft_bail!(req; "couldn't store art-net config: {:?}", e)
+        }
+        *config.lock().unwrap() = new_config;
+        send_as_json!(req, "ok")
+    });
+}
@@ -0,0 +1,220 @@
+//! Distributed Display Protocol receiver.
+//!
+//! DDP addresses the strip as one flat RGB byte buffer, so there is no universe mapping: packets
+//! write at their byte offset and the frame is shown once a packet with the push flag arrives.
+
+use std::{net::Ipv4Addr, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::neopixel::{
+    realtime::{Realtime, Source},
+    strip::color::default::Color,
+};
+
+#[cfg(feature = "esp")]
+mod listener;
+#[cfg(feature = "esp")]
+pub use listener::start;
+
+pub const PORT: u16 = 4048;
+
+const HEADER_LEN: usize = 10;
+const TIMECODE_LEN: usize = 4;
+const VERSION_MASK: u8 = 0xc0;
+const VERSION_1: u8 = 0x40;
+const FLAG_TIMECODE: u8 = 0x10;
+const FLAG_QUERY: u8 = 0x02;
+const FLAG_PUSH: u8 = 0x01;
+
+const DATA_TYPE_UNDEFINED: u8 = 0x00;
+/// what most senders put in for RGB, although the spec would call it "RGB, 1 bit"
+const DATA_TYPE_RGB_LEGACY: u8 = 0x01;
+const DATA_TYPE_RGB8: u8 = 0x0b;
+const ID_DEFAULT_OUTPUT: u8 = 1;
+const ID_ALL: u8 = 255;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DdpConfig {
+    pub enabled: bool,
+    /// the realtime priority of DDP sources, sACN uses 100 by default
+    pub priority: u8,
+    /// fall back to the effect stack when no data came in for this long
+    pub timeout_ms: u32,
+}
+
+impl Default for DdpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            priority: 100,
+            timeout_ms: 2500,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DataPacket<'a> {
+    pub push: bool,
+    /// byte offset into the RGB buffer
+    pub offset: usize,
+    pub data: &'a [u8],
+}
+
+/// Parses a DDP v1 RGB data packet for our display, `None` for anything else.
+pub fn parse(packet: &[u8]) -> Option<DataPacket<'_>> {
+    if packet.len() < HEADER_LEN {
+        return None;
+    }
+    let flags = packet[0];
+    if flags & VERSION_MASK != VERSION_1 || flags & FLAG_QUERY != 0 {
+        return None;
+    }
+    match packet[2] {
+        DATA_TYPE_UNDEFINED | DATA_TYPE_RGB_LEGACY | DATA_TYPE_RGB8 => {}
+        _ => return None,
+    }
+    match packet[3] {
+        ID_DEFAULT_OUTPUT | ID_ALL => {}
+        _ => return None,
+    }
+    let offset = u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]) as usize;
+    let len = u16::from_be_bytes([packet[8], packet[9]]) as usize;
+    let start = match flags & FLAG_TIMECODE != 0 {
+        true => HEADER_LEN + TIMECODE_LEN,
+        false => HEADER_LEN,
+    };
+    Some(DataPacket {
+        push: flags & FLAG_PUSH != 0,
+        offset,
+        data: packet.get(start..start + len)?,
+    })
+}
+
+/// Copies `packet` into the RGB byte buffer `pending`, dropping whatever lies past its end.
+pub fn write_into(pending: &mut [u8], packet: &DataPacket) {
+    if packet.offset >= pending.len() {
+        return;
+    }
+    let len = packet.data.len().min(pending.len() - packet.offset);
+    pending[packet.offset..packet.offset + len].copy_from_slice(&packet.data[..len]);
+}
+
+/// What the listener does with a data packet once it is parsed.
+#[derive(Debug, Default)]
+pub struct Receiver {
+    /// the frame being assembled until the next push, as RGB bytes
+    pending: Vec<u8>,
+    sender: Option<Ipv4Addr>,
+}
+
+impl Receiver {
+    /// Writes `packet` into the pending frame, which is handed to `realtime` on push.
+    pub fn receive(
+        &mut self,
+        from: Ipv4Addr,
+        packet: &DataPacket,
+        config: &DdpConfig,
+        realtime: &Realtime,
+        led_count: usize,
+    ) {
+        // a different sender starts from a black frame instead of finishing someone else's
+        if self.sender != Some(from) {
+            self.sender = Some(from);
+            self.pending.clear();
+        }
+        self.pending.resize(led_count * 3, 0);
+        write_into(&mut self.pending, packet);
+        if !packet.push {
+            return;
+        }
+        let pending = &self.pending;
+        realtime.submit(
+            Source::Ddp(from),
+            config.priority,
+            Duration::from_millis(config.timeout_ms as u64),
+            led_count,
+            |frame| {
+                for (pixel, rgb) in frame.iter_mut().zip(pending.chunks_exact(3)) {
+                    *pixel = Color::from_u8(rgb[0], rgb[1], rgb[2]);
+                }
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SENDER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+
+    fn packet(flags: u8, offset: u32, data: &[u8]) -> Vec<u8> {
+        let mut packet = vec![VERSION_1 | flags, 0, DATA_TYPE_RGB8, ID_DEFAULT_OUTPUT];
+        packet.extend_from_slice(&offset.to_be_bytes());
+        packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        if flags & FLAG_TIMECODE != 0 {
+            packet.extend_from_slice(&[9; TIMECODE_LEN]);
+        }
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    /// the 2 pixels `realtime` shows, `None` if nothing
+    fn shown(realtime: &Realtime) -> Option<[Color; 2]> {
+        realtime.status()?;
+        let mut frame = [Color::black(); 2];
+        realtime.render_into(&mut frame);
+        Some(frame)
+    }
+
+    #[test]
+    fn parses_rgb_data_for_our_display() {
+        let bytes = packet(FLAG_PUSH | FLAG_TIMECODE, 300, &[1, 2, 3]);
+        let parsed = parse(&bytes).unwrap();
+        assert!(parsed.push);
+        assert_eq!((parsed.offset, parsed.data), (300, &[1, 2, 3][..]));
+
+        assert!(parse(&bytes[..bytes.len() - 1]).is_none());
+        let mut query = packet(FLAG_QUERY, 0, &[]);
+        assert!(parse(&query).is_none());
+        query[0] = VERSION_1;
+        query[3] = 2;
+        assert!(parse(&query).is_none());
+    }
+
+    #[test]
+    fn frames_are_shown_on_push() {
+        let (mut receiver, realtime) = (Receiver::default(), Realtime::new());
+        let config = DdpConfig::default();
+        let mut receive = |from, bytes: &[u8]| {
+            let packet = parse(bytes).unwrap();
+            receiver.receive(from, &packet, &config, &realtime, 2);
+        };
+
+        // the second pixel first, starting in its green, and past the end of the strip
+        receive(SENDER, &packet(0, 4, &[2, 3, 4, 5, 6, 7]));
+        assert_eq!(shown(&realtime), None);
+        receive(SENDER, &packet(FLAG_PUSH, 0, &[1, 2, 3, 4]));
+        assert_eq!(
+            shown(&realtime),
+            Some([Color::from_u8(1, 2, 3), Color::from_u8(4, 2, 3)])
+        );
+
+        // another sender does not finish this frame
+        receive(Ipv4Addr::new(10, 0, 0, 3), &packet(0, 0, &[9, 9, 9]));
+        receive(SENDER, &packet(FLAG_PUSH, 3, &[5, 5, 5]));
+        assert_eq!(
+            shown(&realtime),
+            Some([Color::black(), Color::from_u8(5, 5, 5)])
+        );
+    }
+
+    #[test]
+    fn offsets_past_the_strip_are_dropped() {
+        let mut pending = [0u8; 6];
+        let bytes = packet(0, 6, &[1, 2, 3]);
+        write_into(&mut pending, &parse(&bytes).unwrap());
+        assert_eq!(pending, [0; 6]);
+    }
+}
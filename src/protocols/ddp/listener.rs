@@ -0,0 +1,100 @@
+//! The socket side of DDP and the `/ddp` routes.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, UdpSocket},
+    sync::{mpsc::Sender, Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use anyhow::Result;
+use embedded_svc::io::Write;
+use log::{info, warn};
+
+use crate::{
+    add_new_route, connection::ConnectionRelevantEvent, handler_bail, handler_soft_bail,
+    match_parsed_json, neopixel::NeopixelManager, parse_req_or_fail_with_message, send_as_json,
+    store::DStore,
+};
+
+use super::{parse, DdpConfig, Receiver, PORT};
+
+const STORE_KEY: &str = "ddp";
+
+/// Loads the stored config, adds the `/ddp` routes and starts listening.
+pub fn start(
+    nm: Arc<NeopixelManager<'static>>,
+    store: Arc<Mutex<DStore>>,
+    tx: &Sender<ConnectionRelevantEvent>,
+) -> Result<()> {
+    let config = match store.lock().unwrap().get::<DdpConfig>(STORE_KEY) {
+        Ok(Some(config)) => config,
+        Ok(None) => DdpConfig::default(),
+        Err(e) => {
+            warn!("Stored ddp config could not be loaded: {}", e);
+            DdpConfig::default()
+        }
+    };
+    let config = Arc::new(Mutex::new(config));
+    add_routes(tx, config.clone(), store);
+
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, PORT))?;
+    socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+    thread::Builder::new()
+        .stack_size(6 * 1024)
+        .spawn(move || listen(socket, nm, config))?;
+    info!("Listening for ddp on port {}", PORT);
+    Ok(())
+}
+
+fn listen(socket: UdpSocket, nm: Arc<NeopixelManager<'static>>, config: Arc<Mutex<DdpConfig>>) {
+    let mut buf = vec![0u8; 1500];
+    let mut receiver = Receiver::default();
+    loop {
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            // read timeout
+            Err(_) => continue,
+        };
+        let current = config.lock().unwrap().clone();
+        if !current.enabled {
+            continue;
+        }
+        let from = match from.ip() {
+            IpAddr::V4(ip) => ip,
+            IpAddr::V6(_) => continue,
+        };
+        let packet = match parse(&buf[..len]) {
+            Some(packet) => packet,
+            None => continue,
+        };
+        receiver.receive(
+            from,
+            &packet,
+            &current,
+            &nm.realtime,
+            nm.led_count() as usize,
+        );
+    }
+}
+
+fn add_routes(
+    tx: &Sender<ConnectionRelevantEvent>,
+    config: Arc<Mutex<DdpConfig>>,
+    store: Arc<Mutex<DStore>>,
+) {
+    let config2 = config.clone();
+    add_new_route!(tx; "/ddp", Get, move |req| {
+        let config = config2.lock().unwrap().clone();
+        send_as_json!(req, config)
+    });
+
+    add_new_route!(tx; "/ddp", Post, move |mut req| {
+        let new_config: DdpConfig = parse_req_or_fail_with_message!(req; "couldn't parse ddp config.. {}");
+        if let Err(e) = store.lock().unwrap().set(STORE_KEY, &new_config) {
+            handler_soft_bail!(req; "couldn't store ddp config: {:?}", e)
+        }
+        *config.lock().unwrap() = new_config;
+        send_as_json!(req, "ok")
+    });
+}
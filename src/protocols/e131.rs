@@ -0,0 +1,319 @@
+//! E1.31 (streaming ACN) receiver, see `DmxPatch` for how universes map onto the strip.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::neopixel::realtime::{Realtime, Source};
+
+use super::DmxPatch;
+
+#[cfg(feature = "esp")]
+mod listener;
+#[cfg(feature = "esp")]
+pub use listener::start;
+
+pub const PORT: u16 = 5568;
+
+const ACN_PACKET_IDENTIFIER: &[u8; 12] = b"ASC-E1.17\0\0\0";
+const VECTOR_ROOT_E131_DATA: u32 = 0x0000_0004;
+const VECTOR_E131_DATA_PACKET: u32 = 0x0000_0002;
+const DATA_OFFSET: usize = 126;
+const OPTION_PREVIEW_DATA: u8 = 0x80;
+const OPTION_STREAM_TERMINATED: u8 = 0x40;
+/// a packet this close behind the last one of its stream is late, further back the source restarted
+const SEQUENCE_WINDOW: i8 = 20;
+/// streams (source and universe) whose sequence numbers are followed at once
+const MAX_STREAMS: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct E131Config {
+    pub enabled: bool,
+    /// universe of the first pixel, 1 to 63999
+    pub start_universe: u16,
+    /// DMX channel of the first pixel's red in `start_universe`, 1 to 510
+    pub start_channel: u16,
+    /// sources below this priority are ignored
+    pub min_priority: u8,
+    /// fall back to the effect stack when no data came in for this long (E1.31 specifies 2.5s)
+    pub timeout_ms: u32,
+    /// join the multicast groups of our universes, otherwise only unicast is received
+    pub multicast: bool,
+}
+
+impl Default for E131Config {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            start_universe: 1,
+            start_channel: 1,
+            min_priority: 0,
+            timeout_ms: 2500,
+            multicast: true,
+        }
+    }
+}
+
+impl E131Config {
+    pub fn patch(&self) -> DmxPatch {
+        DmxPatch {
+            start_universe: self.start_universe,
+            start_channel: self.start_channel,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DataPacket<'a> {
+    pub cid: [u8; 16],
+    pub priority: u8,
+    pub sequence: u8,
+    pub options: u8,
+    pub universe: u16,
+    /// DMX slots without the start code
+    pub data: &'a [u8],
+}
+
+/// Parses an E1.31 data packet, `None` for anything else (including non-zero start codes).
+pub fn parse(packet: &[u8]) -> Option<DataPacket<'_>> {
+    if packet.len() < DATA_OFFSET
+        || packet[0..2] != [0x00, 0x10]
+        || &packet[4..16] != ACN_PACKET_IDENTIFIER
+        || be_u32(&packet[18..22]) != VECTOR_ROOT_E131_DATA
+        || be_u32(&packet[40..44]) != VECTOR_E131_DATA_PACKET
+        || packet[117] != 0x02
+        || packet[125] != 0x00
+    {
+        return None;
+    }
+    let value_count = u16::from_be_bytes([packet[123], packet[124]]) as usize;
+    let slots = value_count.saturating_sub(1);
+    let data = packet.get(DATA_OFFSET..DATA_OFFSET + slots)?;
+
+    let mut cid = [0u8; 16];
+    cid.copy_from_slice(&packet[22..38]);
+    Some(DataPacket {
+        cid,
+        priority: packet[108],
+        sequence: packet[111],
+        options: packet[112],
+        universe: u16::from_be_bytes([packet[113], packet[114]]),
+        data,
+    })
+}
+
+fn be_u32(b: &[u8]) -> u32 {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
+
+/// What the listener does with a data packet once it is parsed.
+#[derive(Debug, Default)]
+pub struct Receiver {
+    /// the last sequence number of every stream, oldest first
+    sequences: Vec<([u8; 16], u16, u8)>,
+}
+
+impl Receiver {
+    /// Hands `packet` to `realtime`, unless it is preview data, below `min_priority` or late.
+    pub fn receive(
+        &mut self,
+        packet: &DataPacket,
+        config: &E131Config,
+        realtime: &Realtime,
+        led_count: usize,
+    ) {
+        if packet.options & OPTION_PREVIEW_DATA != 0 || packet.priority < config.min_priority {
+            return;
+        }
+        let source = Source::E131(packet.cid);
+        if packet.options & OPTION_STREAM_TERMINATED != 0 {
+            self.sequences.retain(|(cid, _, _)| *cid != packet.cid);
+            realtime.release(&source);
+            return;
+        }
+        if !self.in_sequence(packet) {
+            return;
+        }
+        realtime.submit(
+            source,
+            packet.priority,
+            Duration::from_millis(config.timeout_ms as u64),
+            led_count,
+            |frame| {
+                config
+                    .patch()
+                    .write_universe(packet.universe, packet.data, frame)
+            },
+        );
+    }
+
+    /// Follows the sequence numbers per source and universe, `false` for a late packet.
+    fn in_sequence(&mut self, packet: &DataPacket) -> bool {
+        let stream = self
+            .sequences
+            .iter_mut()
+            .find(|(cid, universe, _)| *cid == packet.cid && *universe == packet.universe);
+        match stream {
+            Some((_, _, last)) => {
+                let ahead = packet.sequence.wrapping_sub(*last) as i8;
+                if ahead <= 0 && ahead > -SEQUENCE_WINDOW {
+                    return false;
+                }
+                *last = packet.sequence;
+            }
+            None => {
+                if self.sequences.len() == MAX_STREAMS {
+                    self.sequences.remove(0);
+                }
+                self.sequences
+                    .push((packet.cid, packet.universe, packet.sequence));
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neopixel::strip::color::default::Color;
+
+    const CID: [u8; 16] = [7; 16];
+
+    fn packet(cid: [u8; 16], priority: u8, sequence: u8, options: u8, data: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0u8; DATA_OFFSET];
+        packet[0..2].copy_from_slice(&[0x00, 0x10]);
+        packet[4..16].copy_from_slice(ACN_PACKET_IDENTIFIER);
+        packet[18..22].copy_from_slice(&VECTOR_ROOT_E131_DATA.to_be_bytes());
+        packet[22..38].copy_from_slice(&cid);
+        packet[40..44].copy_from_slice(&VECTOR_E131_DATA_PACKET.to_be_bytes());
+        packet[108] = priority;
+        packet[111] = sequence;
+        packet[112] = options;
+        packet[113..115].copy_from_slice(&1u16.to_be_bytes());
+        packet[117] = 0x02;
+        packet[123..125].copy_from_slice(&(data.len() as u16 + 1).to_be_bytes());
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    /// what `realtime` shows of a 1 pixel strip, `None` if nothing
+    fn shown(realtime: &Realtime) -> Option<(u8, Color)> {
+        let (_, priority) = realtime.status()?;
+        let mut frame = [Color::black()];
+        realtime.render_into(&mut frame);
+        Some((priority, frame[0]))
+    }
+
+    fn receive(receiver: &mut Receiver, realtime: &Realtime, packet: &[u8]) {
+        let packet = parse(packet).unwrap();
+        receiver.receive(&packet, &E131Config::default(), realtime, 1);
+    }
+
+    #[test]
+    fn parses_data_packets() {
+        let bytes = packet(CID, 150, 3, OPTION_PREVIEW_DATA, &[1, 2, 3]);
+        let parsed = parse(&bytes).unwrap();
+        assert_eq!(parsed.cid, CID);
+        assert_eq!((parsed.priority, parsed.sequence), (150, 3));
+        assert_eq!((parsed.options, parsed.universe), (OPTION_PREVIEW_DATA, 1));
+        assert_eq!(parsed.data, [1, 2, 3]);
+
+        // cut short, or with another start code
+        assert!(parse(&bytes[..bytes.len() - 1]).is_none());
+        let mut text = bytes.clone();
+        text[125] = 0x17;
+        assert!(parse(&text).is_none());
+    }
+
+    #[test]
+    fn preview_data_and_low_priorities_are_ignored() {
+        let (mut receiver, realtime) = (Receiver::default(), Realtime::new());
+        let config = E131Config {
+            min_priority: 50,
+            ..Default::default()
+        };
+        for bytes in [
+            packet(CID, 100, 0, OPTION_PREVIEW_DATA, &[1, 2, 3]),
+            packet(CID, 49, 1, 0, &[1, 2, 3]),
+        ] {
+            receiver.receive(&parse(&bytes).unwrap(), &config, &realtime, 1);
+        }
+        assert_eq!(shown(&realtime), None);
+
+        let bytes = packet(CID, 50, 2, 0, &[1, 2, 3]);
+        receiver.receive(&parse(&bytes).unwrap(), &config, &realtime, 1);
+        assert_eq!(shown(&realtime), Some((50, Color::from_u8(1, 2, 3))));
+    }
+
+    #[test]
+    fn higher_priorities_take_over() {
+        let (mut receiver, realtime) = (Receiver::default(), Realtime::new());
+        receive(
+            &mut receiver,
+            &realtime,
+            &packet(CID, 100, 0, 0, &[1, 1, 1]),
+        );
+        receive(
+            &mut receiver,
+            &realtime,
+            &packet([8; 16], 99, 0, 0, &[2, 2, 2]),
+        );
+        assert_eq!(shown(&realtime), Some((100, Color::from_u8(1, 1, 1))));
+        receive(
+            &mut receiver,
+            &realtime,
+            &packet([8; 16], 101, 1, 0, &[3, 3, 3]),
+        );
+        assert_eq!(shown(&realtime), Some((101, Color::from_u8(3, 3, 3))));
+    }
+
+    #[test]
+    fn late_packets_are_dropped() {
+        let (mut receiver, realtime) = (Receiver::default(), Realtime::new());
+        let red = |sequence, red| packet(CID, 100, sequence, 0, &[red, 0, 0]);
+        let shown_red = |realtime: &Realtime| shown(realtime).unwrap().1;
+
+        receive(&mut receiver, &realtime, &red(250, 1));
+        receive(&mut receiver, &realtime, &red(2, 2));
+        assert_eq!(shown_red(&realtime), Color::from_u8(2, 0, 0));
+        // behind, across the wrap around, and a repeat
+        receive(&mut receiver, &realtime, &red(255, 3));
+        receive(&mut receiver, &realtime, &red(2, 4));
+        assert_eq!(shown_red(&realtime), Color::from_u8(2, 0, 0));
+        // so far behind that the source must have restarted
+        receive(&mut receiver, &realtime, &red(200, 5));
+        assert_eq!(shown_red(&realtime), Color::from_u8(5, 0, 0));
+
+        // other universes and sources count on their own
+        let mut other = red(100, 6);
+        other[113..115].copy_from_slice(&2u16.to_be_bytes());
+        let other = parse(&other).unwrap();
+        assert!(receiver.in_sequence(&other));
+        let other = packet([8; 16], 100, 100, 0, &[]);
+        assert!(receiver.in_sequence(&parse(&other).unwrap()));
+    }
+
+    #[test]
+    fn stream_terminated_releases_the_source() {
+        let (mut receiver, realtime) = (Receiver::default(), Realtime::new());
+        receive(
+            &mut receiver,
+            &realtime,
+            &packet(CID, 100, 10, 0, &[1, 2, 3]),
+        );
+        receive(
+            &mut receiver,
+            &realtime,
+            &packet(CID, 100, 11, OPTION_STREAM_TERMINATED, &[]),
+        );
+        assert_eq!(shown(&realtime), None);
+        // a new stream may start anywhere
+        receive(
+            &mut receiver,
+            &realtime,
+            &packet(CID, 100, 0, 0, &[4, 5, 6]),
+        );
+        assert_eq!(shown(&realtime), Some((100, Color::from_u8(4, 5, 6))));
+    }
+}
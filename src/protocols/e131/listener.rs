@@ -0,0 +1,142 @@
+//! The socket side of E1.31: multicast groups that follow the network, and the `/e131` routes.
+
+use std::{
+    net::{Ipv4Addr, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use anyhow::Result;
+use embedded_svc::io::Write;
+use log::{info, warn};
+
+use crate::{
+    add_new_route, connection::ConnectionRelevantEvent, handler_bail, handler_soft_bail,
+    match_parsed_json, neopixel::NeopixelManager, parse_req_or_fail_with_message, send_as_json,
+    store::DStore,
+};
+
+use super::{parse, E131Config, Receiver, PORT};
+
+const STORE_KEY: &str = "e131";
+
+fn multicast_group(universe: u16) -> Ipv4Addr {
+    let [hi, lo] = universe.to_be_bytes();
+    Ipv4Addr::new(239, 255, hi, lo)
+}
+
+/// Loads the stored config, adds the `/e131` routes and starts listening.
+/// `tx` is also used to follow the network, the multicast groups are joined again on every change.
+pub fn start(
+    nm: Arc<NeopixelManager<'static>>,
+    store: Arc<Mutex<DStore>>,
+    tx: &Sender<ConnectionRelevantEvent>,
+) -> Result<()> {
+    let config = match store.lock().unwrap().get::<E131Config>(STORE_KEY) {
+        Ok(Some(config)) => config,
+        Ok(None) => E131Config::default(),
+        Err(e) => {
+            warn!("Stored e1.31 config could not be loaded: {}", e);
+            E131Config::default()
+        }
+    };
+    let config = Arc::new(Mutex::new(config));
+    add_routes(tx, config.clone(), store);
+
+    let network_changed = Arc::new(AtomicBool::new(false));
+    let changed = network_changed.clone();
+    tx.send(ConnectionRelevantEvent::NetworkListener(Box::new(
+        move |_| changed.store(true, Ordering::SeqCst),
+    )))?;
+
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, PORT))?;
+    socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+    thread::Builder::new()
+        .stack_size(6 * 1024)
+        .spawn(move || listen(socket, nm, config, network_changed))?;
+    info!("Listening for e1.31 on port {}", PORT);
+    Ok(())
+}
+
+fn listen(
+    socket: UdpSocket,
+    nm: Arc<NeopixelManager<'static>>,
+    config: Arc<Mutex<E131Config>>,
+    network_changed: Arc<AtomicBool>,
+) {
+    let mut buf = vec![0u8; 638];
+    let mut joined: Vec<u16> = Vec::new();
+    let mut applied: Option<E131Config> = None;
+    let mut receiver = Receiver::default();
+    loop {
+        // a reconnect or a switch between station and access point takes the memberships along
+        if network_changed.swap(false, Ordering::SeqCst) {
+            for universe in joined.drain(..) {
+                let _ =
+                    socket.leave_multicast_v4(&multicast_group(universe), &Ipv4Addr::UNSPECIFIED);
+            }
+            applied = None;
+        }
+        let current = config.lock().unwrap().clone();
+        if applied.as_ref() != Some(&current) {
+            let wanted: Vec<u16> = match current.enabled && current.multicast {
+                true => current.patch().universes(nm.led_count() as usize).collect(),
+                false => Vec::new(),
+            };
+            for universe in joined.iter().filter(|u| !wanted.contains(u)) {
+                let _ =
+                    socket.leave_multicast_v4(&multicast_group(*universe), &Ipv4Addr::UNSPECIFIED);
+            }
+            for universe in wanted.iter().filter(|u| !joined.contains(u)) {
+                if let Err(e) =
+                    socket.join_multicast_v4(&multicast_group(*universe), &Ipv4Addr::UNSPECIFIED)
+                {
+                    warn!(
+                        "Failed to join e1.31 multicast for universe {}: {}",
+                        universe, e
+                    );
+                }
+            }
+            joined = wanted;
+            applied = Some(current.clone());
+        }
+
+        let len = match socket.recv_from(&mut buf) {
+            Ok((len, _)) => len,
+            // read timeout, just check the config again
+            Err(_) => continue,
+        };
+        if !current.enabled {
+            continue;
+        }
+        if let Some(packet) = parse(&buf[..len]) {
+            receiver.receive(&packet, &current, &nm.realtime, nm.led_count() as usize);
+        }
+    }
+}
+
+fn add_routes(
+    tx: &Sender<ConnectionRelevantEvent>,
+    config: Arc<Mutex<E131Config>>,
+    store: Arc<Mutex<DStore>>,
+) {
+    let config2 = config.clone();
+    add_new_route!(tx; "/e131", Get, move |req| {
+        let config = config2.lock().unwrap().clone();
+        send_as_json!(req, config)
+    });
+
+    add_new_route!(tx; "/e131", Post, move |mut req| {
+        let new_config: E131Config = parse_req_or_fail_with_message!(req; "couldn't parse e1.31 config.. {}");
+        if let Err(e) = store.lock().unwrap().set(STORE_KEY, &new_config) {
+            handler_soft_bail!(req; "couldn't store e1.31 config: {:?}", e)
+        }
+        *config.lock().unwrap() = new_config;
+        send_as_json!(req, "ok")
+    });
+}
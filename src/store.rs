@@ -1,51 +1,338 @@
-use anyhow::bail;
-use embedded_svc::storage::{SerDe, StorageImpl};
+use anyhow::{bail, Result};
+#[cfg(feature = "esp")]
 use esp_idf_svc::nvs::{EspDefaultNvs, EspDefaultNvsPartition};
 use postcard::{from_bytes, to_stdvec};
 use serde::{de::DeserializeOwned, Serialize};
 
-const STORAGE_RWBUFFER_SIZE: usize = 1024;
-pub type DStore = StorageImpl<STORAGE_RWBUFFER_SIZE, EspDefaultNvs, PostCardSerDe>;
+/// Values up to this size are stored inline next to their header,
+/// bigger ones are split into chunks of this size under `<name>#<index>` or `<name>~<index>`.
+const CHUNK_SIZE: usize = 1024;
+/// NVS keys can be at most 15 characters long
+const MAX_KEY_LEN: usize = 15;
 
-pub struct PostCardSerDe;
-impl SerDe for PostCardSerDe {
-    type Error = anyhow::Error;
+const MAGIC: &[u8; 2] = b"DS";
+const FORMAT_VERSION: u8 = 2;
+const HEADER_LEN: usize = 16;
+/// format 1 had no schema field, its values count as schema 0
+const V1_HEADER_LEN: usize = 14;
 
-    fn serialize<'a, T>(&self, slice: &'a mut [u8], value: &T) -> Result<&'a [u8], Self::Error>
+#[cfg(not(feature = "esp"))]
+mod mem;
+mod migrations;
+
+#[cfg(feature = "esp")]
+pub type Nvs = EspDefaultNvs;
+#[cfg(not(feature = "esp"))]
+pub type Nvs = mem::MemNvs;
+
+/// A type that can be put into the `DStore`.
+/// `SCHEMA` is stored next to every value, and has to be bumped whenever the postcard layout of the type changes
+/// (fields added/removed/reordered, enum variants inserted before others, ...).
+pub trait Versioned: Serialize + DeserializeOwned {
+    const SCHEMA: u16;
+
+    /// `migrations()[i]` turns a payload written with schema `i` into one with schema `i + 1`,
+    /// so there has to be one step per schema bump. See `migrations.rs`.
+    fn migrations() -> &'static [Migration] {
+        &[]
+    }
+}
+
+pub type Migration = fn(&[u8]) -> Result<Vec<u8>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Layout {
+    Inline,
+    /// the chunks are under `<name>#<index>` (bank 0) or `<name>~<index>` (bank 1), every write goes
+    /// to the bank the current value does not use
+    Chunked {
+        bank: u8,
+    },
+}
+
+/// What is stored under the plain key: magic `DS`, format version, layout (0 inline, 1 + bank),
+/// payload length `u32`, crc32 of the payload `u32`, chunk count `u16`, schema of the payload `u16`
+/// (all little endian).
+#[derive(Debug, Clone, Copy)]
+struct Header {
+    layout: Layout,
+    len: u32,
+    crc: u32,
+    chunks: u16,
+    schema: u16,
+}
+
+impl Header {
+    fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut out = [0u8; HEADER_LEN];
+        out[..2].copy_from_slice(MAGIC);
+        out[2] = FORMAT_VERSION;
+        out[3] = match self.layout {
+            Layout::Inline => 0,
+            Layout::Chunked { bank } => 1 + bank,
+        };
+        out[4..8].copy_from_slice(&self.len.to_le_bytes());
+        out[8..12].copy_from_slice(&self.crc.to_le_bytes());
+        out[12..14].copy_from_slice(&self.chunks.to_le_bytes());
+        out[14..16].copy_from_slice(&self.schema.to_le_bytes());
+        out
+    }
+
+    /// `None` if `bytes` does not start with a header, i.e. was written before values were framed
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < V1_HEADER_LEN || &bytes[..2] != MAGIC {
+            return None;
+        }
+        let schema = match bytes[2] {
+            1 => 0,
+            FORMAT_VERSION if bytes.len() >= HEADER_LEN => {
+                u16::from_le_bytes([bytes[14], bytes[15]])
+            }
+            _ => return None,
+        };
+        let layout = match bytes[3] {
+            0 => Layout::Inline,
+            1 => Layout::Chunked { bank: 0 },
+            2 => Layout::Chunked { bank: 1 },
+            _ => return None,
+        };
+        Some(Self {
+            layout,
+            len: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+            crc: u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+            chunks: u16::from_le_bytes([bytes[12], bytes[13]]),
+            schema,
+        })
+    }
+
+    /// how many bytes the header took up, inline payloads start right after it
+    fn encoded_len(bytes: &[u8]) -> usize {
+        if bytes[2] == 1 {
+            V1_HEADER_LEN
+        } else {
+            HEADER_LEN
+        }
+    }
+}
+
+/// Postcard-serialized values in NVS, without a size limit per value.
+pub struct DStore {
+    nvs: Nvs,
+}
+
+#[allow(dead_code)]
+impl DStore {
+    pub fn new(nvs: Nvs) -> Self {
+        Self { nvs }
+    }
+
+    pub fn contains(&self, name: &str) -> Result<bool> {
+        Ok(self.nvs.contains(name)?)
+    }
+
+    /// Reads `name`, upgrading it first if it was written with an older schema of `T`.
+    pub fn get<T>(&self, name: &str) -> Result<Option<T>>
+    where
+        T: Versioned,
+    {
+        let (schema, bytes) = match self.get_bytes(name)? {
+            Some(stored) => stored,
+            None => return Ok(None),
+        };
+        let bytes = migrations::upgrade::<T>(schema, bytes)?;
+        match from_bytes(&bytes) {
+            Ok(v) => Ok(Some(v)),
+            Err(e) => bail!("Deserialization error: {}", e),
+        }
+    }
+
+    pub fn set<T>(&mut self, name: &str, value: &T) -> Result<bool>
     where
-        T: Serialize,
+        T: Versioned,
     {
         match to_stdvec(value) {
-            Ok(v) => {
-                let len = v.len();
-                if len > slice.len() {
-                    bail!("Slice too small");
+            Ok(bytes) => self.set_bytes(name, &bytes, T::SCHEMA),
+            Err(e) => bail!("Serialization error: {}", e),
+        }
+    }
+
+    /// Upgrades what spans several keys, to run once at boot before anything is read.
+    /// `led_count` is the length of the configured strips.
+    pub fn migrate(&mut self, led_count: u16) -> Result<()> {
+        migrations::ranges_to_segments(self, led_count)?;
+        migrations::dither_to_strips(self)
+    }
+
+    /// Removes the value and all of its chunks.
+    pub fn remove(&mut self, name: &str) -> Result<bool> {
+        if let Some(Header {
+            layout: Layout::Chunked { bank },
+            chunks,
+            ..
+        }) = self.header(name)?
+        {
+            self.remove_chunks(name, bank, 0, chunks)?;
+        }
+        Ok(self.nvs.remove(name)?)
+    }
+
+    /// The schema and payload stored under `name`, reassembled and checked against its crc.
+    pub fn get_bytes(&self, name: &str) -> Result<Option<(u16, Vec<u8>)>> {
+        let raw = match self.read_raw(name)? {
+            Some(raw) => raw,
+            None => return Ok(None),
+        };
+        let header = match Header::decode(&raw) {
+            Some(header) => header,
+            // stored before values were framed, the blob is the payload
+            None => return Ok(Some((0, raw))),
+        };
+
+        let payload = match header.layout {
+            Layout::Inline => raw[Header::encoded_len(&raw)..].to_vec(),
+            Layout::Chunked { bank } => {
+                let mut payload = Vec::with_capacity(header.len as usize);
+                for index in 0..header.chunks {
+                    match self.read_raw(&chunk_key(name, bank, index)?)? {
+                        Some(chunk) => payload.extend_from_slice(&chunk),
+                        None => bail!("chunk {} of '{}' is missing", index, name),
+                    }
                 }
-                slice[..len].copy_from_slice(&v);
-                Ok(&slice[..len])
+                payload
             }
-            Err(e) => bail!("Serialization error: {}", e),
+        };
+        if payload.len() != header.len as usize || crc32(&payload) != header.crc {
+            bail!("'{}' is corrupted (length or checksum mismatch)", name);
         }
+        Ok(Some((header.schema, payload)))
     }
 
-    fn deserialize<T>(&self, slice: &[u8]) -> Result<T, Self::Error>
-    where
-        T: DeserializeOwned,
-    {
-        match from_bytes(slice) {
-            Ok(v) => Ok(v),
-            Err(e) => bail!("Deserialization error: {}", e),
+    /// Stores `bytes` under `name`, splitting it into chunks if it does not fit a single entry.
+    /// Chunks go to the bank the current value does not use and the header is written last, so a
+    /// write that is interrupted leaves the old value as it was.
+    pub fn set_bytes(&mut self, name: &str, bytes: &[u8], schema: u16) -> Result<bool> {
+        check_key(name)?;
+        let old = match self.header(name)? {
+            Some(Header {
+                layout: Layout::Chunked { bank },
+                chunks,
+                ..
+            }) => Some((bank, chunks)),
+            _ => None,
+        };
+
+        let mut header = Header {
+            layout: Layout::Inline,
+            len: bytes.len() as u32,
+            crc: crc32(bytes),
+            chunks: 0,
+            schema,
+        };
+        let mut entry = Vec::with_capacity(HEADER_LEN + bytes.len().min(CHUNK_SIZE));
+        if bytes.len() <= CHUNK_SIZE {
+            entry.extend_from_slice(&header.encode());
+            entry.extend_from_slice(bytes);
+        } else {
+            let chunk_count = bytes.len().div_ceil(CHUNK_SIZE);
+            if chunk_count > u16::MAX as usize {
+                bail!("'{}' is too big to be stored ({} bytes)", name, bytes.len());
+            }
+            let bank = match old {
+                Some((0, _)) => 1,
+                _ => 0,
+            };
+            for (index, chunk) in bytes.chunks(CHUNK_SIZE).enumerate() {
+                self.nvs
+                    .set_raw(&chunk_key(name, bank, index as u16)?, chunk)?;
+            }
+            // left over from a write that was interrupted before its header
+            let mut index = chunk_count as u16;
+            while index < u16::MAX && self.nvs.remove(&chunk_key(name, bank, index)?)? {
+                index += 1;
+            }
+            header.layout = Layout::Chunked { bank };
+            header.chunks = chunk_count as u16;
+            entry.extend_from_slice(&header.encode());
+        }
+        let written = self.nvs.set_raw(name, &entry)?;
+
+        if let Some((bank, chunks)) = old {
+            self.remove_chunks(name, bank, 0, chunks)?;
+        }
+        Ok(written)
+    }
+
+    fn header(&self, name: &str) -> Result<Option<Header>> {
+        Ok(self.read_raw(name)?.and_then(|raw| Header::decode(&raw)))
+    }
+
+    fn read_raw(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let len = match self.nvs.len(name)? {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+        let mut buf = vec![0u8; len];
+        let read = self.nvs.get_raw(name, &mut buf)?.map(|b| b.len());
+        Ok(read.map(|read| {
+            buf.truncate(read);
+            buf
+        }))
+    }
+
+    fn remove_chunks(&mut self, name: &str, bank: u8, from: u16, to: u16) -> Result<()> {
+        for index in from..to {
+            self.nvs.remove(&chunk_key(name, bank, index)?)?;
+        }
+        Ok(())
+    }
+}
+
+fn check_key(name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > MAX_KEY_LEN {
+        bail!("invalid storage key '{}'", name);
+    }
+    Ok(())
+}
+
+fn chunk_key(name: &str, bank: u8, index: u16) -> Result<String> {
+    let separator = match bank {
+        0 => '#',
+        _ => '~',
+    };
+    let key = format!("{}{}{}", name, separator, index);
+    if key.len() > MAX_KEY_LEN {
+        bail!("'{}' is too long a name for a value this big", name);
+    }
+    Ok(key)
+}
+
+/// CRC-32 (IEEE), bitwise since values are small and rarely written
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
         }
     }
+    !crc
 }
 
 // pub struct Storage {}
 
 // impl Storage {
+#[cfg(feature = "esp")]
 pub fn default() -> DStore {
     let nvsp = EspDefaultNvsPartition::take().expect("Failed to take NVS partition");
     let rs = EspDefaultNvs::new(nvsp, "breb", true).expect("Failed to create nvs");
-    StorageImpl::new(rs, PostCardSerDe)
+    DStore::new(rs)
+}
+
+/// an empty store that forgets everything when dropped
+#[cfg(not(feature = "esp"))]
+pub fn default() -> DStore {
+    DStore::new(mem::MemNvs::default())
 }
 // }
 
@@ -58,3 +345,123 @@ pub trait SelfStorable {
 
     fn store_in(&self, store: &mut DStore) -> anyhow::Result<()>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(len: usize, seed: u8) -> Vec<u8> {
+        (0..len)
+            .map(|i| (i as u8).wrapping_mul(31) ^ seed)
+            .collect()
+    }
+
+    /// how many chunks of `name` there are in `bank`
+    fn chunks_in(store: &DStore, name: &str, bank: u8) -> u16 {
+        (0..)
+            .take_while(|i| {
+                store
+                    .nvs
+                    .contains(&chunk_key(name, bank, *i).unwrap())
+                    .unwrap()
+            })
+            .count() as u16
+    }
+
+    #[test]
+    fn small_values_are_stored_inline() {
+        let mut store = default();
+        let value = bytes(CHUNK_SIZE, 1);
+        store.set_bytes("value", &value, 3).unwrap();
+        assert_eq!(store.get_bytes("value").unwrap(), Some((3, value)));
+        assert_eq!(chunks_in(&store, "value", 0), 0);
+        assert_eq!(chunks_in(&store, "value", 1), 0);
+    }
+
+    #[test]
+    fn big_values_are_chunked() {
+        let mut store = default();
+        let value = bytes(3 * CHUNK_SIZE + 1, 2);
+        store.set_bytes("value", &value, 1).unwrap();
+        assert_eq!(store.get_bytes("value").unwrap(), Some((1, value)));
+        assert_eq!(chunks_in(&store, "value", 0), 4);
+    }
+
+    #[test]
+    fn rewrites_switch_banks_and_drop_the_old_chunks() {
+        let mut store = default();
+        store
+            .set_bytes("value", &bytes(3 * CHUNK_SIZE, 3), 0)
+            .unwrap();
+        let bigger = bytes(5 * CHUNK_SIZE, 4);
+        store.set_bytes("value", &bigger, 0).unwrap();
+        assert_eq!(store.get_bytes("value").unwrap(), Some((0, bigger)));
+        assert_eq!(
+            (chunks_in(&store, "value", 0), chunks_in(&store, "value", 1)),
+            (0, 5)
+        );
+
+        let smaller = bytes(2 * CHUNK_SIZE, 5);
+        store.set_bytes("value", &smaller, 0).unwrap();
+        assert_eq!(store.get_bytes("value").unwrap(), Some((0, smaller)));
+        assert_eq!(
+            (chunks_in(&store, "value", 0), chunks_in(&store, "value", 1)),
+            (2, 0)
+        );
+
+        store.set_bytes("value", b"tiny", 0).unwrap();
+        assert_eq!(
+            store.get_bytes("value").unwrap(),
+            Some((0, b"tiny".to_vec()))
+        );
+        assert_eq!(
+            (chunks_in(&store, "value", 0), chunks_in(&store, "value", 1)),
+            (0, 0)
+        );
+
+        store.remove("value").unwrap();
+        assert_eq!(store.get_bytes("value").unwrap(), None);
+    }
+
+    #[test]
+    fn an_interrupted_write_keeps_the_old_value() {
+        let mut store = default();
+        let old = bytes(2 * CHUNK_SIZE, 6);
+        store.set_bytes("value", &old, 0).unwrap();
+        // the chunks of a bigger value made it to the other bank, its header did not
+        for index in 0..4 {
+            store
+                .nvs
+                .set_raw(
+                    &chunk_key("value", 1, index).unwrap(),
+                    &bytes(CHUNK_SIZE, 7),
+                )
+                .unwrap();
+        }
+        assert_eq!(store.get_bytes("value").unwrap(), Some((0, old)));
+
+        // the next write goes to that bank and clears what the interrupted one left behind
+        let new = bytes(2 * CHUNK_SIZE, 8);
+        store.set_bytes("value", &new, 0).unwrap();
+        assert_eq!(store.get_bytes("value").unwrap(), Some((0, new)));
+        assert_eq!(
+            (chunks_in(&store, "value", 0), chunks_in(&store, "value", 1)),
+            (0, 2)
+        );
+    }
+
+    #[test]
+    fn corrupted_chunks_fail_the_crc() {
+        let mut store = default();
+        store
+            .set_bytes("value", &bytes(2 * CHUNK_SIZE, 9), 0)
+            .unwrap();
+        let mut chunk = bytes(CHUNK_SIZE, 9);
+        chunk[10] ^= 1;
+        store
+            .nvs
+            .set_raw(&chunk_key("value", 0, 0).unwrap(), &chunk)
+            .unwrap();
+        assert!(store.get_bytes("value").is_err());
+    }
+}
@@ -0,0 +1,39 @@
+//! What `DStore` sits on when there is no flash, i.e. on the host. Mirrors the part of `EspNvs`
+//! the store uses.
+
+use std::{collections::HashMap, convert::Infallible};
+
+/// Memory can not fail, the error only stands in for `EspError` so `DStore` converts both alike.
+type Result<T> = std::result::Result<T, Infallible>;
+
+#[derive(Debug, Default)]
+pub struct MemNvs {
+    entries: HashMap<String, Vec<u8>>,
+}
+
+impl MemNvs {
+    pub fn contains(&self, name: &str) -> Result<bool> {
+        Ok(self.entries.contains_key(name))
+    }
+
+    pub fn len(&self, name: &str) -> Result<Option<usize>> {
+        Ok(self.entries.get(name).map(Vec::len))
+    }
+
+    pub fn get_raw<'a>(&self, name: &str, buf: &'a mut [u8]) -> Result<Option<&'a [u8]>> {
+        Ok(self.entries.get(name).map(move |entry| {
+            let len = entry.len().min(buf.len());
+            buf[..len].copy_from_slice(&entry[..len]);
+            &buf[..len]
+        }))
+    }
+
+    pub fn set_raw(&mut self, name: &str, buf: &[u8]) -> Result<bool> {
+        self.entries.insert(name.to_owned(), buf.to_vec());
+        Ok(true)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<bool> {
+        Ok(self.entries.remove(name).is_some())
+    }
+}
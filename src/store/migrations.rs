@@ -0,0 +1,1000 @@
+//! Every type that is kept in the `DStore`, with its current schema and the steps to get there.
+//!
+//! When the postcard layout of a stored type changes:
+//! 1. copy the old layout into a `v<N>` module below (old steps deserialize those, never the live types),
+//! 2. bump `SCHEMA` and append a step that turns schema `N` bytes into schema `N + 1` bytes.
+//!
+//! Steps only see one value. What has to change several keys at once runs at boot through
+//! `DStore::migrate`, before anything is loaded, see `ranges_to_segments`.
+
+use std::ops::Range;
+
+use anyhow::{anyhow, bail, Result};
+use postcard::{from_bytes, to_stdvec};
+
+use super::{DStore, Migration, Versioned};
+use crate::mqtt::MqttConfig;
+use crate::neopixel::{
+    effects::{alarm, hue, invert, solid, strobo, EffectConfig},
+    layer::{BlendMode, Layer},
+    matrix::MatrixConfig,
+    output::{OutputConfig, PowerConfig},
+    pixelmap::Point,
+    presets::{self, Preset, PresetIndex},
+    segment::{Segment, Segments, WHOLE_STRIP},
+    strip::{
+        calibration::{CalibrationConfig, WhiteExtraction},
+        color::default::Color,
+        StripConfig,
+    },
+};
+#[cfg(feature = "esp")]
+use crate::{
+    connection::wifi::Creds,
+    protocols::{artnet::ArtNetConfig, ddp::DdpConfig, e131::E131Config},
+};
+
+/// Turns a payload written with `schema` into the current layout of `T`.
+pub(super) fn upgrade<T: Versioned>(schema: u16, mut bytes: Vec<u8>) -> Result<Vec<u8>> {
+    if schema > T::SCHEMA {
+        bail!(
+            "stored by newer firmware (schema {}, this firmware knows up to {})",
+            schema,
+            T::SCHEMA
+        );
+    }
+    let steps = T::migrations();
+    if steps.len() != T::SCHEMA as usize {
+        bail!(
+            "schema {} needs {} migration steps, found {}",
+            T::SCHEMA,
+            T::SCHEMA,
+            steps.len()
+        );
+    }
+    for (from, step) in steps.iter().enumerate().skip(schema as usize) {
+        bytes =
+            step(&bytes).map_err(|e| anyhow!("migrating from schema {} failed: {}", from, e))?;
+    }
+    Ok(bytes)
+}
+
+/// Like `upgrade`, but stops at `target` instead of the current schema.
+fn upgrade_to<T: Versioned>(schema: u16, target: u16, mut bytes: Vec<u8>) -> Result<Vec<u8>> {
+    for step in &T::migrations()[schema as usize..target as usize] {
+        bytes = step(&bytes)?;
+    }
+    Ok(bytes)
+}
+
+/// Layers used to carry their own range, now they point into the segment registry. A step of
+/// `Vec<Layer>` or `Preset` can not add segments, so this turns every distinct range in the
+/// stored effects and presets into a segment and rewrites them, before any of them is loaded.
+/// Payloads that already have segments are left alone, which also makes this cheap after the
+/// first boot.
+pub(super) fn ranges_to_segments(store: &mut DStore, led_count: u16) -> Result<()> {
+    let mut old_effects = None;
+    if let Some((schema, bytes)) = store.get_bytes("effects")? {
+        if schema < 3 {
+            let bytes = upgrade_to::<Vec<Layer>>(schema, 2, bytes)?;
+            old_effects = Some(from_bytes::<Vec<v2::Layer>>(&bytes)?);
+        }
+    }
+    let mut old_presets = Vec::new();
+    for key in presets::keys(store)? {
+        if let Some((schema, bytes)) = store.get_bytes(&key)? {
+            if schema < 2 {
+                let bytes = upgrade_to::<Preset>(schema, 1, bytes)?;
+                old_presets.push((key, from_bytes::<v1::Preset>(&bytes)?));
+            }
+        }
+    }
+    if old_effects.is_none() && old_presets.is_empty() {
+        return Ok(());
+    }
+
+    // the segments go first, if we are cut off halfway the next boot finds and reuses them
+    let stored = store.get::<Vec<Segment>>("segments")?.unwrap_or_default();
+    let mut segments = Segments::new(stored);
+    let effects = old_effects.map(|layers| layers_with_segments(layers, &mut segments, led_count));
+    let presets: Vec<(String, Preset)> = old_presets
+        .into_iter()
+        .map(|(key, preset)| {
+            let effects = layers_with_segments(preset.effects, &mut segments, led_count);
+            let output = preset.output;
+            let calibration = None;
+            (
+                key,
+                Preset {
+                    effects,
+                    output,
+                    calibration,
+                },
+            )
+        })
+        .collect();
+    store.set("segments", &segments.list().to_vec())?;
+    if let Some(effects) = effects {
+        store.set("effects", &effects)?;
+    }
+    for (key, preset) in presets {
+        store.set(&key, &preset)?;
+    }
+    Ok(())
+}
+
+fn layers_with_segments(
+    layers: Vec<v2::Layer>,
+    segments: &mut Segments,
+    led_count: u16,
+) -> Vec<Layer> {
+    layers
+        .into_iter()
+        .map(|l| {
+            let (range, effect) = effect_from_v0(l.effect);
+            Layer {
+                id: l.id,
+                segment: segments.plain(range, led_count),
+                effect,
+                opacity: l.opacity,
+                blend: blend_from_v1(l.blend),
+                enabled: l.enabled,
+            }
+        })
+        .collect()
+}
+
+/// `dither` moved from the calibration to every strip. A step of `Vec<StripConfig>` can not see
+/// the calibration, so this hands the stored setting to the strips, before either is loaded.
+/// Without a stored strip list the default strip is stored, unless it dithers anyway.
+pub(super) fn dither_to_strips(store: &mut DStore) -> Result<()> {
+    let dither = match store.get_bytes("calibration")? {
+        Some((schema, bytes)) if schema < 3 => {
+            let bytes = upgrade_to::<CalibrationConfig>(schema, 2, bytes)?;
+            from_bytes::<v2::CalibrationConfig>(&bytes)?.dither
+        }
+        _ => return Ok(()),
+    };
+
+    // the strips go first, if we are cut off halfway the next boot still finds the old calibration
+    let strips = match store.get_bytes("strip")? {
+        Some((schema, bytes)) if schema < 2 => {
+            let bytes = upgrade_to::<Vec<StripConfig>>(schema, 1, bytes)?;
+            let strips: Vec<v1::StripConfig> = from_bytes(&bytes)?;
+            Some(
+                strips
+                    .into_iter()
+                    .map(|s| strip_from_v1(s, dither))
+                    .collect(),
+            )
+        }
+        Some(_) => None,
+        None if !dither => Some(vec![StripConfig {
+            dither,
+            ..Default::default()
+        }]),
+        None => None,
+    };
+    if let Some(strips) = strips {
+        store.set("strip", &strips)?;
+    }
+    if let Some(calibration) = store.get::<CalibrationConfig>("calibration")? {
+        store.set("calibration", &calibration)?;
+    }
+    Ok(())
+}
+
+/// 0: a plain `Vec<EffectConfig>`, before effects were layered
+/// 1: layers with `effect`, `opacity` and `blend`
+/// 2: layers got a stable `id` and `enabled`
+/// 3: layers point at a `segment`, effects lost their `range`
+impl Versioned for Vec<Layer> {
+    const SCHEMA: u16 = 3;
+
+    fn migrations() -> &'static [Migration] {
+        &[layers_0_to_1, layers_1_to_2, layers_2_to_3]
+    }
+}
+
+mod v2 {
+    use serde::{Deserialize, Serialize};
+
+    use crate::neopixel::{layer, output::OutputConfig, strip::calibration::WhiteExtraction};
+
+    use super::{v0::EffectConfig, v1::BlendMode};
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Layer {
+        pub id: u32,
+        pub effect: EffectConfig,
+        pub opacity: f32,
+        pub blend: BlendMode,
+        pub enabled: bool,
+    }
+
+    /// `effects` and `output` are still the live types: when their schema changes, freeze them
+    /// here as well
+    #[derive(Serialize, Deserialize)]
+    pub struct Preset {
+        pub effects: Vec<layer::Layer>,
+        pub output: OutputConfig,
+    }
+
+    /// `white` is still the live `WhiteExtraction`, new variants only ever get appended
+    #[derive(Serialize, Deserialize)]
+    pub struct CalibrationConfig {
+        pub gamma: f32,
+        pub red: f32,
+        pub green: f32,
+        pub blue: f32,
+        pub dither: bool,
+        pub white: WhiteExtraction,
+    }
+}
+
+mod v1 {
+    use serde::{Deserialize, Serialize};
+
+    use crate::neopixel::{
+        output::OutputConfig,
+        strip::{Chipset, LedColorOrder},
+    };
+
+    use super::{v0::EffectConfig, v2};
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Layer {
+        pub effect: EffectConfig,
+        pub opacity: f32,
+        pub blend: BlendMode,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub enum BlendMode {
+        Normal,
+        Add,
+        Multiply,
+        Screen,
+        Max,
+        Min,
+        AlphaOver,
+    }
+
+    /// `output` is still the live `OutputConfig`: when its schema changes, freeze it here as well
+    #[derive(Serialize, Deserialize)]
+    pub struct Preset {
+        pub effects: Vec<v2::Layer>,
+        pub output: OutputConfig,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct CalibrationConfig {
+        pub gamma: f32,
+        pub red: f32,
+        pub green: f32,
+        pub blue: f32,
+        pub dither: bool,
+    }
+
+    /// `chipset` and `order` are still the live types, new variants only ever get appended
+    #[derive(Serialize, Deserialize)]
+    pub struct StripConfig {
+        pub chipset: Chipset,
+        pub order: LedColorOrder,
+        pub led_count: u16,
+        pub gpio: u8,
+        pub channel: u8,
+        pub reversed: bool,
+    }
+}
+
+fn layers_0_to_1(bytes: &[u8]) -> Result<Vec<u8>> {
+    let effects: Vec<v0::EffectConfig> = from_bytes(bytes)?;
+    let layers: Vec<v1::Layer> = effects
+        .into_iter()
+        .map(|effect| v1::Layer {
+            effect,
+            opacity: 1.0,
+            blend: v1::BlendMode::Normal,
+        })
+        .collect();
+    Ok(to_stdvec(&layers)?)
+}
+
+fn layers_1_to_2(bytes: &[u8]) -> Result<Vec<u8>> {
+    let layers: Vec<v1::Layer> = from_bytes(bytes)?;
+    let layers: Vec<v2::Layer> = layers
+        .into_iter()
+        .map(|l| v2::Layer {
+            id: 0,
+            effect: l.effect,
+            opacity: l.opacity,
+            blend: l.blend,
+            enabled: true,
+        })
+        .collect();
+    Ok(to_stdvec(&layers)?)
+}
+
+/// Only reached by payloads `ranges_to_segments` did not get to,
+/// their layers end up on the whole strip.
+fn layers_2_to_3(bytes: &[u8]) -> Result<Vec<u8>> {
+    let layers: Vec<v2::Layer> = from_bytes(bytes)?;
+    Ok(to_stdvec(&layers_on_whole_strip(layers))?)
+}
+
+fn layers_on_whole_strip(layers: Vec<v2::Layer>) -> Vec<Layer> {
+    layers
+        .into_iter()
+        .map(|l| Layer {
+            id: l.id,
+            segment: WHOLE_STRIP,
+            effect: effect_from_v0(l.effect).1,
+            opacity: l.opacity,
+            blend: blend_from_v1(l.blend),
+            enabled: l.enabled,
+        })
+        .collect()
+}
+
+fn blend_from_v1(blend: v1::BlendMode) -> BlendMode {
+    match blend {
+        v1::BlendMode::Normal => BlendMode::Normal,
+        v1::BlendMode::Add => BlendMode::Add,
+        v1::BlendMode::Multiply => BlendMode::Multiply,
+        v1::BlendMode::Screen => BlendMode::Screen,
+        v1::BlendMode::Max => BlendMode::Max,
+        v1::BlendMode::Min => BlendMode::Min,
+        v1::BlendMode::AlphaOver => BlendMode::AlphaOver,
+    }
+}
+
+/// the range the effect used to cover, and the effect without it
+fn effect_from_v0(effect: v0::EffectConfig) -> (Range<u16>, EffectConfig) {
+    match effect {
+        v0::EffectConfig::Invert(c) => (c.range, EffectConfig::Invert(invert::InversionConfig {})),
+        v0::EffectConfig::HueShift(c) => (
+            c.range,
+            EffectConfig::HueShift(hue::HueShiftConfig {
+                degrees_per_second: c.degrees_per_second,
+                degrees_per_led: c.degrees_per_led,
+            }),
+        ),
+        v0::EffectConfig::SolidColor(c) => (
+            c.range,
+            EffectConfig::SolidColor(solid::SolidColorConfig {
+                color: Color {
+                    red: c.color.red,
+                    green: c.color.green,
+                    blue: c.color.blue,
+                },
+            }),
+        ),
+        v0::EffectConfig::Strobo(c) => (
+            c.range,
+            EffectConfig::Strobo(strobo::StroboConfig {
+                frequency_hz: c.frequency_hz,
+            }),
+        ),
+        v0::EffectConfig::Alarm(c) => (
+            c.range,
+            EffectConfig::Alarm(alarm::AlarmConfig {
+                at_ms_since_1970: c.at_ms_since_1970,
+                alarm_type: match c.alarm_type {
+                    v0::AlarmType::Sunrise => alarm::AlarmType::Sunrise,
+                    v0::AlarmType::Silvester => alarm::AlarmType::Silvester,
+                    v0::AlarmType::Strobo => alarm::AlarmType::Strobo,
+                },
+            }),
+        ),
+    }
+}
+
+/// 0: effects as `Vec<Layer>` schema 2, output as `OutputConfig` schema 0
+/// 1: output as `OutputConfig` schema 1
+/// 2: effects as `Vec<Layer>` schema 3
+/// 3: `calibration`
+/// 4: calibration as `CalibrationConfig` schema 3
+impl Versioned for Preset {
+    const SCHEMA: u16 = 4;
+
+    fn migrations() -> &'static [Migration] {
+        &[preset_0_to_1, preset_1_to_2, preset_2_to_3, preset_3_to_4]
+    }
+}
+
+mod v3 {
+    use serde::{Deserialize, Serialize};
+
+    use crate::neopixel::{layer::Layer, output::OutputConfig};
+
+    use super::v2::CalibrationConfig;
+
+    /// `effects` and `output` are still the live types: when their schema changes, freeze them
+    /// here as well
+    #[derive(Serialize, Deserialize)]
+    pub struct Preset {
+        pub effects: Vec<Layer>,
+        pub output: OutputConfig,
+        pub calibration: Option<CalibrationConfig>,
+    }
+}
+
+mod v0 {
+    use std::{ops::Range, time::Duration};
+
+    use serde::{Deserialize, Serialize};
+    use serde_with::{serde_as, DurationMilliSeconds};
+
+    use crate::neopixel::strip::{Chipset, LedColorOrder};
+
+    use super::v2;
+
+    /// every effect with its own range, up to `Vec<Layer>` schema 2
+    #[derive(Serialize, Deserialize)]
+    pub enum EffectConfig {
+        Invert(InversionConfig),
+        HueShift(HueShiftConfig),
+        SolidColor(SolidColorConfig),
+        Strobo(StroboConfig),
+        Alarm(AlarmConfig),
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct InversionConfig {
+        pub range: Range<u16>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct HueShiftConfig {
+        pub degrees_per_second: f32,
+        pub degrees_per_led: f32,
+        pub range: Range<u16>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct SolidColorConfig {
+        pub color: Color,
+        pub range: Range<u16>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct StroboConfig {
+        pub frequency_hz: f32,
+        pub range: Range<u16>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Color {
+        pub red: f32,
+        pub green: f32,
+        pub blue: f32,
+    }
+
+    #[serde_as]
+    #[derive(Serialize, Deserialize)]
+    pub struct AlarmConfig {
+        #[serde_as(as = "DurationMilliSeconds<u64>")]
+        pub at_ms_since_1970: Duration,
+        pub alarm_type: AlarmType,
+        pub range: Range<u16>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub enum AlarmType {
+        Sunrise,
+        Silvester,
+        Strobo,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct OutputConfig {
+        pub brightness: f32,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct CalibrationConfig {
+        pub gamma: f32,
+        pub red: f32,
+        pub green: f32,
+        pub blue: f32,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Preset {
+        pub effects: Vec<v2::Layer>,
+        pub output: OutputConfig,
+    }
+
+    /// `chipset` and `order` are still the live types, new variants only ever get appended
+    #[derive(Serialize, Deserialize)]
+    pub struct StripConfig {
+        pub chipset: Chipset,
+        pub order: LedColorOrder,
+        pub led_count: u16,
+        pub gpio: u8,
+    }
+}
+
+fn output_from_v0(output: v0::OutputConfig) -> OutputConfig {
+    OutputConfig {
+        brightness: output.brightness,
+        on: true,
+    }
+}
+
+fn preset_0_to_1(bytes: &[u8]) -> Result<Vec<u8>> {
+    let preset: v0::Preset = from_bytes(bytes)?;
+    Ok(to_stdvec(&v1::Preset {
+        effects: preset.effects,
+        output: output_from_v0(preset.output),
+    })?)
+}
+
+/// Like `layers_2_to_3`, only for what `ranges_to_segments` did not get to.
+fn preset_1_to_2(bytes: &[u8]) -> Result<Vec<u8>> {
+    let preset: v1::Preset = from_bytes(bytes)?;
+    Ok(to_stdvec(&v2::Preset {
+        effects: layers_on_whole_strip(preset.effects),
+        output: preset.output,
+    })?)
+}
+
+/// Older presets leave the calibration alone.
+fn preset_2_to_3(bytes: &[u8]) -> Result<Vec<u8>> {
+    let preset: v2::Preset = from_bytes(bytes)?;
+    Ok(to_stdvec(&v3::Preset {
+        effects: preset.effects,
+        output: preset.output,
+        calibration: None,
+    })?)
+}
+
+/// Whether the strips dither is no longer part of a preset.
+fn preset_3_to_4(bytes: &[u8]) -> Result<Vec<u8>> {
+    let preset: v3::Preset = from_bytes(bytes)?;
+    Ok(to_stdvec(&Preset {
+        effects: preset.effects,
+        output: preset.output,
+        calibration: preset.calibration.map(calibration_from_v2),
+    })?)
+}
+
+impl Versioned for PresetIndex {
+    const SCHEMA: u16 = 0;
+}
+
+/// 0: only `brightness`
+/// 1: `on`
+impl Versioned for OutputConfig {
+    const SCHEMA: u16 = 1;
+
+    fn migrations() -> &'static [Migration] {
+        &[output_0_to_1]
+    }
+}
+
+fn output_0_to_1(bytes: &[u8]) -> Result<Vec<u8>> {
+    let output: v0::OutputConfig = from_bytes(bytes)?;
+    Ok(to_stdvec(&output_from_v0(output))?)
+}
+
+impl Versioned for PowerConfig {
+    const SCHEMA: u16 = 0;
+}
+
+impl Versioned for Vec<Segment> {
+    const SCHEMA: u16 = 0;
+}
+
+impl Versioned for MatrixConfig {
+    const SCHEMA: u16 = 0;
+}
+
+/// the pixel map, as uploaded
+impl Versioned for Vec<Point> {
+    const SCHEMA: u16 = 0;
+}
+
+/// 0: gamma and white balance
+/// 1: `dither`
+/// 2: `white`
+/// 3: `dither` moved to `StripConfig`, see `dither_to_strips`
+impl Versioned for CalibrationConfig {
+    const SCHEMA: u16 = 3;
+
+    fn migrations() -> &'static [Migration] {
+        &[calibration_0_to_1, calibration_1_to_2, calibration_2_to_3]
+    }
+}
+
+fn calibration_0_to_1(bytes: &[u8]) -> Result<Vec<u8>> {
+    let calibration: v0::CalibrationConfig = from_bytes(bytes)?;
+    Ok(to_stdvec(&v1::CalibrationConfig {
+        gamma: calibration.gamma,
+        red: calibration.red,
+        green: calibration.green,
+        blue: calibration.blue,
+        dither: true,
+    })?)
+}
+
+fn calibration_1_to_2(bytes: &[u8]) -> Result<Vec<u8>> {
+    let calibration: v1::CalibrationConfig = from_bytes(bytes)?;
+    Ok(to_stdvec(&v2::CalibrationConfig {
+        gamma: calibration.gamma,
+        red: calibration.red,
+        green: calibration.green,
+        blue: calibration.blue,
+        dither: calibration.dither,
+        white: WhiteExtraction::MinSubtract,
+    })?)
+}
+
+fn calibration_2_to_3(bytes: &[u8]) -> Result<Vec<u8>> {
+    let calibration: v2::CalibrationConfig = from_bytes(bytes)?;
+    Ok(to_stdvec(&calibration_from_v2(calibration))?)
+}
+
+fn calibration_from_v2(calibration: v2::CalibrationConfig) -> CalibrationConfig {
+    CalibrationConfig {
+        gamma: calibration.gamma,
+        red: calibration.red,
+        green: calibration.green,
+        blue: calibration.blue,
+        white: calibration.white,
+    }
+}
+
+/// 0: a single `StripConfig` without `channel` and `reversed`, always on rmt channel 1 or SPI2
+/// 1: a list of strips
+/// 2: `dither`, see `dither_to_strips`
+impl Versioned for Vec<StripConfig> {
+    const SCHEMA: u16 = 2;
+
+    fn migrations() -> &'static [Migration] {
+        &[strip_0_to_1, strip_1_to_2]
+    }
+}
+
+fn strip_0_to_1(bytes: &[u8]) -> Result<Vec<u8>> {
+    let strip: v0::StripConfig = from_bytes(bytes)?;
+    let channel = match strip.chipset.clocked() {
+        Some(_) => 2,
+        None => 1,
+    };
+    Ok(to_stdvec(&vec![v1::StripConfig {
+        chipset: strip.chipset,
+        order: strip.order,
+        led_count: strip.led_count,
+        gpio: strip.gpio,
+        channel,
+        reversed: false,
+    }])?)
+}
+
+/// Only reached when there was no calibration to take `dither` from, which dithered by default.
+fn strip_1_to_2(bytes: &[u8]) -> Result<Vec<u8>> {
+    let strips: Vec<v1::StripConfig> = from_bytes(bytes)?;
+    let strips: Vec<StripConfig> = strips.into_iter().map(|s| strip_from_v1(s, true)).collect();
+    Ok(to_stdvec(&strips)?)
+}
+
+fn strip_from_v1(strip: v1::StripConfig, dither: bool) -> StripConfig {
+    StripConfig {
+        chipset: strip.chipset,
+        order: strip.order,
+        led_count: strip.led_count,
+        gpio: strip.gpio,
+        channel: strip.channel,
+        reversed: strip.reversed,
+        dither,
+    }
+}
+
+#[cfg(feature = "esp")]
+impl Versioned for Creds {
+    const SCHEMA: u16 = 0;
+}
+
+#[cfg(feature = "esp")]
+impl Versioned for E131Config {
+    const SCHEMA: u16 = 0;
+}
+
+#[cfg(feature = "esp")]
+impl Versioned for ArtNetConfig {
+    const SCHEMA: u16 = 0;
+}
+
+#[cfg(feature = "esp")]
+impl Versioned for DdpConfig {
+    const SCHEMA: u16 = 0;
+}
+
+impl Versioned for MqttConfig {
+    const SCHEMA: u16 = 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        neopixel::{
+            effects::{alarm::AlarmType, solid::SolidColorConfig},
+            strip::{Chipset, Clocked, LedColorOrder},
+        },
+        store,
+    };
+
+    // Payloads as older firmware wrote them, spelled out byte by byte so that they stay what was
+    // on the flash no matter how the types above change.
+
+    /// `effects` from before layers, a bare blob without a header:
+    /// `HueShift` on 0..60, `SolidColor` on 10..20
+    const EFFECTS_0: &[u8] = &[
+        0x02, // two effects
+        0x01, // HueShift
+        0x00, 0x00, 0x20, 0x41, // degrees_per_second 10.0
+        0x00, 0x00, 0x00, 0x40, // degrees_per_led 2.0
+        0x00, 0x3c, // range 0..60
+        0x02, // SolidColor
+        0x00, 0x00, 0x80, 0x3f, // red 1.0
+        0x00, 0x00, 0x00, 0x00, // green 0.0
+        0x00, 0x00, 0x00, 0x3f, // blue 0.5
+        0x0a, 0x14, // range 10..20
+    ];
+
+    /// `effects` schema 1: an `Alarm` on 0..10, half opaque, blended with `Screen`
+    const EFFECTS_1: &[u8] = &[
+        0x01, // one layer
+        0x04, // Alarm
+        0x80, 0xd0, 0x95, 0xff, 0xbc, 0x31, // at_ms_since_1970 1_700_000_000_000
+        0x01, // Silvester
+        0x00, 0x0a, // range 0..10
+        0x00, 0x00, 0x00, 0x3f, // opacity 0.5
+        0x03, // Screen
+    ];
+
+    /// `Preset` schema 0: a disabled `Invert` on 20..30 with id 7, at a quarter opacity and
+    /// blended with `Add`, at half brightness
+    const PRESET_0: &[u8] = &[
+        0x01, // one layer
+        0x07, // id
+        0x00, // Invert
+        0x14, 0x1e, // range 20..30
+        0x00, 0x00, 0x80, 0x3e, // opacity 0.25
+        0x01, // Add
+        0x00, // enabled false
+        0x00, 0x00, 0x00, 0x3f, // brightness 0.5
+    ];
+
+    /// `CalibrationConfig` schema 0
+    const CALIBRATION_0: &[u8] = &[
+        0xcd, 0xcc, 0x0c, 0x40, // gamma 2.2
+        0x00, 0x00, 0x80, 0x3f, // red 1.0
+        0xcd, 0xcc, 0x4c, 0x3f, // green 0.8
+        0x66, 0x66, 0x66, 0x3f, // blue 0.9
+    ];
+
+    /// `CalibrationConfig` schema 1, the same without dithering
+    const CALIBRATION_1: &[u8] = &[
+        0xcd, 0xcc, 0x0c, 0x40, // gamma 2.2
+        0x00, 0x00, 0x80, 0x3f, // red 1.0
+        0xcd, 0xcc, 0x4c, 0x3f, // green 0.8
+        0x66, 0x66, 0x66, 0x3f, // blue 0.9
+        0x00, // dither false
+    ];
+
+    /// the single `StripConfig` of schema 0: 144 BGR APA102 on gpio 23, clocked by gpio 18
+    const STRIP_0: &[u8] = &[
+        0x04, // Apa102
+        0x12, // clock_gpio 18
+        0x80, 0x92, 0xf4, 0x01, // clock_hz 4_000_000
+        0x01, // hdr
+        0x05, // BGR
+        0x90, 0x01, // led_count 144
+        0x17, // gpio 23
+    ];
+
+    #[test]
+    fn effects_from_before_layers_get_segments() {
+        let mut nvs = store::Nvs::default();
+        nvs.set_raw("effects", EFFECTS_0).unwrap();
+        let mut store = DStore::new(nvs);
+        store.migrate(60).unwrap();
+
+        let layers = store.get::<Vec<Layer>>("effects").unwrap().unwrap();
+        assert_eq!(layers.len(), 2);
+        for layer in &layers {
+            assert_eq!(layer.opacity, 1.0);
+            assert_eq!(layer.blend, BlendMode::Normal);
+            assert!(layer.enabled);
+        }
+        assert_eq!(layers[0].segment, WHOLE_STRIP);
+        match &layers[0].effect {
+            EffectConfig::HueShift(c) => {
+                assert_eq!(c.degrees_per_second, 10.0);
+                assert_eq!(c.degrees_per_led, 2.0);
+            }
+            other => panic!("expected HueShift, got {:?}", other),
+        }
+        match &layers[1].effect {
+            EffectConfig::SolidColor(c) => {
+                assert_eq!((c.color.red, c.color.green, c.color.blue), (1.0, 0.0, 0.5))
+            }
+            other => panic!("expected SolidColor, got {:?}", other),
+        }
+
+        let segments = store.get::<Vec<Segment>>("segments").unwrap().unwrap();
+        let segment = segments.iter().find(|s| s.id == layers[1].segment).unwrap();
+        assert_eq!((segment.start, segment.len), (10, 10));
+    }
+
+    #[test]
+    fn layers_nobody_migrated_end_up_on_the_whole_strip() {
+        let mut store = store::default();
+        store.set_bytes("effects", EFFECTS_1, 1).unwrap();
+
+        let layers = store.get::<Vec<Layer>>("effects").unwrap().unwrap();
+        assert_eq!(layers.len(), 1);
+        let layer = &layers[0];
+        assert_eq!(layer.segment, WHOLE_STRIP);
+        assert_eq!(layer.opacity, 0.5);
+        assert_eq!(layer.blend, BlendMode::Screen);
+        assert!(layer.enabled);
+        match &layer.effect {
+            EffectConfig::Alarm(c) => {
+                assert_eq!(c.at_ms_since_1970.as_millis(), 1_700_000_000_000);
+                assert!(matches!(c.alarm_type, AlarmType::Silvester));
+            }
+            other => panic!("expected Alarm, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn presets_from_schema_0_get_segments_and_stay_on() {
+        let mut store = store::default();
+        let placeholder = Preset {
+            effects: Vec::new(),
+            output: OutputConfig::default(),
+            calibration: None,
+        };
+        presets::save(&mut store, "old", &placeholder).unwrap();
+        let key = presets::keys(&store).unwrap().remove(0);
+        store.set_bytes(&key, PRESET_0, 0).unwrap();
+        store.migrate(60).unwrap();
+
+        let preset = presets::load(&store, "old").unwrap().unwrap();
+        assert_eq!(preset.output.brightness, 0.5);
+        assert!(preset.output.on);
+        assert!(preset.calibration.is_none());
+        assert_eq!(preset.effects.len(), 1);
+        let layer = &preset.effects[0];
+        assert_eq!(layer.id, 7);
+        assert_eq!(layer.opacity, 0.25);
+        assert_eq!(layer.blend, BlendMode::Add);
+        assert!(!layer.enabled);
+        assert!(matches!(layer.effect, EffectConfig::Invert(_)));
+
+        let segments = store.get::<Vec<Segment>>("segments").unwrap().unwrap();
+        let segment = segments.iter().find(|s| s.id == layer.segment).unwrap();
+        assert_eq!((segment.start, segment.len), (20, 10));
+    }
+
+    #[test]
+    fn calibrations_from_schema_0_keep_their_balance() {
+        let mut store = store::default();
+        store.set_bytes("calibration", CALIBRATION_0, 0).unwrap();
+
+        let calibration = store
+            .get::<CalibrationConfig>("calibration")
+            .unwrap()
+            .unwrap();
+        assert_eq!(calibration.gamma, 2.2);
+        assert_eq!(
+            (calibration.red, calibration.green, calibration.blue),
+            (1.0, 0.8, 0.9)
+        );
+        assert_eq!(calibration.white, WhiteExtraction::MinSubtract);
+    }
+
+    #[test]
+    fn strips_take_over_dither_from_the_calibration() {
+        let mut store = store::default();
+        store.set_bytes("calibration", CALIBRATION_1, 1).unwrap();
+        store.set_bytes("strip", STRIP_0, 0).unwrap();
+        store.migrate(144).unwrap();
+
+        let strips = store.get::<Vec<StripConfig>>("strip").unwrap().unwrap();
+        assert_eq!(strips.len(), 1);
+        assert!(!strips[0].dither);
+        assert_eq!(strips[0].channel, 2);
+        let (schema, _) = store.get_bytes("calibration").unwrap().unwrap();
+        assert_eq!(schema, CalibrationConfig::SCHEMA);
+        let calibration = store.get::<CalibrationConfig>("calibration").unwrap();
+        assert_eq!(calibration.unwrap().green, 0.8);
+
+        // without stored strips the default one is stored, so it does not start dithering
+        let mut store = store::default();
+        store.set_bytes("calibration", CALIBRATION_1, 1).unwrap();
+        store.migrate(500).unwrap();
+        let strips = store.get::<Vec<StripConfig>>("strip").unwrap().unwrap();
+        assert_eq!(
+            strips,
+            vec![StripConfig {
+                dither: false,
+                ..Default::default()
+            }]
+        );
+    }
+
+    #[test]
+    fn a_single_strip_becomes_a_list_of_one() {
+        let mut store = store::default();
+        store.set_bytes("strip", STRIP_0, 0).unwrap();
+
+        let strips = store.get::<Vec<StripConfig>>("strip").unwrap().unwrap();
+        assert_eq!(
+            strips,
+            vec![StripConfig {
+                chipset: Chipset::Apa102(Clocked {
+                    clock_gpio: 18,
+                    clock_hz: 4_000_000,
+                    hdr: true,
+                }),
+                order: LedColorOrder::BGR,
+                led_count: 144,
+                gpio: 23,
+                channel: 2,
+                reversed: false,
+                dither: true,
+            }]
+        );
+    }
+
+    #[test]
+    fn presets_from_before_calibration_leave_it_alone() {
+        let effects = vec![Layer::new(EffectConfig::SolidColor(
+            SolidColorConfig::default(),
+        ))];
+        let output = OutputConfig {
+            brightness: 0.5,
+            on: true,
+        };
+        let bytes = to_stdvec(&v2::Preset { effects, output }).unwrap();
+        let mut store = store::default();
+        store.set_bytes("preset0", &bytes, 2).unwrap();
+
+        let preset = store.get::<Preset>("preset0").unwrap().unwrap();
+        assert_eq!(preset.effects.len(), 1);
+        assert_eq!(preset.output.brightness, 0.5);
+        assert!(preset.calibration.is_none());
+    }
+
+    #[test]
+    fn presets_drop_dither_from_their_calibration() {
+        let calibration = v2::CalibrationConfig {
+            gamma: 2.8,
+            red: 1.0,
+            green: 1.0,
+            blue: 0.5,
+            dither: false,
+            white: WhiteExtraction::None,
+        };
+        let preset = v3::Preset {
+            effects: Vec::new(),
+            output: OutputConfig::default(),
+            calibration: Some(calibration),
+        };
+        let mut store = store::default();
+        store
+            .set_bytes("preset0", &to_stdvec(&preset).unwrap(), 3)
+            .unwrap();
+
+        let preset = store.get::<Preset>("preset0").unwrap().unwrap();
+        let calibration = preset.calibration.unwrap();
+        assert_eq!((calibration.gamma, calibration.blue), (2.8, 0.5));
+        assert_eq!(calibration.white, WhiteExtraction::None);
+    }
+}